@@ -2,13 +2,15 @@ use std::time::Instant;
 
 use graphics::app::{App, AppState};
 use graphics::canvas::Canvas;
+use graphics::context::ContextConfig;
 use graphics::geometry::*;
 use graphics::math::Transform;
-use graphics::mesh::{MeshProvider, PNVertex, Static};
+use graphics::mesh::{MeshProvider, PNUVertex, Static};
 use math::auto_grad::{AutoGrad, Float};
 
 fn main() {
-    App::run_with(State::new());
+    let config = ContextConfig::default().sample_count(4).title("demo");
+    App::run_with_config(State::new(), config);
 }
 
 struct State {
@@ -61,7 +63,7 @@ impl AppState for State {
 struct RemCanyon;
 
 impl MeshProvider for RemCanyon {
-    type Vertex = PNVertex;
+    type Vertex = PNUVertex;
 
     type Kind = Static;
 
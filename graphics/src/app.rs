@@ -2,7 +2,7 @@ use winit::{application::ApplicationHandler, event_loop::EventLoop};
 
 use crate::{
     canvas::Canvas,
-    context::{self, Context},
+    context::{Context, ContextConfig},
 };
 
 type Update<State> = fn(&mut State) -> ();
@@ -12,6 +12,8 @@ pub struct App<State> {
     state: State,
     update: Update<State>,
     draw: Draw<State>,
+    watch_shaders: bool,
+    config: ContextConfig,
     context: Option<Context>,
 }
 
@@ -21,10 +23,26 @@ impl<State> App<State> {
             state,
             update,
             draw,
+            watch_shaders: false,
+            config: ContextConfig::default(),
             context: None,
         }
     }
 
+    /// Reloads the shaders whenever their files change, see
+    /// [`Context::watch_shaders`].
+    pub fn watch_shaders(mut self) -> Self {
+        self.watch_shaders = true;
+        self
+    }
+
+    /// The window, anti-aliasing and presentation options,
+    /// [`ContextConfig::default`] unless set.
+    pub fn config(mut self, config: ContextConfig) -> Self {
+        self.config = config;
+        self
+    }
+
     pub fn run(mut self) {
         let event_loop = EventLoop::new().unwrap();
         // let mut app = crate::backend::winit::WinitApp::new(
@@ -37,7 +55,11 @@ impl<State> App<State> {
 }
 impl<State: AppState> App<State> {
     pub fn run_with(state: State) {
-        let app = App::new(state, State::update, State::draw);
+        Self::run_with_config(state, ContextConfig::default())
+    }
+
+    pub fn run_with_config(state: State, config: ContextConfig) {
+        let app = App::new(state, State::update, State::draw).config(config);
         app.run()
     }
 }
@@ -49,7 +71,11 @@ pub trait AppState {
 
 impl<State> ApplicationHandler for App<State> {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        self.context = Some(Context::new(event_loop));
+        let mut context = Context::new(event_loop, &self.config);
+        if self.watch_shaders {
+            context.watch_shaders();
+        }
+        self.context = Some(context);
     }
 
     fn window_event(
@@ -69,8 +95,8 @@ impl<State> ApplicationHandler for App<State> {
                 (self.update)(&mut self.state);
                 let mut canvas = context.create_canvas();
                 (self.draw)(&self.state, &mut canvas);
-                let commands = canvas.commands;
-                context.render(commands);
+                let (commands, lights) = (canvas.commands, canvas.lights);
+                context.render(commands, lights);
             }
             _ => (),
         }
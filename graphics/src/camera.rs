@@ -2,6 +2,8 @@ use nalgebra::{Matrix4, Perspective3, Point3, Rotation3, Vector3};
 use winit::event::{DeviceEvent, Event, KeyEvent, WindowEvent};
 use winit::keyboard::{Key, NamedKey};
 
+use crate::math::Frustum;
+
 // use crate::ray::Ray;
 
 #[allow(clippy::struct_excessive_bools)]
@@ -52,6 +54,22 @@ impl FirstPersonCamera {
         self.position + self.look_direction()
     }
 
+    pub fn set_position(&mut self, position: Point3<f32>) {
+        self.position = position;
+    }
+
+    /// Turns the camera to `yaw` degrees around the up axis and `pitch`
+    /// degrees up or down, `0, 0` looks along +z.
+    pub fn set_orientation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-89.9, 89.9);
+    }
+
+    /// Width divided by height of the image the camera renders to.
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+    }
+
     pub fn move_facing(&mut self, direction: Vector3<f32>) {
         self.position += self.yaw_rotation() * direction;
     }
@@ -61,19 +79,44 @@ impl FirstPersonCamera {
         self.partial_view_proj(0.0, 1.0)
     }
 
+    #[must_use]
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(&self.view_proj())
+    }
+
     /// # Panics
     /// If the invariant `0.0 <= start < end <= 1.0` is not satisfied.
     #[must_use]
     pub fn partial_view_proj(&self, start: f32, end: f32) -> Matrix4<f32> {
         assert!(0.0 <= start && start < end && end <= 1.0);
-        Perspective3::new(
-            self.aspect,
-            60.0f32.to_radians(),
+        self.projection(
             (1.0 - start).mul_add(self.near, start * self.far),
             (1.0 - end).mul_add(self.near, end * self.far),
-        )
-        .to_homogeneous()
-            * Matrix4::look_at_rh(&self.position, &self.look_at(), &Self::UP)
+        ) * Matrix4::look_at_rh(&self.position, &self.look_at(), &Self::UP)
+    }
+
+    /// The corners of the part of the view frustum closer than `distance`,
+    /// the four near ones first.
+    #[must_use]
+    pub fn frustum_corners(&self, distance: f32) -> [Point3<f32>; 8] {
+        let far = distance.clamp(self.near * 2.0, self.far);
+        let view_proj = self.projection(self.near, far)
+            * Matrix4::look_at_rh(&self.position, &self.look_at(), &Self::UP);
+        let inverse = view_proj.try_inverse().unwrap();
+        [-1.0, 1.0]
+            .map(|z| {
+                [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].map(
+                    |(x, y)| inverse.transform_point(&Point3::new(x, y, z)),
+                )
+            })
+            .concat()
+            .try_into()
+            .unwrap()
+    }
+
+    fn projection(&self, near: f32, far: f32) -> Matrix4<f32> {
+        Perspective3::new(self.aspect, 60.0f32.to_radians(), near, far)
+            .to_homogeneous()
     }
 
     #[rustfmt::skip]
@@ -1,6 +1,14 @@
+use std::any::TypeId;
+
 use nalgebra::Matrix4;
 
-use crate::{math::Transform, mesh::MeshId};
+use crate::{
+    material::Material,
+    math::Transform,
+    mesh::MeshId,
+    shader::{ShaderId, ShaderProvider},
+    texture::{TextureId, TextureProvider},
+};
 
 use super::{Canvas, DrawCommand};
 
@@ -11,20 +19,77 @@ pub struct Drawing<'c, 'cref> {
 
 impl<'c, 'cref> Drawing<'c, 'cref> {
     pub fn new(canvas: &'cref mut Canvas<'c>, mesh_id: MeshId) -> Self {
-        // let shader = canvas.shaders.get_or_insert(DefaultShader);
         Self {
             canvas,
             command: DrawCommand {
                 mesh_id,
-                // shader,
+                shader: ShaderId::DEFAULT,
+                texture: TextureId::WHITE,
                 transform: Matrix4::identity(),
-                color: [1.0, 1.0, 1.0],
+                material: Material::DEFAULT,
+                cast_shadows: true,
+                receive_shadows: true,
             },
         }
     }
 
+    /// Sets the base color of the material.
     pub fn color(mut self, color: [f32; 3]) -> Self {
-        self.command.color = color;
+        self.command.material.base_color = color;
+        self
+    }
+
+    /// Sets the opacity of the material. Drawings with an alpha below one
+    /// are blended over what is behind them, drawn after all opaque ones
+    /// from back to front, and cast no shadows.
+    pub fn alpha(mut self, alpha: f32) -> Self {
+        self.command.material.alpha = alpha;
+        self
+    }
+
+    /// [`Material::DEFAULT`] unless set.
+    pub fn material(mut self, material: Material) -> Self {
+        self.command.material = material;
+        self
+    }
+
+    /// Multiplies the base color with `texture`. Meshes without texture
+    /// coordinates, like those of [`PNVertex`], ignore it.
+    ///
+    /// [`PNVertex`]: crate::mesh::PNVertex
+    pub fn texture<T: TextureProvider>(mut self, texture: T) -> Self {
+        self.command.texture = self.canvas.textures.get_or_insert(texture);
+        self
+    }
+
+    /// Whether the drawing throws shadows of directional and spot lights,
+    /// on by default.
+    pub fn cast_shadows(mut self, cast_shadows: bool) -> Self {
+        self.command.cast_shadows = cast_shadows;
+        self
+    }
+
+    /// Whether shadows fall onto the drawing, on by default. Lines are
+    /// never shadowed.
+    pub fn receive_shadows(mut self, receive_shadows: bool) -> Self {
+        self.command.receive_shadows = receive_shadows;
+        self
+    }
+
+    /// Draws with `shader` instead of the default shader.
+    ///
+    /// A shader for another vertex type than the mesh skips the drawing,
+    /// like one that does not compile, and reports it in
+    /// [`Context::shader_errors`].
+    ///
+    /// [`Context::shader_errors`]: crate::context::Context::shader_errors
+    pub fn shader<S: ShaderProvider>(mut self, shader: S) -> Self {
+        let shaders = &mut self.canvas.shaders;
+        let id = shaders.get_or_insert(shader);
+        if TypeId::of::<S::Vertex>() != self.command.mesh_id.vtx_type_id {
+            shaders.vertex_mismatch::<S>(id);
+        }
+        self.command.shader = id;
         self
     }
 
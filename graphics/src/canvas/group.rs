@@ -24,13 +24,24 @@ impl<'c, 'cref, GroupFn: FnOnce(&mut Canvas)> Drop
     for Group<'c, 'cref, GroupFn>
 {
     fn drop(&mut self) {
-        let mut canv = Canvas::new(self.canvas.meshes);
+        let frustum = self.canvas.frustum.transformed(&self.transform);
+        let mut canv = Canvas::new(
+            self.canvas.meshes,
+            self.canvas.shaders,
+            self.canvas.textures,
+            frustum,
+        );
         (self.group_fn.take().unwrap())(&mut canv);
         self.canvas.commands.extend(
             canv.commands
                 .into_iter()
                 .map(|cmd| cmd.transform(&self.transform)),
-        )
+        );
+        self.canvas.lights.extend(
+            canv.lights
+                .into_iter()
+                .map(|light| light.transform(&self.transform)),
+        );
     }
 }
 
@@ -1,35 +1,58 @@
 pub mod drawing;
 pub mod group;
 
-use crate::math::Transform;
+use crate::light::Light;
+use crate::material::Material;
+use crate::math::{Frustum, Transform};
 use crate::mesh::{MeshId, MeshKind, MeshManager, MeshProvider};
+use crate::shader::{ShaderId, ShaderManager};
+use crate::texture::{TextureId, TextureManager};
 use drawing::Drawing;
 use group::Group;
 use nalgebra::Matrix4;
 
 pub struct Canvas<'c> {
     pub(crate) commands: Vec<DrawCommand>,
+    pub(crate) lights: Vec<Light>,
     meshes: &'c mut MeshManager,
-    // shaders: &'c mut ShaderManager,
+    shaders: &'c mut ShaderManager,
+    textures: &'c mut TextureManager,
+    frustum: Frustum,
 }
 
 impl<'c> Canvas<'c> {
     #[allow(clippy::new_without_default)]
     pub fn new(
         meshes: &'c mut MeshManager,
-        // shaders: &'c mut ShaderManager,
+        shaders: &'c mut ShaderManager,
+        textures: &'c mut TextureManager,
+        frustum: Frustum,
     ) -> Self {
         Self {
             commands: vec![],
+            lights: vec![],
             meshes,
-            // shaders,
+            shaders,
+            textures,
+            frustum,
         }
     }
 
+    /// The camera frustum, in the local space of the current group.
+    pub fn frustum(&self) -> &Frustum {
+        &self.frustum
+    }
+
     fn add_command(&mut self, command: DrawCommand) {
         self.commands.push(command);
     }
 
+    /// Lights the whole frame, not only what is drawn after. Frames
+    /// without lights use [`Light::DEFAULT`].
+    pub fn light(&mut self, light: impl Into<Light>) {
+        self.lights.push(light.into());
+    }
+
     pub fn group<'cref, GroupFn: FnOnce(&mut Canvas)>(
         &'cref mut self,
         group_fn: GroupFn,
@@ -43,6 +66,33 @@ impl<'c> Canvas<'c> {
     ) -> Drawing<'c, 'cref> {
         thing.draw(self)
     }
+
+    /// Calls `draw` for every item of `index` that may be visible, items
+    /// outside of the camera frustum are skipped without being drawn.
+    pub fn draw_visible<'i, I: FrustumIndex + ?Sized>(
+        &mut self,
+        index: &'i I,
+        mut draw: impl FnMut(&mut Self, I::Item<'i>),
+    ) {
+        let frustum = self.frustum.clone();
+        index.visible(&frustum, &mut |item| draw(self, item));
+    }
+}
+
+/// A spatial index that can find the items inside a [`Frustum`], used to
+/// cull draws with [`Canvas::draw_visible`].
+pub trait FrustumIndex {
+    /// An item borrowed from the index, such as its bounding box and data.
+    type Item<'a>
+    where
+        Self: 'a;
+
+    /// Calls `visit` with every item that may be inside `frustum`.
+    fn visible<'a>(
+        &'a self,
+        frustum: &Frustum,
+        visit: &mut dyn FnMut(Self::Item<'a>),
+    );
 }
 
 pub trait Drawable {
@@ -65,9 +115,20 @@ impl<Provider: MeshProvider> Drawable for Provider {
 #[derive(Clone)]
 pub struct DrawCommand {
     pub mesh_id: MeshId,
-    // pub shader: ShaderId,
+    pub shader: ShaderId,
+    pub texture: TextureId,
     pub transform: Matrix4<f32>,
-    pub color: [f32; 3],
+    pub material: Material,
+    pub cast_shadows: bool,
+    pub receive_shadows: bool,
+}
+
+impl DrawCommand {
+    /// Whether the command is drawn with alpha blending, see
+    /// [`Drawing::alpha`].
+    pub(crate) fn is_transparent(&self) -> bool {
+        self.material.alpha < 1.0
+    }
 }
 
 impl Transform for DrawCommand {
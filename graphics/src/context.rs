@@ -1,51 +1,149 @@
 use std::{
-    any::TypeId, borrow::Cow, collections::HashMap, num::NonZero, sync::Arc,
+    any::TypeId, collections::HashMap, num::NonZero, ops::Range, sync::Arc,
 };
 
+use nalgebra::Point3;
 use pollster::FutureExt;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
-    BindGroup, Buffer, BufferDescriptor, BufferUsages, Color,
+    Adapter, BindGroup, Buffer, BufferDescriptor, BufferUsages, Color,
     CommandEncoderDescriptor, DepthBiasState, DepthStencilState, Device,
-    Extent3d, IndexFormat, PresentMode, Queue,
-    RenderPassDepthStencilAttachment, RenderPipeline, StencilState, Surface,
-    SurfaceConfiguration, Texture, TextureDescriptor, TextureView,
-    TextureViewDescriptor, VertexAttribute, VertexBufferLayout,
+    Extent3d, IndexFormat, PipelineLayout, PowerPreference, PresentMode, Queue,
+    RenderPassDepthStencilAttachment, RenderPipeline, RequestDeviceError,
+    StencilState, Surface, SurfaceConfiguration, Texture, TextureDescriptor,
+    TextureFormat, TextureView, TextureViewDescriptor, VertexAttribute,
+    VertexBufferLayout,
 };
 use winit::{
-    dpi::PhysicalSize, event::Event, event_loop::ActiveEventLoop,
+    dpi::{LogicalSize, PhysicalSize},
+    event::Event,
+    event_loop::ActiveEventLoop,
     window::Window,
 };
 
 use crate::{
     camera::FirstPersonCamera,
     canvas::{Canvas, DrawCommand},
-    mesh::{MeshManager, PDVertex, PNVertex, Vertex},
+    light::{Light, LightsUniform},
+    mesh::{MeshId, MeshManager, VertexLayout},
+    shader::{ShaderError, ShaderId, ShaderManager},
+    shadow::ShadowMaps,
+    texture::TextureManager,
 };
 
+/// How [`Context`] creates its window and renders, see
+/// [`App::config`](crate::app::App::config).
+///
+/// ```no_run
+/// # use graphics::context::ContextConfig;
+/// let config = ContextConfig::default()
+///     .sample_count(4)
+///     .vsync(false)
+///     .title("boxes");
+/// ```
+#[derive(Debug, Clone)]
+pub struct ContextConfig {
+    pub(crate) sample_count: u32,
+    pub(crate) present_mode: PresentMode,
+    pub(crate) clear_color: Color,
+    pub(crate) title: Option<String>,
+    pub(crate) size: Option<(u32, u32)>,
+    pub(crate) power_preference: PowerPreference,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            sample_count: 1,
+            present_mode: PresentMode::Fifo,
+            clear_color: Color {
+                r: 100.0 / 255.0,
+                g: 149.0 / 255.0,
+                b: 237.0 / 255.0,
+                a: 1.0,
+            },
+            title: None,
+            size: None,
+            power_preference: PowerPreference::default(),
+        }
+    }
+}
+
+impl ContextConfig {
+    /// The number of samples per pixel for multisample anti-aliasing, 1
+    /// (off) by default. Lowered to the highest count the device
+    /// supports, which is 4 for the formats used here.
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count.max(1);
+        self
+    }
+
+    /// [`PresentMode::Fifo`] by default, which every surface supports and
+    /// which other modes fall back to if the surface does not.
+    pub fn present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    /// Waits for the vertical blank if `vsync` is set, like the default
+    /// [`PresentMode::Fifo`], otherwise presents immediately if possible.
+    pub fn vsync(self, vsync: bool) -> Self {
+        self.present_mode(if vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        })
+    }
+
+    /// The background, in linear color space. Cornflower blue by default.
+    pub fn clear_color(mut self, clear_color: Color) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// The inner size of the window in logical pixels, chosen by the
+    /// platform by default.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    pub fn power_preference(
+        mut self,
+        power_preference: PowerPreference,
+    ) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+}
+
 pub struct Context {
     window: Arc<Window>,
     config: SurfaceConfiguration,
     surface: Surface<'static>,
-    device: Arc<Device>,
-    render_pipelines: HashMap<TypeId, RenderPipeline>,
-    queue: Queue,
-    camera_uniform_buffer: Buffer,
-    camera_bind_group: BindGroup,
     camera: FirstPersonCamera,
-    meshes: MeshManager,
-    depth_texture: Texture,
-    depth_texture_view: TextureView,
-    instance_buffer: Buffer,
+    renderer: Renderer,
 }
 
 impl Context {
-    pub(crate) fn new(event_loop: &ActiveEventLoop) -> Self {
-        let window = Arc::new(
-            event_loop
-                .create_window(Window::default_attributes())
-                .unwrap(),
-        );
+    pub(crate) fn new(
+        event_loop: &ActiveEventLoop,
+        config: &ContextConfig,
+    ) -> Self {
+        let mut attributes = Window::default_attributes();
+        if let Some(title) = &config.title {
+            attributes = attributes.with_title(title);
+        }
+        if let Some((width, height)) = config.size {
+            attributes =
+                attributes.with_inner_size(LogicalSize::new(width, height));
+        }
+        let window = Arc::new(event_loop.create_window(attributes).unwrap());
         let size = window.inner_size();
 
         let instance = wgpu::Instance::default();
@@ -55,74 +153,279 @@ impl Context {
         let (adapter, device, queue) = async {
             let adapter = instance
                 .request_adapter(&wgpu::RequestAdapterOptions {
-                    power_preference: wgpu::PowerPreference::default(),
+                    power_preference: config.power_preference,
                     force_fallback_adapter: false,
                     compatible_surface: Some(&surface),
                 })
                 .await
                 .unwrap();
-            let (device, queue) = adapter
-                .request_device(
-                    &wgpu::DeviceDescriptor {
-                        label: None,
-                        required_features: wgpu::Features::empty(),
-                        required_limits:
-                            wgpu::Limits::downlevel_webgl2_defaults()
-                                .using_resolution(adapter.limits()),
-                        memory_hints: wgpu::MemoryHints::MemoryUsage,
-                    },
-                    None,
-                )
-                .await
-                .unwrap();
-            (adapter, Arc::new(device), queue)
+            let (device, queue) = request_device(&adapter).await.unwrap();
+            (adapter, device, queue)
         }
         .block_on();
 
-        let pn_shader =
-            device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: None,
-                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
-                    "pn_shader.wgsl"
-                ))),
-            });
+        let swapchain_capabilities = surface.get_capabilities(&adapter);
+        let swapchain_format = swapchain_capabilities.formats[0];
+
+        let mut surface_config = surface
+            .get_default_config(&adapter, size.width, size.height)
+            .unwrap();
+        if swapchain_capabilities
+            .present_modes
+            .contains(&config.present_mode)
+        {
+            surface_config.present_mode = config.present_mode;
+        } else {
+            surface_config.present_mode = PresentMode::Fifo;
+        }
+        surface.configure(&device, &surface_config);
+        let renderer_config =
+            RendererConfig::new(&device, swapchain_format, config);
+        let renderer = Renderer::new(
+            device,
+            queue,
+            swapchain_format,
+            surface_config.width,
+            surface_config.height,
+            &renderer_config,
+        );
+        Self {
+            window,
+            config: surface_config,
+            surface,
+            camera: FirstPersonCamera::default(),
+            renderer,
+        }
+    }
 
-        let pd_shader =
-            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+    pub fn create_canvas(&mut self) -> Canvas<'_> {
+        Canvas::new(
+            &mut self.renderer.meshes,
+            &mut self.renderer.shaders,
+            &mut self.renderer.textures,
+            self.camera.frustum(),
+        )
+    }
+
+    pub(crate) fn render(
+        &mut self,
+        commands: Vec<DrawCommand>,
+        lights: Vec<Light>,
+    ) {
+        let frame = self.surface.get_current_texture().unwrap();
+        let view = frame.texture.create_view(&TextureViewDescriptor::default());
+        self.renderer.render(&self.camera, commands, &lights, &view);
+        frame.present();
+        self.window.request_redraw();
+    }
+
+    /// Reloads the shaders whenever their files change, for development.
+    ///
+    /// The built-in shaders are read from the source directory of this
+    /// crate, custom shaders from their [`ShaderProvider::path`]. A shader
+    /// that fails to compile keeps the last version that compiled, see
+    /// [`shader_errors`](Self::shader_errors).
+    ///
+    /// [`ShaderProvider::path`]: crate::shader::ShaderProvider::path
+    pub fn watch_shaders(&mut self) {
+        self.renderer.shaders.watch();
+    }
+
+    /// Why the shaders that currently fail to compile fail, and the shaders
+    /// used for meshes of another vertex type. Drawings with a shader that
+    /// never compiled or does not fit their mesh are skipped.
+    pub fn shader_errors(&self) -> impl Iterator<Item = &ShaderError> {
+        self.renderer.shaders.errors()
+    }
+
+    pub fn event(&mut self, event: &Event<()>) {
+        self.camera.event(event);
+    }
+
+    pub fn update_camera(&mut self, dt: f32) {
+        self.camera.update(dt);
+    }
+
+    pub(crate) fn resize(&mut self, new_size: &PhysicalSize<u32>) {
+        self.config.width = new_size.width.max(1);
+        self.config.height = new_size.height.max(1);
+        self.renderer.resize(self.config.width, self.config.height);
+        self.surface.configure(&self.renderer.device, &self.config);
+    }
+}
+
+pub(crate) async fn request_device(
+    adapter: &Adapter,
+) -> Result<(Arc<Device>, Queue), RequestDeviceError> {
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
                 label: None,
-                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
-                    "pd_shader.wgsl"
-                ))),
-            });
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::downlevel_webgl2_defaults()
+                    .using_resolution(adapter.limits()),
+                memory_hints: wgpu::MemoryHints::MemoryUsage,
+            },
+            None,
+        )
+        .await?;
+    Ok((Arc::new(device), queue))
+}
+
+/// The options of [`ContextConfig`] that [`Renderer`] uses, checked
+/// against the adapter.
+pub(crate) struct RendererConfig {
+    sample_count: u32,
+    clear_color: Color,
+}
+
+impl RendererConfig {
+    pub(crate) fn new(
+        device: &Device,
+        format: TextureFormat,
+        config: &ContextConfig,
+    ) -> Self {
+        // both the color and the depth target have to support the count,
+        // without adapter specific features only 4 samples are guaranteed
+        let supported = |count| {
+            [format, TextureFormat::Depth32Float].iter().all(|&format| {
+                format
+                    .guaranteed_format_features(device.features())
+                    .flags
+                    .sample_count_supported(count)
+            })
+        };
+        let sample_count = (1..=config.sample_count)
+            .rev()
+            .find(|&count| supported(count))
+            .unwrap_or(1);
+        Self {
+            sample_count,
+            clear_color: config.clear_color,
+        }
+    }
+}
 
-        let camera_bind_group_layout =
+/// The GPU state shared by [`Context`] and [`HeadlessContext`], renders
+/// [`DrawCommand`]s into any texture of the format it was created with.
+///
+/// [`HeadlessContext`]: crate::headless::HeadlessContext
+pub(crate) struct Renderer {
+    pub(crate) device: Arc<Device>,
+    pub(crate) queue: Queue,
+    pub(crate) meshes: MeshManager,
+    pub(crate) shaders: ShaderManager,
+    pub(crate) textures: TextureManager,
+    format: TextureFormat,
+    /// Created when a shader first draws a vertex type, opaque or
+    /// transparent.
+    render_pipelines: HashMap<(ShaderId, TypeId, bool), RenderPipeline>,
+    pipeline_layout: PipelineLayout,
+    /// For shaders with uniforms.
+    uniform_pipeline_layout: PipelineLayout,
+    /// Bound instead of the uniforms of shaders without any.
+    empty_bind_group: BindGroup,
+    camera_uniform_buffer: Buffer,
+    lights_uniform_buffer: Buffer,
+    /// Binds the camera, the lights and the shadow maps.
+    frame_bind_group: BindGroup,
+    shadows: ShadowMaps,
+    sample_count: u32,
+    clear_color: Color,
+    depth_texture: Texture,
+    depth_texture_view: TextureView,
+    /// Rendered into and resolved to the target if there is more than one
+    /// sample per pixel.
+    multisampled: Option<(Texture, TextureView)>,
+    instance_buffer: Buffer,
+    /// The instances of the current frame, kept to reuse the allocation.
+    instances: Vec<InstanceData>,
+}
+
+impl Renderer {
+    pub(crate) fn new(
+        device: Arc<Device>,
+        queue: Queue,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+        config: &RendererConfig,
+    ) -> Self {
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let frame_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    uniform_entry(0, wgpu::ShaderStages::VERTEX),
+                    uniform_entry(1, wgpu::ShaderStages::FRAGMENT),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(
+                            wgpu::SamplerBindingType::Comparison,
+                        ),
+                        count: None,
                     },
-                    count: None,
-                }],
+                ],
             });
 
+        // the uniforms of custom shaders are bind group 1 and the texture
+        // of the drawing bind group 2
+        let shaders = ShaderManager::new(device.clone());
+        let textures = TextureManager::new(device.clone());
+        let empty_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[],
+            });
+        let empty_bind_group =
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &empty_bind_group_layout,
+                entries: &[],
+            });
         let pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: None,
-                bind_group_layouts: &[&camera_bind_group_layout],
+                bind_group_layouts: &[
+                    &frame_bind_group_layout,
+                    &empty_bind_group_layout,
+                    textures.layout(),
+                ],
+                push_constant_ranges: &[],
+            });
+        let uniform_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &frame_bind_group_layout,
+                    shaders.uniform_layout(),
+                    textures.layout(),
+                ],
                 push_constant_ranges: &[],
             });
 
-        let swapchain_capabilities = surface.get_capabilities(&adapter);
-        let swapchain_format = swapchain_capabilities.formats[0];
-
-        let camera = FirstPersonCamera::default();
-
-        let camera_uniform_data = CameraUniform::from(&camera);
+        let camera_uniform_data =
+            CameraUniform::from(&FirstPersonCamera::default());
 
         let camera_uniform_buffer =
             device.create_buffer_init(&BufferInitDescriptor {
@@ -131,80 +434,105 @@ impl Context {
                 usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             });
 
-        let instance_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: (size_of::<InstanceData>() * MAX_INSTANCE_COUNT) as u64,
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let mut render_pipelines = HashMap::new();
+        let lights_uniform_buffer =
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::bytes_of(&LightsUniform::new(
+                    &[],
+                    &FirstPersonCamera::default(),
+                )),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            });
 
-        render_pipelines.insert(
-            TypeId::of::<PNVertex>(),
-            create_render_pipeline::<PNVertex>(
-                &device,
-                &pipeline_layout,
-                &pn_shader,
-                swapchain_format,
-            ),
-        );
+        let instance_buffer =
+            create_instance_buffer(&device, INITIAL_INSTANCE_CAPACITY);
 
-        render_pipelines.insert(
-            TypeId::of::<PDVertex>(),
-            create_render_pipeline::<PDVertex>(
-                &device,
-                &pipeline_layout,
-                &pd_shader,
-                swapchain_format,
-            ),
-        );
+        let shadows = ShadowMaps::new(&device);
 
-        let camera_bind_group =
+        let frame_bind_group =
             device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: None,
-                layout: &camera_bind_group_layout,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera_uniform_buffer.as_entire_binding(),
-                }],
+                layout: &frame_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: camera_uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: lights_uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(
+                            &shadows.view,
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::Sampler(
+                            &shadows.sampler,
+                        ),
+                    },
+                ],
             });
 
-        let mut config = surface
-            .get_default_config(&adapter, size.width, size.height)
-            .unwrap();
-        config.present_mode = PresentMode::Fifo;
-        let (depth_texture, depth_texture_view) =
-            create_depth_texture(&device, &config);
+        let (depth_texture, depth_texture_view) = create_depth_texture(
+            &device,
+            width,
+            height,
+            1,
+            config.sample_count,
+        );
+        let multisampled = create_multisampled_texture(
+            &device,
+            format,
+            width,
+            height,
+            config.sample_count,
+        );
 
-        surface.configure(&device, &config);
         let meshes = MeshManager::new(device.clone());
         Self {
-            window,
-            config,
-            surface,
             device,
-            render_pipelines,
             queue,
-            camera_uniform_buffer,
-            camera_bind_group,
-            camera,
             meshes,
+            shaders,
+            textures,
+            format,
+            render_pipelines: HashMap::new(),
+            pipeline_layout,
+            uniform_pipeline_layout,
+            empty_bind_group,
+            camera_uniform_buffer,
+            lights_uniform_buffer,
+            frame_bind_group,
+            shadows,
+            sample_count: config.sample_count,
+            clear_color: config.clear_color,
             depth_texture,
             depth_texture_view,
+            multisampled,
             instance_buffer,
+            instances: Vec::new(),
         }
     }
 
-    pub fn create_canvas(&mut self) -> Canvas {
-        Canvas::new(&mut self.meshes)
-    }
-
-    pub(crate) fn render(&mut self, commands: Vec<DrawCommand>) {
-        let frame = self.surface.get_current_texture().unwrap();
-        let view = frame.texture.create_view(&TextureViewDescriptor::default());
-
-        let camera_uniform_data = CameraUniform::from(&self.camera);
+    /// Renders `commands` lit by `lights` as seen by `camera` into `view`,
+    /// which has to be as large as the last [`resize`](Self::resize).
+    ///
+    /// The instances of all commands are uploaded at once, grouped by
+    /// shader, mesh and texture, and drawn in a single render pass after
+    /// the shadow maps. Transparent commands are drawn last, from back to
+    /// front, and cast no shadows.
+    pub(crate) fn render(
+        &mut self,
+        camera: &FirstPersonCamera,
+        commands: Vec<DrawCommand>,
+        lights: &[Light],
+        view: &TextureView,
+    ) {
+        let camera_uniform_data = CameraUniform::from(camera);
         self.queue
             .write_buffer_with(
                 &self.camera_uniform_buffer,
@@ -213,168 +541,380 @@ impl Context {
             )
             .unwrap()
             .copy_from_slice(bytemuck::bytes_of(&camera_uniform_data));
-        let mut color_load_op = wgpu::LoadOp::Clear(Color {
-            r: 100.0 / 255.0,
-            g: 149.0 / 255.0,
-            b: 237.0 / 255.0,
-            a: 1.0,
-        });
-        let mut depth_load_op = wgpu::LoadOp::Clear(1.0);
-        let mut batches = HashMap::new();
-        for command in commands {
-            batches
-                .entry(command.mesh_id)
-                .or_insert_with(Vec::new)
-                .push(InstanceData::from(&command));
+        let lights = LightsUniform::new(lights, camera);
+        self.queue.write_buffer(
+            &self.lights_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&lights),
+        );
+
+        // drawings with shaders that never compiled are skipped
+        for shader in self.shaders.reload() {
+            self.render_pipelines.retain(|k, _| k.0 != shader);
         }
-        for (mesh_id, instance_batch) in
-            batches.iter().flat_map(|(mesh_id, instances)| {
-                instances
-                    .chunks(MAX_INSTANCE_COUNT)
-                    .map(move |instance| (mesh_id, instance))
+        let (mut commands, transparent): (Vec<_>, Vec<_>) = commands
+            .into_iter()
+            .filter(|command| {
+                self.shaders
+                    .is_compiled(command.shader, command.mesh_id.vtx_type_id)
+            })
+            .partition(|command| !command.is_transparent());
+        // the shadow casters first, so the shadow maps draw a prefix of
+        // every batch
+        commands.sort_by_key(|command| {
+            (
+                command.shader,
+                command.mesh_id,
+                command.texture,
+                !command.cast_shadows,
+            )
+        });
+        let opaque_count = commands.len();
+        // back to front, so nearer surfaces blend over farther ones
+        let mut transparent: Vec<_> = transparent
+            .into_iter()
+            .map(|command| {
+                let center =
+                    command.transform.transform_point(&Point3::origin());
+                (nalgebra::distance(&center, &camera.position()), command)
             })
+            .collect();
+        transparent.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        commands.extend(transparent.into_iter().map(|(_, command)| command));
+        self.instances.clear();
+        self.instances
+            .extend(commands.iter().map(InstanceData::from));
+        self.reserve_instances(self.instances.len());
+        if let Some(instance_bytes) =
+            NonZero::new(size_of_val(self.instances.as_slice()) as u64)
         {
-            let instance_batch_bytes = bytemuck::cast_slice(instance_batch);
-            let mut encoder = self
-                .device
-                .create_command_encoder(&CommandEncoderDescriptor::default());
             self.queue
-                .write_buffer_with(
-                    &self.instance_buffer,
-                    0,
-                    NonZero::new(instance_batch_bytes.len() as u64).unwrap(),
-                )
+                .write_buffer_with(&self.instance_buffer, 0, instance_bytes)
                 .unwrap()
-                .copy_from_slice(instance_batch_bytes);
-            let mut rpass =
-                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: None,
-                    color_attachments: &[Some(
-                        wgpu::RenderPassColorAttachment {
-                            view: &view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: color_load_op,
-                                store: wgpu::StoreOp::Store,
-                            },
-                        },
-                    )],
-                    depth_stencil_attachment: Some(
-                        RenderPassDepthStencilAttachment {
-                            view: &self.depth_texture_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: depth_load_op,
-                                store: wgpu::StoreOp::Store,
-                            }),
-                            stencil_ops: None,
-                        },
-                    ),
-                    timestamp_writes: None,
-                    occlusion_query_set: None,
-                });
-            let mesh_buffers = self.meshes.get_by_id(*mesh_id);
-            rpass.set_vertex_buffer(0, mesh_buffers.vertex.slice(..));
-            rpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            rpass.set_index_buffer(
-                mesh_buffers.index.slice(..),
-                IndexFormat::Uint32,
-            );
-            rpass.set_pipeline(&self.render_pipelines[&mesh_id.vtx_type_id]);
-            rpass.set_bind_group(0, &self.camera_bind_group, &[]);
-            rpass.draw_indexed(
-                mesh_buffers.index_range.clone(),
-                0,
-                0..instance_batch.len() as u32,
+                .copy_from_slice(bytemuck::cast_slice(&self.instances));
+        }
+        self.shaders.upload_uniforms(&self.queue);
+        self.textures.upload(&self.queue);
+        for (batch, _) in batches(&commands) {
+            let key = pipeline_key(&batch[0]);
+            if !self.render_pipelines.contains_key(&key) {
+                let pipeline = self.create_pipeline(&batch[0]);
+                self.render_pipelines.insert(key, pipeline);
+            }
+        }
+        let opaque = &commands[..opaque_count];
+        self.shadows.prepare(&self.device, &self.meshes, opaque);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor::default());
+        self.shadows.render(
+            &self.queue,
+            &mut encoder,
+            lights.shadow_view_projs(),
+            &self.meshes,
+            &self.instance_buffer,
+            opaque,
+        );
+        // the samples are only needed until they are resolved into `view`
+        let (view, resolve_target, store) = match &self.multisampled {
+            Some((_, multisampled)) => {
+                (multisampled, Some(view), wgpu::StoreOp::Discard)
+            }
+            None => (view, None, wgpu::StoreOp::Store),
+        };
+        let mut rpass =
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.clear_color),
+                        store,
+                    },
+                })],
+                depth_stencil_attachment: Some(
+                    RenderPassDepthStencilAttachment {
+                        view: &self.depth_texture_view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    },
+                ),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+        rpass.set_bind_group(0, &self.frame_bind_group, &[]);
+        for (batch, instances) in batches(&commands) {
+            let (shader, mesh_id) = (batch[0].shader, batch[0].mesh_id);
+            rpass
+                .set_pipeline(&self.render_pipelines[&pipeline_key(&batch[0])]);
+            let uniforms = match &self.shaders.get_by_id(shader).uniforms {
+                Some(uniforms) => &uniforms.bind_group,
+                None => &self.empty_bind_group,
+            };
+            rpass.set_bind_group(1, uniforms, &[]);
+            let texture = self.textures.bind_group(batch[0].texture);
+            rpass.set_bind_group(2, texture, &[]);
+            draw_instances(
+                &mut rpass,
+                &self.meshes,
+                &self.instance_buffer,
+                mesh_id,
+                instances,
             );
-            drop(rpass);
-            self.queue.submit(Some(encoder.finish()));
-            color_load_op = wgpu::LoadOp::Load;
-            depth_load_op = wgpu::LoadOp::Load;
         }
-        frame.present();
-        self.window.request_redraw();
+        drop(rpass);
+        self.queue.submit(Some(encoder.finish()));
         self.meshes.clear_dynamic();
     }
 
-    pub fn event(&mut self, event: &Event<()>) {
-        self.camera.event(event);
+    /// # Panics
+    /// If the shader has no module for the vertex type of the mesh, which
+    /// only happens for the default shader.
+    fn create_pipeline(&self, command: &DrawCommand) -> RenderPipeline {
+        let shader = self.shaders.get_by_id(command.shader);
+        let module = &shader
+            .modules
+            .get(&command.mesh_id.vtx_type_id)
+            .expect(
+                "the default shader only draws meshes of PNVertex, PNUVertex \
+                 and PDVertex",
+            )
+            .module
+            .as_ref()
+            .expect("only drawings with compiled shaders are drawn");
+        let layout = if shader.uniforms.is_some() {
+            &self.uniform_pipeline_layout
+        } else {
+            &self.pipeline_layout
+        };
+        create_render_pipeline(
+            &self.device,
+            layout,
+            module,
+            self.meshes.vertex_layout(command.mesh_id),
+            self.format,
+            self.sample_count,
+            command.is_transparent(),
+        )
     }
 
-    pub fn update_camera(&mut self, dt: f32) {
-        self.camera.update(dt);
+    /// Grows the instance buffer to hold at least `count` instances.
+    fn reserve_instances(&mut self, count: usize) {
+        let capacity =
+            self.instance_buffer.size() as usize / size_of::<InstanceData>();
+        if count <= capacity {
+            return;
+        }
+        self.instance_buffer =
+            create_instance_buffer(&self.device, count.next_power_of_two());
     }
 
-    pub(crate) fn resize(&mut self, new_size: &PhysicalSize<u32>) {
-        self.config.width = new_size.width.max(1);
-        self.config.height = new_size.height.max(1);
-        (self.depth_texture, self.depth_texture_view) =
-            create_depth_texture(&self.device, &self.config);
-        self.surface.configure(&self.device, &self.config);
+    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        (self.depth_texture, self.depth_texture_view) = create_depth_texture(
+            &self.device,
+            width,
+            height,
+            1,
+            self.sample_count,
+        );
+        self.multisampled = create_multisampled_texture(
+            &self.device,
+            self.format,
+            width,
+            height,
+            self.sample_count,
+        );
     }
 }
 
-fn create_render_pipeline<V: Vertex>(
+/// Splits `commands`, sorted by shader, mesh and texture, into one batch
+/// per shader, mesh and texture together with the range of their instances
+/// in the instance buffer. Transparent commands are never batched with
+/// opaque ones.
+pub(crate) fn batches(
+    commands: &[DrawCommand],
+) -> impl Iterator<Item = (&[DrawCommand], Range<u32>)> {
+    let mut start = 0;
+    commands
+        .chunk_by(|a, b| {
+            let key = |c: &DrawCommand| {
+                (c.shader, c.mesh_id, c.texture, c.is_transparent())
+            };
+            key(a) == key(b)
+        })
+        .map(move |batch| {
+            let instances = start..start + batch.len() as u32;
+            start = instances.end;
+            (batch, instances)
+        })
+}
+
+/// Draws `instances` of the instance buffer with the mesh `mesh_id`, the
+/// pipeline has to be set already.
+pub(crate) fn draw_instances(
+    rpass: &mut wgpu::RenderPass<'_>,
+    meshes: &MeshManager,
+    instance_buffer: &Buffer,
+    mesh_id: MeshId,
+    instances: Range<u32>,
+) {
+    let instance_size = size_of::<InstanceData>() as u64;
+    let offset = u64::from(instances.start) * instance_size;
+    let end = u64::from(instances.end) * instance_size;
+    let mesh_buffers = meshes.get_by_id(mesh_id);
+    rpass.set_vertex_buffer(0, mesh_buffers.vertex.slice(..));
+    rpass.set_vertex_buffer(1, instance_buffer.slice(offset..end));
+    rpass.set_index_buffer(mesh_buffers.index.slice(..), IndexFormat::Uint32);
+    rpass.draw_indexed(
+        mesh_buffers.index_range.clone(),
+        0,
+        0..instances.len() as u32,
+    );
+}
+
+fn pipeline_key(command: &DrawCommand) -> (ShaderId, TypeId, bool) {
+    (
+        command.shader,
+        command.mesh_id.vtx_type_id,
+        command.is_transparent(),
+    )
+}
+
+/// Transparent pipelines blend with what is behind them and leave the depth
+/// buffer as it is. Opaque ones keep the alpha of the target.
+fn create_render_pipeline(
     device: &Device,
-    pipeline_layout: &wgpu::PipelineLayout,
+    pipeline_layout: &PipelineLayout,
     shader: &wgpu::ShaderModule,
+    vertex: &VertexLayout,
     swapchain_format: wgpu::TextureFormat,
+    sample_count: u32,
+    transparent: bool,
 ) -> RenderPipeline {
+    let target = if transparent {
+        wgpu::ColorTargetState {
+            format: swapchain_format,
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            write_mask: wgpu::ColorWrites::ALL,
+        }
+    } else {
+        wgpu::ColorTargetState {
+            format: swapchain_format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::COLOR,
+        }
+    };
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: None,
         layout: Some(pipeline_layout),
         vertex: wgpu::VertexState {
             module: shader,
             entry_point: "vs_main",
-            buffers: &[V::BUFFER_LAYOUT, InstanceData::BUFFER_LAYOUT],
+            buffers: &[vertex.buffer.clone(), InstanceData::BUFFER_LAYOUT],
             compilation_options: Default::default(),
         },
         primitive: wgpu::PrimitiveState {
             // cull_mode: Some(wgpu::Face::Back),
-            topology: V::PRIMITIVE_TOPOLOGY,
+            topology: vertex.topology,
             ..wgpu::PrimitiveState::default()
         },
         depth_stencil: Some(DepthStencilState {
             format: wgpu::TextureFormat::Depth32Float,
-            depth_write_enabled: true,
+            depth_write_enabled: !transparent,
             depth_compare: wgpu::CompareFunction::Less,
             stencil: StencilState::default(),
             bias: DepthBiasState::default(),
         }),
-        multisample: wgpu::MultisampleState::default(),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
         fragment: Some(wgpu::FragmentState {
             module: shader,
             entry_point: "fs_main",
             compilation_options: Default::default(),
-            targets: &[Some(swapchain_format.into())],
+            targets: &[Some(target)],
         }),
         multiview: None,
         cache: None,
     })
 }
 
-fn create_depth_texture(
+/// A depth texture with `layers` layers, the view covers all of them.
+pub(crate) fn create_depth_texture(
     device: &Device,
-    config: &SurfaceConfiguration,
+    width: u32,
+    height: u32,
+    layers: u32,
+    sample_count: u32,
 ) -> (Texture, TextureView) {
     let texture = device.create_texture(&TextureDescriptor {
         label: None,
         size: Extent3d {
-            width: config.width,
-            height: config.height,
-            depth_or_array_layers: 1,
+            width,
+            height,
+            depth_or_array_layers: layers,
         },
         mip_level_count: 1,
-        sample_count: 1,
+        sample_count,
         dimension: wgpu::TextureDimension::D2,
         format: wgpu::TextureFormat::Depth32Float,
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-            | wgpu::TextureUsages::TEXTURE_BINDING,
+        // only single sampled depth is read, by the shadows, and OpenGL
+        // cannot resolve into a framebuffer that mixes multisampled
+        // textures and renderbuffers
+        usage: if sample_count == 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        },
         view_formats: &[],
     });
-    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(if layers == 1 {
+            wgpu::TextureViewDimension::D2
+        } else {
+            wgpu::TextureViewDimension::D2Array
+        }),
+        ..Default::default()
+    });
     (texture, view)
 }
 
+/// The color target of multisampled rendering, `None` for a single sample.
+fn create_multisampled_texture(
+    device: &Device,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> Option<(Texture, TextureView)> {
+    if sample_count == 1 {
+        return None;
+    }
+    let texture = device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    Some((texture, view))
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
@@ -393,18 +933,35 @@ impl From<&FirstPersonCamera> for CameraUniform {
     }
 }
 
-const MAX_INSTANCE_COUNT: usize = 1024;
+/// The instance buffer grows to the next power of two when a frame has
+/// more instances.
+const INITIAL_INSTANCE_CAPACITY: usize = 1024;
+
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: (size_of::<InstanceData>() * capacity) as u64,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceData {
+pub(crate) struct InstanceData {
     model: [[f32; 4]; 4],
     model_inv: [[f32; 4]; 4],
+    /// The base color of the material.
     color: [f32; 3],
+    /// 1 or 0, as vertex attributes cannot be booleans.
+    receive_shadows: f32,
+    /// Metallic, roughness and alpha of the material, and padding.
+    material: [f32; 4],
+    emissive: [f32; 3],
 }
 
 impl InstanceData {
-    pub const ATTRIB: [VertexAttribute; 9] = wgpu::vertex_attr_array![
+    pub const ATTRIB: [VertexAttribute; 12] = wgpu::vertex_attr_array![
         4 => Float32x4,
         5 => Float32x4,
         6 => Float32x4,
@@ -414,6 +971,9 @@ impl InstanceData {
         10 => Float32x4,
         11 => Float32x4,
         12 => Float32x3,
+        13 => Float32,
+        14 => Float32x4,
+        15 => Float32x3,
     ];
 
     pub const BUFFER_LAYOUT: VertexBufferLayout<'static> = VertexBufferLayout {
@@ -425,10 +985,19 @@ impl InstanceData {
 
 impl From<&DrawCommand> for InstanceData {
     fn from(command: &DrawCommand) -> Self {
+        let material = &command.material;
         Self {
             model: command.transform.into(),
             model_inv: command.transform.try_inverse().unwrap().into(),
-            color: command.color,
+            color: material.base_color,
+            receive_shadows: if command.receive_shadows { 1.0 } else { 0.0 },
+            material: [
+                material.metallic,
+                material.roughness,
+                material.alpha,
+                0.0,
+            ],
+            emissive: material.emissive,
         }
     }
 }
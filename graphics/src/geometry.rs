@@ -1,10 +1,13 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
 
-use crate::mesh::{Dynamic, Mesh, MeshProvider, PDVertex, PNVertex, Static};
+use crate::mesh::{Dynamic, Mesh, MeshProvider, PDVertex, PNUVertex, Static};
 
 #[derive(Clone, Copy)]
 pub struct Box;
 
+/// A unit sphere, its texture is wrapped around the y axis with the top at
+/// `y = 1`.
 #[derive(Clone, Copy)]
 pub struct Ellipsoid;
 
@@ -12,41 +15,41 @@ pub struct Ellipsoid;
 pub struct BoxLines;
 
 impl MeshProvider for Box {
-    type Vertex = PNVertex;
+    type Vertex = PNUVertex;
     type Kind = Static;
 
     fn create_mesh(self) -> Mesh<Self::Vertex> {
         #[rustfmt::skip]
         let vertices = vec![
-           PNVertex { position: [ 0.5,  0.5,  0.5], normal: [ 0.0,  0.0,  1.0] },
-           PNVertex { position: [-0.5,  0.5,  0.5], normal: [ 0.0,  0.0,  1.0] },
-           PNVertex { position: [ 0.5, -0.5,  0.5], normal: [ 0.0,  0.0,  1.0] },
-           PNVertex { position: [-0.5, -0.5,  0.5], normal: [ 0.0,  0.0,  1.0] },
-
-           PNVertex { position: [-0.5,  0.5, -0.5], normal: [ 0.0,  0.0, -1.0] },
-           PNVertex { position: [ 0.5,  0.5, -0.5], normal: [ 0.0,  0.0, -1.0] },
-           PNVertex { position: [-0.5, -0.5, -0.5], normal: [ 0.0,  0.0, -1.0] },
-           PNVertex { position: [ 0.5, -0.5, -0.5], normal: [ 0.0,  0.0, -1.0] },
-
-           PNVertex { position: [ 0.5,  0.5,  0.5], normal: [ 1.0,  0.0,  0.0] },
-           PNVertex { position: [ 0.5, -0.5,  0.5], normal: [ 1.0,  0.0,  0.0] },
-           PNVertex { position: [ 0.5,  0.5, -0.5], normal: [ 1.0,  0.0,  0.0] },
-           PNVertex { position: [ 0.5, -0.5, -0.5], normal: [ 1.0,  0.0,  0.0] },
-
-           PNVertex { position: [-0.5, -0.5,  0.5], normal: [-1.0,  0.0,  0.0] },
-           PNVertex { position: [-0.5,  0.5,  0.5], normal: [-1.0,  0.0,  0.0] },
-           PNVertex { position: [-0.5, -0.5, -0.5], normal: [-1.0,  0.0,  0.0] },
-           PNVertex { position: [-0.5,  0.5, -0.5], normal: [-1.0,  0.0,  0.0] },
-
-           PNVertex { position: [ 0.5,  0.5,  0.5], normal: [ 0.0,  1.0,  0.0] },
-           PNVertex { position: [ 0.5,  0.5, -0.5], normal: [ 0.0,  1.0,  0.0] },
-           PNVertex { position: [-0.5,  0.5,  0.5], normal: [ 0.0,  1.0,  0.0] },
-           PNVertex { position: [-0.5,  0.5, -0.5], normal: [ 0.0,  1.0,  0.0] },
-
-           PNVertex { position: [ 0.5, -0.5, -0.5], normal: [ 0.0, -1.0,  0.0] },
-           PNVertex { position: [ 0.5, -0.5,  0.5], normal: [ 0.0, -1.0,  0.0] },
-           PNVertex { position: [-0.5, -0.5, -0.5], normal: [ 0.0, -1.0,  0.0] },
-           PNVertex { position: [-0.5, -0.5,  0.5], normal: [ 0.0, -1.0,  0.0] },
+           PNUVertex { position: [ 0.5,  0.5,  0.5], normal: [ 0.0,  0.0,  1.0], uv: [1.0, 0.0] },
+           PNUVertex { position: [-0.5,  0.5,  0.5], normal: [ 0.0,  0.0,  1.0], uv: [0.0, 0.0] },
+           PNUVertex { position: [ 0.5, -0.5,  0.5], normal: [ 0.0,  0.0,  1.0], uv: [1.0, 1.0] },
+           PNUVertex { position: [-0.5, -0.5,  0.5], normal: [ 0.0,  0.0,  1.0], uv: [0.0, 1.0] },
+
+           PNUVertex { position: [-0.5,  0.5, -0.5], normal: [ 0.0,  0.0, -1.0], uv: [1.0, 0.0] },
+           PNUVertex { position: [ 0.5,  0.5, -0.5], normal: [ 0.0,  0.0, -1.0], uv: [0.0, 0.0] },
+           PNUVertex { position: [-0.5, -0.5, -0.5], normal: [ 0.0,  0.0, -1.0], uv: [1.0, 1.0] },
+           PNUVertex { position: [ 0.5, -0.5, -0.5], normal: [ 0.0,  0.0, -1.0], uv: [0.0, 1.0] },
+
+           PNUVertex { position: [ 0.5,  0.5,  0.5], normal: [ 1.0,  0.0,  0.0], uv: [0.0, 0.0] },
+           PNUVertex { position: [ 0.5, -0.5,  0.5], normal: [ 1.0,  0.0,  0.0], uv: [0.0, 1.0] },
+           PNUVertex { position: [ 0.5,  0.5, -0.5], normal: [ 1.0,  0.0,  0.0], uv: [1.0, 0.0] },
+           PNUVertex { position: [ 0.5, -0.5, -0.5], normal: [ 1.0,  0.0,  0.0], uv: [1.0, 1.0] },
+
+           PNUVertex { position: [-0.5, -0.5,  0.5], normal: [-1.0,  0.0,  0.0], uv: [1.0, 1.0] },
+           PNUVertex { position: [-0.5,  0.5,  0.5], normal: [-1.0,  0.0,  0.0], uv: [1.0, 0.0] },
+           PNUVertex { position: [-0.5, -0.5, -0.5], normal: [-1.0,  0.0,  0.0], uv: [0.0, 1.0] },
+           PNUVertex { position: [-0.5,  0.5, -0.5], normal: [-1.0,  0.0,  0.0], uv: [0.0, 0.0] },
+
+           PNUVertex { position: [ 0.5,  0.5,  0.5], normal: [ 0.0,  1.0,  0.0], uv: [1.0, 1.0] },
+           PNUVertex { position: [ 0.5,  0.5, -0.5], normal: [ 0.0,  1.0,  0.0], uv: [1.0, 0.0] },
+           PNUVertex { position: [-0.5,  0.5,  0.5], normal: [ 0.0,  1.0,  0.0], uv: [0.0, 1.0] },
+           PNUVertex { position: [-0.5,  0.5, -0.5], normal: [ 0.0,  1.0,  0.0], uv: [0.0, 0.0] },
+
+           PNUVertex { position: [ 0.5, -0.5, -0.5], normal: [ 0.0, -1.0,  0.0], uv: [1.0, 1.0] },
+           PNUVertex { position: [ 0.5, -0.5,  0.5], normal: [ 0.0, -1.0,  0.0], uv: [1.0, 0.0] },
+           PNUVertex { position: [-0.5, -0.5, -0.5], normal: [ 0.0, -1.0,  0.0], uv: [0.0, 1.0] },
+           PNUVertex { position: [-0.5, -0.5,  0.5], normal: [ 0.0, -1.0,  0.0], uv: [0.0, 0.0] },
         ];
         #[rustfmt::skip]
         let indices = vec![
@@ -62,22 +65,44 @@ impl MeshProvider for Box {
 }
 
 impl MeshProvider for Ellipsoid {
-    type Vertex = PNVertex;
+    type Vertex = PNUVertex;
     type Kind = Static;
 
     fn create_mesh(self) -> Mesh<Self::Vertex> {
         let Polyhedron { vertices, faces } = icosphere(3);
-        let vertices = vertices
+        let mut vertices: Vec<_> = vertices
             .into_iter()
-            .map(|p| PNVertex {
+            .map(|p| PNUVertex {
                 position: p,
                 normal: p,
+                uv: [
+                    0.5 - p[2].atan2(p[0]) / (2.0 * PI),
+                    0.5 - p[1].asin() / PI,
+                ],
             })
             .collect();
-        let indices = faces
-            .into_iter()
-            .flat_map(|f| f.map(|i| i as u32))
-            .collect();
+        // faces across the seam of the texture get copies of their
+        // vertices on its left side, moved one texture width to the right
+        let mut seam_copies = HashMap::new();
+        let mut indices = Vec::with_capacity(faces.len() * 3);
+        for face in faces {
+            let u = face.map(|i| vertices[i].uv[0]);
+            let crosses_seam =
+                u.iter().any(|&u| u < 0.25) && u.iter().any(|&u| u > 0.75);
+            for i in face {
+                if crosses_seam && vertices[i].uv[0] < 0.5 {
+                    let copy = *seam_copies.entry(i).or_insert_with(|| {
+                        let mut vertex = vertices[i];
+                        vertex.uv[0] += 1.0;
+                        vertices.push(vertex);
+                        vertices.len() - 1
+                    });
+                    indices.push(copy as u32);
+                } else {
+                    indices.push(i as u32);
+                }
+            }
+        }
         Mesh { vertices, indices }
     }
 }
@@ -281,7 +306,7 @@ impl<GenFn: Fn(f32, f32) -> (f32, f32, f32)> ParametricSquare<GenFn> {
 impl<GenFn: Fn(f32, f32) -> (f32, f32, f32) + Copy> MeshProvider
     for ParametricSquare<GenFn>
 {
-    type Vertex = PNVertex;
+    type Vertex = PNUVertex;
     type Kind = Dynamic;
 
     fn create_mesh(self) -> Mesh<Self::Vertex> {
@@ -302,9 +327,10 @@ impl<GenFn: Fn(f32, f32) -> (f32, f32, f32) + Copy> MeshProvider
                 ];
                 let normal_len = normal[0].hypot(normal[1]).hypot(normal[2]);
                 let normal = normal.map(|n| n / normal_len);
-                vertices.push(PNVertex {
+                vertices.push(PNUVertex {
                     position: [x, y, z],
                     normal,
+                    uv: [x + 0.5, z + 0.5],
                 });
             }
         }
@@ -326,14 +352,14 @@ impl<GenFn: Fn(f32, f32) -> (f32, f32, f32) + Copy> MeshProvider
 }
 
 #[derive(Clone, Copy)]
-pub struct LowPoly<Provider: MeshProvider<Vertex = PNVertex, Kind = Dynamic>>(
+pub struct LowPoly<Provider: MeshProvider<Vertex = PNUVertex, Kind = Dynamic>>(
     pub Provider,
 );
 
-impl<Provider: MeshProvider<Vertex = PNVertex, Kind = Dynamic>> MeshProvider
+impl<Provider: MeshProvider<Vertex = PNUVertex, Kind = Dynamic>> MeshProvider
     for LowPoly<Provider>
 {
-    type Vertex = PNVertex;
+    type Vertex = PNUVertex;
 
     type Kind = Dynamic;
 
@@ -344,13 +370,13 @@ impl<Provider: MeshProvider<Vertex = PNVertex, Kind = Dynamic>> MeshProvider
 
 #[derive(Clone, Copy)]
 pub struct StaticLowPoly<
-    Provider: MeshProvider<Vertex = PNVertex, Kind = Static> + 'static,
+    Provider: MeshProvider<Vertex = PNUVertex, Kind = Static> + 'static,
 >(pub Provider);
 
-impl<Provider: MeshProvider<Vertex = PNVertex, Kind = Static> + 'static>
+impl<Provider: MeshProvider<Vertex = PNUVertex, Kind = Static> + 'static>
     MeshProvider for StaticLowPoly<Provider>
 {
-    type Vertex = PNVertex;
+    type Vertex = PNUVertex;
 
     type Kind = Static;
 
@@ -359,7 +385,7 @@ impl<Provider: MeshProvider<Vertex = PNVertex, Kind = Static> + 'static>
     }
 }
 
-fn low_poly_triangles(mesh: Mesh<PNVertex>) -> Mesh<PNVertex> {
+fn low_poly_triangles(mesh: Mesh<PNUVertex>) -> Mesh<PNUVertex> {
     let mut vertices = Vec::with_capacity(mesh.indices.len());
     let indices = (0..mesh.indices.len() as u32).collect();
     for i in mesh
@@ -377,10 +403,7 @@ fn low_poly_triangles(mesh: Mesh<PNVertex>) -> Mesh<PNVertex> {
         ];
         let normal_len = normal[0].hypot(normal[1]).hypot(normal[2]);
         let normal = normal.map(|n| n / normal_len);
-        vertices.extend_from_slice(&v.map(|v| PNVertex {
-            position: v.position,
-            normal,
-        }));
+        vertices.extend_from_slice(&v.map(|v| PNUVertex { normal, ..v }));
     }
     Mesh { vertices, indices }
 }
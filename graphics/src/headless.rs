@@ -0,0 +1,379 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use pollster::FutureExt;
+use wgpu::{
+    AdapterInfo, BufferDescriptor, BufferUsages, CommandEncoderDescriptor,
+    Extent3d, Texture, TextureDescriptor, TextureFormat, TextureUsages,
+    TextureView,
+};
+
+use crate::{
+    camera::FirstPersonCamera,
+    canvas::Canvas,
+    context::{request_device, ContextConfig, Renderer, RendererConfig},
+    image::Image,
+    shader::ShaderError,
+};
+
+/// The format of the offscreen texture, so the pixels read back are sRGB
+/// like in a PNG.
+const FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+/// Renders into an offscreen texture instead of a window, so scenes can be
+/// rendered without a display, e.g. in tests.
+pub struct HeadlessContext {
+    renderer: Renderer,
+    adapter_info: AdapterInfo,
+    camera: FirstPersonCamera,
+    texture: Texture,
+    view: TextureView,
+}
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    /// Neither a hardware nor a fallback adapter is available.
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+}
+
+impl Display for HeadlessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoAdapter => write!(f, "no graphics adapter available"),
+            Self::RequestDevice(e) => write!(f, "cannot create device: {e}"),
+        }
+    }
+}
+
+impl Error for HeadlessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::NoAdapter => None,
+            Self::RequestDevice(e) => Some(e),
+        }
+    }
+}
+
+impl HeadlessContext {
+    /// Uses the default adapter, or the fallback (software) adapter if
+    /// there is none.
+    pub fn new(width: u32, height: u32) -> Result<Self, HeadlessError> {
+        Self::with_config(width, height, &ContextConfig::default())
+    }
+
+    /// Like [`new`](Self::new), with the sample count, clear color and
+    /// power preference of `config`. Its window and presentation options
+    /// do not apply.
+    pub fn with_config(
+        width: u32,
+        height: u32,
+        config: &ContextConfig,
+    ) -> Result<Self, HeadlessError> {
+        Self::create(width, height, config, false)
+            .or_else(|_| Self::create(width, height, config, true))
+    }
+
+    /// Uses only the fallback adapter if `force_fallback_adapter` is set,
+    /// which renders the same on every machine.
+    pub fn with_fallback(
+        width: u32,
+        height: u32,
+        force_fallback_adapter: bool,
+    ) -> Result<Self, HeadlessError> {
+        Self::create(
+            width,
+            height,
+            &ContextConfig::default(),
+            force_fallback_adapter,
+        )
+    }
+
+    fn create(
+        width: u32,
+        height: u32,
+        config: &ContextConfig,
+        force_fallback_adapter: bool,
+    ) -> Result<Self, HeadlessError> {
+        let instance = wgpu::Instance::default();
+        let (adapter, device, queue) = async {
+            let adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: config.power_preference,
+                    force_fallback_adapter,
+                    compatible_surface: None,
+                })
+                .await
+                .ok_or(HeadlessError::NoAdapter)?;
+            let (device, queue) = request_device(&adapter)
+                .await
+                .map_err(HeadlessError::RequestDevice)?;
+            Ok((adapter, device, queue))
+        }
+        .block_on()?;
+        let (width, height) = (width.max(1), height.max(1));
+        let renderer_config = RendererConfig::new(&device, FORMAT, config);
+        let renderer = Renderer::new(
+            device,
+            queue,
+            FORMAT,
+            width,
+            height,
+            &renderer_config,
+        );
+        let (texture, view) = create_texture(&renderer, width, height);
+        let mut camera = FirstPersonCamera::default();
+        camera.set_aspect(width as f32 / height as f32);
+        Ok(Self {
+            renderer,
+            adapter_info: adapter.get_info(),
+            camera,
+            texture,
+            view,
+        })
+    }
+
+    /// Which adapter renders, e.g. to tell a software renderer apart.
+    pub fn adapter_info(&self) -> &AdapterInfo {
+        &self.adapter_info
+    }
+
+    pub fn width(&self) -> u32 {
+        self.texture.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.texture.height()
+    }
+
+    pub fn camera(&self) -> &FirstPersonCamera {
+        &self.camera
+    }
+
+    /// The camera starts at the origin looking along +z, its aspect ratio
+    /// is kept in sync with the image size.
+    pub fn camera_mut(&mut self) -> &mut FirstPersonCamera {
+        &mut self.camera
+    }
+
+    /// Reloads the shaders whenever their files change, see
+    /// [`Context::watch_shaders`](crate::context::Context::watch_shaders).
+    pub fn watch_shaders(&mut self) {
+        self.renderer.shaders.watch();
+    }
+
+    /// Why the shaders that currently fail to compile or do not fit their
+    /// meshes fail, see
+    /// [`Context::shader_errors`](crate::context::Context::shader_errors).
+    pub fn shader_errors(&self) -> impl Iterator<Item = &ShaderError> {
+        self.renderer.shaders.errors()
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let (width, height) = (width.max(1), height.max(1));
+        self.renderer.resize(width, height);
+        (self.texture, self.view) =
+            create_texture(&self.renderer, width, height);
+        self.camera.set_aspect(width as f32 / height as f32);
+    }
+
+    /// Renders what `draw` draws on the canvas and reads it back.
+    pub fn render(&mut self, draw: impl FnOnce(&mut Canvas)) -> Image {
+        let mut canvas = Canvas::new(
+            &mut self.renderer.meshes,
+            &mut self.renderer.shaders,
+            &mut self.renderer.textures,
+            self.camera.frustum(),
+        );
+        draw(&mut canvas);
+        let (commands, lights) = (canvas.commands, canvas.lights);
+        self.renderer
+            .render(&self.camera, commands, &lights, &self.view);
+        self.read_back()
+    }
+
+    fn read_back(&self) -> Image {
+        let (width, height) = (self.width(), self.height());
+        let bytes_per_row =
+            (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let device = &self.renderer.device;
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: u64::from(bytes_per_row * height),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            self.texture.size(),
+        );
+        self.renderer.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait).panic_on_timeout();
+        let mapped = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for row in mapped.chunks_exact(bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..(width * 4) as usize]);
+        }
+        drop(mapped);
+        buffer.unmap();
+        Image::new(width, height, pixels)
+    }
+}
+
+fn create_texture(
+    renderer: &Renderer,
+    width: u32,
+    height: u32,
+) -> (Texture, TextureView) {
+    let texture = renderer.device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{Box, BoxLines};
+    use crate::material::Material;
+    use crate::math::Transform;
+
+    #[test]
+    fn renders_box() {
+        let Ok(mut context) = HeadlessContext::new(64, 48) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        let image = context.render(|canvas| {
+            canvas
+                .draw(Box)
+                .translate(0.0, 0.0, 5.0)
+                .color([1.0, 0.0, 0.0]);
+        });
+        assert_eq!((image.width, image.height), (64, 48));
+        // the corners show the clear color, the center the box
+        let background = image.pixel(0, 0);
+        assert_eq!(image.pixel(63, 47), background);
+        let center = image.pixel(32, 24);
+        assert_ne!(center, background);
+        assert!(center[0] > center[1] && center[0] > center[2]);
+
+        let empty = context.render(|_| {});
+        assert!(empty.pixels.chunks(4).all(|p| p == background));
+    }
+
+    type Cell = (f32, f32, [f32; 3]);
+
+    fn draw_cells<'a>(
+        canvas: &mut Canvas,
+        cells: impl Iterator<Item = &'a Cell>,
+    ) {
+        for &(x, y, color) in cells {
+            canvas
+                .draw(Box)
+                .scale(0.04, 0.04, 0.04)
+                .translate(x, y, 5.0)
+                .color(color);
+            canvas
+                .draw(BoxLines)
+                .scale(0.045, 0.045, 0.045)
+                .translate(x, y, 5.0);
+        }
+    }
+
+    #[test]
+    fn renders_more_instances_than_the_initial_buffer() {
+        let Ok(mut context) = HeadlessContext::new(64, 48) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        // a wall of 60x60 small boxes that do not touch, in a checkerboard
+        // of colors and with lines around them, far more instances than
+        // the buffer starts with
+        let cells: Vec<Cell> = (0..60 * 60)
+            .map(|i| {
+                let (x, y) = (i % 60, i / 60);
+                let color = if (x + y) % 2 == 0 {
+                    [1.0, 0.0, 0.0]
+                } else {
+                    [0.0, 0.0, 1.0]
+                };
+                (x as f32 / 10.0 - 3.0, y as f32 / 10.0 - 3.0, color)
+            })
+            .collect();
+        let image = context.render(|canvas| draw_cells(canvas, cells.iter()));
+        let reversed =
+            context.render(|canvas| draw_cells(canvas, cells.iter().rev()));
+        // the shading is not exactly the same in any order
+        assert_eq!(image.compare(&reversed, 0.1).unwrap().differing, 0);
+        let background = context.render(|_| {}).pixel(0, 0);
+        let covered =
+            image.pixels.chunks(4).filter(|&p| p != background).count();
+        assert!(covered > 64 * 48 / 2, "{covered}");
+    }
+
+    #[test]
+    fn smooths_edges_with_multisampling() {
+        // a white tilted box on black, without anti-aliasing only black
+        // and white pixels
+        let render = |sample_count| {
+            let config = ContextConfig::default()
+                .sample_count(sample_count)
+                .clear_color(wgpu::Color::BLACK);
+            let mut context = HeadlessContext::with_config(64, 48, &config)
+                .map_err(|e| eprintln!("{e}, skipping"))
+                .ok()?;
+            Some(context.render(|canvas| {
+                canvas
+                    .draw(Box)
+                    .rotate_z(0.3)
+                    .translate(0.0, 0.0, 5.0)
+                    .material(Material {
+                        base_color: [0.0, 0.0, 0.0],
+                        emissive: [1.0, 1.0, 1.0],
+                        ..Material::DEFAULT
+                    });
+            }))
+        };
+        let (Some(aliased), Some(smooth)) = (render(1), render(4)) else {
+            return;
+        };
+        let grey = |image: &Image| {
+            image
+                .pixels
+                .chunks(4)
+                .filter(|p| p[0] != 0 && p[0] != 255)
+                .count()
+        };
+        assert_eq!(aliased.pixel(0, 0), [0, 0, 0, 255]);
+        assert_eq!(grey(&aliased), 0);
+        assert!(grey(&smooth) > 0);
+        assert_eq!(smooth.pixel(0, 0), [0, 0, 0, 255]);
+        assert_eq!(smooth.pixel(32, 24), [255, 255, 255, 255]);
+    }
+}
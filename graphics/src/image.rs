@@ -0,0 +1,265 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// An 8 bit RGBA image with sRGB colors, rows from top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// `width * height * 4` bytes.
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// # Panics
+    /// If `pixels` does not hold exactly `width * height` pixels.
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize * 4);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[i..][..4].try_into().unwrap()
+    }
+
+    pub fn write_png(&self, writer: impl Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+
+    /// Reads an 8 bit PNG, grayscale, RGB and palette images are expanded
+    /// to RGBA.
+    pub fn read_png(reader: impl Read) -> io::Result<Self> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(
+            png::Transformations::EXPAND | png::Transformations::STRIP_16,
+        );
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        buffer.truncate(info.buffer_size());
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buffer,
+            png::ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => {
+                buffer.iter().flat_map(|&g| [g, g, g, 255]).collect()
+            }
+            png::ColorType::Indexed => {
+                unreachable!("palettes are expanded by the decoder")
+            }
+        };
+        Ok(Self::new(info.width, info.height, pixels))
+    }
+
+    pub fn load_png(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_png(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a baseline or progressive JPEG, grayscale and RGB images are
+    /// expanded to RGBA.
+    pub fn read_jpeg(reader: impl Read) -> io::Result<Self> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut decoder = jpeg_decoder::Decoder::new(reader);
+        let buffer = decoder.decode().map_err(invalid)?;
+        let info = decoder.info().expect("decoded images have info");
+        let pixels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::RGB24 => buffer
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            jpeg_decoder::PixelFormat::L8 => {
+                buffer.iter().flat_map(|&g| [g, g, g, 255]).collect()
+            }
+            // big endian, the high byte comes first
+            jpeg_decoder::PixelFormat::L16 => buffer
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], 255])
+                .collect(),
+            jpeg_decoder::PixelFormat::CMYK32 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "CMYK JPEGs are not supported",
+                ))
+            }
+        };
+        Ok(Self::new(
+            u32::from(info.width),
+            u32::from(info.height),
+            pixels,
+        ))
+    }
+
+    pub fn load_jpeg(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_jpeg(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads a PNG or a JPEG, told apart by their first bytes.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+            Self::read_png(&bytes[..])
+        } else if bytes.starts_with(&[0xFF, 0xD8]) {
+            Self::read_jpeg(&bytes[..])
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "neither a PNG nor a JPEG",
+            ))
+        }
+    }
+
+    /// Compares the images pixel by pixel, returns `None` if their sizes
+    /// differ.
+    ///
+    /// Pixels count as different if their perceived color difference is
+    /// above `threshold`. It goes from 0, where any difference counts, to
+    /// 1, where none does. Black against white is about 0.97.
+    pub fn compare(&self, other: &Image, threshold: f32) -> Option<Comparison> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        let mut differing = 0;
+        let mut max_delta = 0.0f32;
+        let mut diff = Vec::with_capacity(self.pixels.len());
+        for (a, b) in self.pixels.chunks(4).zip(other.pixels.chunks(4)) {
+            let delta = color_delta(a, b);
+            max_delta = max_delta.max(delta);
+            if delta > threshold {
+                differing += 1;
+                diff.extend_from_slice(&[255, 0, 0, 255]);
+            } else {
+                // a faded gray version of the image for context
+                let gray = 0.3 * f32::from(a[0])
+                    + 0.59 * f32::from(a[1])
+                    + 0.11 * f32::from(a[2]);
+                let faded = (255.0 - (255.0 - gray) * 0.2) as u8;
+                diff.extend_from_slice(&[faded, faded, faded, 255]);
+            }
+        }
+        Some(Comparison {
+            differing,
+            max_delta,
+            diff: Image::new(self.width, self.height, diff),
+        })
+    }
+}
+
+/// The result of [`Image::compare`].
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    /// Pixels whose difference is above the threshold.
+    pub differing: usize,
+    /// The largest difference of any pixel, from 0 to 1.
+    pub max_delta: f32,
+    /// The differing pixels in red over a faded copy of the first image.
+    pub diff: Image,
+}
+
+impl Comparison {
+    /// Differing pixels relative to all pixels.
+    pub fn differing_fraction(&self) -> f64 {
+        let total = self.diff.width as usize * self.diff.height as usize;
+        self.differing as f64 / total.max(1) as f64
+    }
+}
+
+/// The perceived difference of two sRGB colors, from 0 for equal colors to
+/// 1 for the most different ones.
+///
+/// Uses the distance in YIQ space from "Measuring perceived color
+/// difference using YIQ NTSC transmission color space in mobile
+/// applications" by Kotsarenko and Ramos, alpha is blended onto white.
+fn color_delta(a: &[u8], b: &[u8]) -> f32 {
+    let blend = |p: &[u8]| {
+        let alpha = f32::from(p[3]) / 255.0;
+        [0, 1, 2].map(|i| 255.0 + (f32::from(p[i]) - 255.0) * alpha)
+    };
+    let yiq = |[r, g, b]: [f32; 3]| {
+        [
+            r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_2,
+            r * 0.595_978 - g * 0.274_176_1 - b * 0.321_801_9,
+            r * 0.211_470_2 - g * 0.522_617_1 + b * 0.311_146_9,
+        ]
+    };
+    let (a, b) = (yiq(blend(a)), yiq(blend(b)));
+    let [y, i, q] = [0, 1, 2].map(|c| a[c] - b[c]);
+    // 35215 is the largest squared delta of any two colors
+    ((0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / 35215.0).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_round_trip() {
+        let pixels = (0..6 * 4 * 4).map(|i| (i * 7) as u8).collect();
+        let image = Image::new(6, 4, pixels);
+        let mut png = vec![];
+        image.write_png(&mut png).unwrap();
+        assert_eq!(Image::read_png(&png[..]).unwrap(), image);
+        assert_eq!(image.pixel(1, 2), [108, 115, 122, 129]);
+    }
+
+    #[test]
+    fn reads_jpeg() {
+        // an 8x8 gray JPEG: only the DC coefficient, with one symbol Huffman
+        // tables and all quantization factors 1
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xDB, 0, 67, 0];
+        jpeg.extend([1; 64]);
+        jpeg.extend([0xFF, 0xC0, 0, 11, 8, 0, 8, 0, 8, 1, 1, 0x11, 0]);
+        for (class, symbol) in [(0x00, 8), (0x10, 0)] {
+            jpeg.extend([0xFF, 0xC4, 0, 20, class, 1]);
+            jpeg.extend([0; 15]);
+            jpeg.push(symbol);
+        }
+        jpeg.extend([0xFF, 0xDA, 0, 8, 1, 1, 0x00, 0, 63, 0]);
+        // DC difference 128 in category 8, then end of block
+        jpeg.extend([0b0100_0000, 0b0011_1111, 0xFF, 0xD9]);
+        let image = Image::read_jpeg(&jpeg[..]).unwrap();
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.pixel(3, 5), [144, 144, 144, 255]);
+    }
+
+    #[test]
+    fn compare() {
+        let black = Image::new(2, 1, vec![0, 0, 0, 255, 0, 0, 0, 255]);
+        let mut other = black.clone();
+        assert_eq!(black.compare(&other, 0.0).unwrap().differing, 0);
+        other.pixels[0] = 255;
+        other.pixels[1] = 255;
+        other.pixels[2] = 255;
+        let comparison = black.compare(&other, 0.1).unwrap();
+        assert_eq!(comparison.differing, 1);
+        assert!(comparison.max_delta > 0.9);
+        assert_eq!(comparison.diff.pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(comparison.differing_fraction(), 0.5);
+        other.pixels[4] = 2;
+        let comparison = black.compare(&other, 0.1).unwrap();
+        assert_eq!(comparison.differing, 1);
+        assert!(black.compare(&Image::new(1, 1, vec![0; 4]), 0.1).is_none());
+    }
+}
@@ -3,6 +3,12 @@ pub mod camera;
 pub mod canvas;
 pub mod context;
 pub mod geometry;
+pub mod headless;
+pub mod image;
+pub mod light;
+pub mod material;
 pub mod math;
 pub mod mesh;
-// pub mod shader;
+pub mod shader;
+mod shadow;
+pub mod texture;
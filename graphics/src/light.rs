@@ -0,0 +1,314 @@
+//! Lights added to a [`Canvas`](crate::canvas::Canvas) with
+//! [`Canvas::light`](crate::canvas::Canvas::light).
+//!
+//! Surfaces are shaded with Blinn-Phong, summing the light of every light
+//! of the frame. Positions and directions are in the space of the group the
+//! light is added in, like the transforms of drawings.
+//!
+//! The first [`MAX_SHADOW_MAPS`] directional and spot lights of a frame cast
+//! shadows, see [`Drawing::cast_shadows`].
+//!
+//! [`Drawing::cast_shadows`]: crate::canvas::drawing::Drawing::cast_shadows
+
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use crate::{camera::FirstPersonCamera, shadow};
+
+/// Lights beyond this many per frame are ignored.
+pub const MAX_LIGHTS: usize = 16;
+
+/// Directional and spot lights beyond this many per frame cast no shadows.
+pub const MAX_SHADOW_MAPS: usize = 4;
+
+/// Light reaching every surface equally from all directions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmbientLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// Light from infinitely far away, like the sun.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    /// The direction the light travels in, it does not have to be
+    /// normalized.
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// Light shining from a point in all directions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    /// The intensity at distance 1, it falls off with the square of the
+    /// distance.
+    pub intensity: f32,
+    /// The light fades out smoothly to nothing at this distance.
+    pub range: f32,
+}
+
+/// A [`PointLight`] that only shines into a cone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpotLight {
+    pub position: [f32; 3],
+    /// The axis of the cone, it does not have to be normalized.
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+    /// Angle from the axis in radians up to which the light is at full
+    /// intensity.
+    pub inner_angle: f32,
+    /// Angle from the axis in radians at which the light has faded out.
+    pub outer_angle: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    Ambient(AmbientLight),
+    Directional(DirectionalLight),
+    Point(PointLight),
+    Spot(SpotLight),
+}
+
+impl Light {
+    /// Used for frames without any lights: a dim ambient light and a
+    /// directional light shining from `(1, 1, 1)` towards the origin.
+    pub const DEFAULT: [Light; 2] = [
+        Light::Ambient(AmbientLight {
+            color: [1.0, 1.0, 1.0],
+            intensity: 0.2,
+        }),
+        Light::Directional(DirectionalLight {
+            direction: [-1.0, -1.0, -1.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 0.8,
+        }),
+    ];
+
+    /// Moves the light from the space of a group into its parent.
+    pub(crate) fn transform(self, transform: &Matrix4<f32>) -> Self {
+        let point =
+            |p: [f32; 3]| transform.transform_point(&Point3::from(p)).into();
+        let vector =
+            |v: [f32; 3]| transform.transform_vector(&Vector3::from(v)).into();
+        match self {
+            Light::Ambient(_) => self,
+            Light::Directional(light) => Light::Directional(DirectionalLight {
+                direction: vector(light.direction),
+                ..light
+            }),
+            Light::Point(light) => Light::Point(PointLight {
+                position: point(light.position),
+                ..light
+            }),
+            Light::Spot(light) => Light::Spot(SpotLight {
+                position: point(light.position),
+                direction: vector(light.direction),
+                ..light
+            }),
+        }
+    }
+}
+
+impl From<AmbientLight> for Light {
+    fn from(light: AmbientLight) -> Self {
+        Light::Ambient(light)
+    }
+}
+
+impl From<DirectionalLight> for Light {
+    fn from(light: DirectionalLight) -> Self {
+        Light::Directional(light)
+    }
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Self {
+        Light::Point(light)
+    }
+}
+
+impl From<SpotLight> for Light {
+    fn from(light: SpotLight) -> Self {
+        Light::Spot(light)
+    }
+}
+
+/// The lights of a frame as laid out in the uniform buffer of the shaders,
+/// see `lighting.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightsUniform {
+    /// The sum of all ambient lights.
+    ambient: [f32; 3],
+    count: u32,
+    lights: [LightUniform; MAX_LIGHTS],
+    /// From world space to the shadow map of each shadow casting light.
+    shadow_view_projs: [[[f32; 4]; 4]; MAX_SHADOW_MAPS],
+    shadow_count: u32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightUniform {
+    position: [f32; 3],
+    kind: u32,
+    /// Normalized.
+    direction: [f32; 3],
+    range: f32,
+    /// Premultiplied with the intensity.
+    color: [f32; 3],
+    inner_cos: f32,
+    outer_cos: f32,
+    /// The layer of the shadow map, or -1 if the light casts no shadows.
+    shadow: i32,
+    _padding: [u32; 2],
+}
+
+const DIRECTIONAL: u32 = 0;
+const POINT: u32 = 1;
+const SPOT: u32 = 2;
+
+impl LightsUniform {
+    /// The shadows of directional lights cover what `camera` sees.
+    pub(crate) fn new(lights: &[Light], camera: &FirstPersonCamera) -> Self {
+        let lights = if lights.is_empty() {
+            &Light::DEFAULT
+        } else {
+            lights
+        };
+        let mut uniform = Self {
+            ambient: [0.0; 3],
+            count: 0,
+            lights: [LightUniform::default(); MAX_LIGHTS],
+            shadow_view_projs: [[[0.0; 4]; 4]; MAX_SHADOW_MAPS],
+            shadow_count: 0,
+            _padding: [0; 3],
+        };
+        let premultiply =
+            |color: [f32; 3], intensity: f32| color.map(|c| c * intensity);
+        let normalize = |v: [f32; 3]| Vector3::from(v).normalize().into();
+        for light in lights {
+            let gpu_light = match *light {
+                Light::Ambient(light) => {
+                    let color = premultiply(light.color, light.intensity);
+                    for (sum, c) in uniform.ambient.iter_mut().zip(color) {
+                        *sum += c;
+                    }
+                    continue;
+                }
+                Light::Directional(light) => LightUniform {
+                    kind: DIRECTIONAL,
+                    direction: normalize(light.direction),
+                    color: premultiply(light.color, light.intensity),
+                    ..Default::default()
+                },
+                Light::Point(light) => LightUniform {
+                    position: light.position,
+                    kind: POINT,
+                    range: light.range,
+                    color: premultiply(light.color, light.intensity),
+                    ..Default::default()
+                },
+                Light::Spot(light) => LightUniform {
+                    position: light.position,
+                    kind: SPOT,
+                    direction: normalize(light.direction),
+                    range: light.range,
+                    color: premultiply(light.color, light.intensity),
+                    inner_cos: light.inner_angle.cos(),
+                    outer_cos: light.outer_angle.cos(),
+                    ..Default::default()
+                },
+            };
+            let Some(slot) = uniform.lights.get_mut(uniform.count as usize)
+            else {
+                continue;
+            };
+            *slot = LightUniform {
+                shadow: -1,
+                ..gpu_light
+            };
+            uniform.count += 1;
+            let shadow_count = uniform.shadow_count as usize;
+            if shadow_count == MAX_SHADOW_MAPS {
+                continue;
+            }
+            if let Some(view_proj) = shadow::view_proj(light, camera) {
+                uniform.shadow_view_projs[shadow_count] = view_proj.into();
+                slot.shadow = shadow_count as i32;
+                uniform.shadow_count += 1;
+            }
+        }
+        uniform
+    }
+
+    /// The view projections of the shadow maps that are used.
+    pub(crate) fn shadow_view_projs(&self) -> &[[[f32; 4]; 4]] {
+        &self.shadow_view_projs[..self.shadow_count as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Translation3;
+
+    use super::*;
+
+    #[test]
+    fn packs_lights() {
+        let ambient = AmbientLight {
+            color: [1.0, 0.5, 0.0],
+            intensity: 0.5,
+        };
+        let point = PointLight {
+            position: [1.0, 2.0, 3.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 2.0,
+            range: 10.0,
+        };
+        let mut lights = vec![Light::from(ambient); 2];
+        lights.extend([Light::from(point); MAX_LIGHTS + 1]);
+        let camera = FirstPersonCamera::default();
+        let uniform = LightsUniform::new(&lights, &camera);
+        assert_eq!(uniform.ambient, [1.0, 0.5, 0.0]);
+        assert_eq!(uniform.count as usize, MAX_LIGHTS);
+        assert_eq!(uniform.lights[0].kind, POINT);
+        assert_eq!(uniform.lights[0].color, [2.0, 2.0, 2.0]);
+        assert_eq!(uniform.lights[0].shadow, -1);
+        assert!(uniform.shadow_view_projs().is_empty());
+
+        let uniform = LightsUniform::new(&[], &camera);
+        assert_eq!(uniform.count, 1);
+        assert_eq!(uniform.lights[0].kind, DIRECTIONAL);
+        assert_eq!(uniform.lights[0].shadow, 0);
+
+        let sun = Light::DEFAULT[1];
+        let uniform = LightsUniform::new(&[sun; MAX_SHADOW_MAPS + 1], &camera);
+        assert_eq!(uniform.shadow_view_projs().len(), MAX_SHADOW_MAPS);
+        assert_eq!(uniform.lights[MAX_SHADOW_MAPS].shadow, -1);
+    }
+
+    #[test]
+    fn transforms_positions_and_directions() {
+        let spot = Light::from(SpotLight {
+            position: [1.0, 0.0, 0.0],
+            direction: [0.0, -1.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            range: 1.0,
+            inner_angle: 0.1,
+            outer_angle: 0.2,
+        });
+        let moved = spot.transform(&Translation3::new(0.0, 5.0, 0.0).into());
+        let Light::Spot(moved) = moved else {
+            panic!("{moved:?}")
+        };
+        assert_eq!(moved.position, [1.0, 5.0, 0.0]);
+        assert_eq!(moved.direction, [0.0, -1.0, 0.0]);
+    }
+}
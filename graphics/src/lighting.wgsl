@@ -0,0 +1,178 @@
+// Blinn-Phong and physically based shading shared by the shaders, the
+// lights are filled in by `LightsUniform` in `light.rs`.
+
+const MAX_LIGHTS: u32 = 16u;
+const MAX_SHADOW_MAPS: u32 = 4u;
+
+const DIRECTIONAL: u32 = 0u;
+const POINT: u32 = 1u;
+const SPOT: u32 = 2u;
+
+const PI: f32 = 3.14159265;
+
+// `shade` ignores the metallic and roughness of materials
+const SHININESS: f32 = 32.0;
+const SPECULAR: f32 = 0.3;
+
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    inner_cos: f32,
+    outer_cos: f32,
+    // the layer of the shadow map, -1 without shadows
+    shadow: i32,
+}
+
+struct Lights {
+    ambient: vec3<f32>,
+    count: u32,
+    lights: array<Light, MAX_LIGHTS>,
+    shadow_view_projs: array<mat4x4<f32>, MAX_SHADOW_MAPS>,
+    shadow_count: u32,
+}
+
+@group(0) @binding(1)
+var<uniform> lights: Lights;
+
+@group(0) @binding(2)
+var shadow_maps: texture_depth_2d_array;
+
+@group(0) @binding(3)
+var shadow_sampler: sampler_comparison;
+
+// Fades the light out with the square of the distance and smoothly to
+// zero at `range`.
+fn attenuation(distance: f32, range: f32) -> f32 {
+    let falloff = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    return falloff * falloff / max(distance * distance, 0.0001);
+}
+
+// How much of the light with the shadow map `layer` reaches `position`,
+// averaged over 3x3 texels to soften the edges.
+fn shadow(layer: i32, position: vec3<f32>) -> f32 {
+    let clip = lights.shadow_view_projs[layer] * vec4(position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    // outside of the shadow map nothing is in shadow
+    if any(abs(ndc.xy) > vec2(1.0)) || ndc.z > 1.0 || clip.w <= 0.0 {
+        return 1.0;
+    }
+    let uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_maps));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            lit += textureSampleCompareLevel(
+                shadow_maps,
+                shadow_sampler,
+                uv + vec2(f32(x), f32(y)) * texel,
+                layer,
+                ndc.z,
+            );
+        }
+    }
+    return lit / 9.0;
+}
+
+// The light of one light arriving at a surface, zero if the surface faces
+// away from it.
+struct Incoming {
+    // normalized, from the surface towards the light
+    direction: vec3<f32>,
+    radiance: vec3<f32>,
+}
+
+fn incoming(
+    light: Light,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    receive_shadows: bool,
+) -> Incoming {
+    var light_dir = -light.direction;
+    var strength = 1.0;
+    if light.kind != DIRECTIONAL {
+        let to_light = light.position - position;
+        let distance = length(to_light);
+        light_dir = to_light / max(distance, 0.0001);
+        strength = attenuation(distance, light.range);
+    }
+    if light.kind == SPOT {
+        let cos_angle = dot(-light_dir, light.direction);
+        strength *= smoothstep(light.outer_cos, light.inner_cos, cos_angle);
+    }
+    if dot(normal, light_dir) <= 0.0 || strength <= 0.0 {
+        return Incoming(light_dir, vec3(0.0));
+    }
+    if receive_shadows && light.shadow >= 0 {
+        strength *= shadow(light.shadow, position);
+    }
+    return Incoming(light_dir, strength * light.color);
+}
+
+// The light leaving `position` towards the viewer, `normal` and `view_dir`
+// have to be normalized. Shadows only fall on it if `receive_shadows` is
+// set.
+fn shade(
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    color: vec3<f32>,
+    receive_shadows: bool,
+) -> vec3<f32> {
+    var diffuse = lights.ambient;
+    var specular = vec3(0.0);
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i++) {
+        let light = incoming(lights.lights[i], position, normal, receive_shadows);
+        let lambert = max(dot(normal, light.direction), 0.0);
+        let half_dir = normalize(light.direction + view_dir);
+        let highlight = pow(max(dot(normal, half_dir), 0.0), SHININESS);
+        diffuse += lambert * light.radiance;
+        specular += SPECULAR * highlight * light.radiance;
+    }
+    return diffuse * color + specular;
+}
+
+// Like `shade`, with the Cook-Torrance BRDF: the GGX normal distribution,
+// the Smith-Schlick geometry term and Schlick's Fresnel approximation.
+// Ambient light comes from all directions equally, so it is reflected with
+// the Fresnel term head on.
+fn shade_pbr(
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    base_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    receive_shadows: bool,
+) -> vec3<f32> {
+    // dielectrics reflect about 4% head on, metals tint the reflection
+    let f0 = mix(vec3(0.04), base_color, metallic);
+    let diffuse_color = base_color * (1.0 - metallic);
+    let alpha = max(roughness * roughness, 0.002);
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+    var color = lights.ambient * (diffuse_color + f0);
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i++) {
+        let light = incoming(lights.lights[i], position, normal, receive_shadows);
+        let n_dot_l = max(dot(normal, light.direction), 0.0);
+        let half_dir = normalize(light.direction + view_dir);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+        let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+        let d_denom = n_dot_h * n_dot_h * (alpha * alpha - 1.0) + 1.0;
+        let distribution = alpha * alpha / (PI * d_denom * d_denom);
+        let geometry = n_dot_v / (n_dot_v * (1.0 - k) + k)
+            * n_dot_l / (n_dot_l * (1.0 - k) + k);
+        let fresnel = f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+
+        let specular = distribution * geometry * fresnel
+            / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+        let diffuse = (1.0 - fresnel) * diffuse_color / PI;
+        // times pi, so lights are as bright on a white surface facing them
+        // as in `shade`
+        color += (diffuse + specular) * PI * light.radiance * n_dot_l;
+    }
+    return color;
+}
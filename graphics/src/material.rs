@@ -0,0 +1,67 @@
+//! Materials of drawings, see [`Drawing::material`].
+//!
+//! The default shader only uses the base color, emissive and alpha of a
+//! material, [`Pbr`] also its metallic and roughness.
+//!
+//! [`Drawing::material`]: crate::canvas::drawing::Drawing::material
+
+use std::{borrow::Cow, path::PathBuf};
+
+use crate::{
+    mesh::PNUVertex,
+    shader::{ShaderProvider, BUILTIN_DIR},
+};
+
+/// How a surface reflects and emits light, in the metallic-roughness model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    /// Multiplied with the texture of the drawing.
+    pub base_color: [f32; 3],
+    /// From 0 for dielectrics like plastic to 1 for metals.
+    pub metallic: f32,
+    /// From 0 for mirror-like to 1 for completely diffuse reflections.
+    pub roughness: f32,
+    /// Light given off by the surface itself, added to the reflected light.
+    pub emissive: [f32; 3],
+    /// The opacity, written to the alpha channel of the target.
+    pub alpha: f32,
+}
+
+impl Material {
+    /// An opaque white plastic.
+    pub const DEFAULT: Material = Material {
+        base_color: [1.0, 1.0, 1.0],
+        metallic: 0.0,
+        roughness: 0.5,
+        emissive: [0.0, 0.0, 0.0],
+        alpha: 1.0,
+    };
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Shades meshes with the Cook-Torrance BRDF instead of the Blinn-Phong
+/// shading of the default shader, select it with [`Drawing::shader`].
+///
+/// [`Drawing::shader`]: crate::canvas::drawing::Drawing::shader
+#[derive(Debug, Clone, Copy)]
+pub struct Pbr;
+
+impl ShaderProvider for Pbr {
+    type Vertex = PNUVertex;
+    type Uniforms = ();
+
+    fn source(&self) -> Cow<'static, str> {
+        Cow::Borrowed(include_str!("pbr_shader.wgsl"))
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(BUILTIN_DIR).join("pbr_shader.wgsl"))
+    }
+
+    fn uniforms(&self) {}
+}
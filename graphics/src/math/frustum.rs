@@ -0,0 +1,133 @@
+use nalgebra::{Matrix4, Vector4};
+
+use super::Float;
+
+/// Where a box lies relative to a [`Frustum`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+/// The six planes of a view frustum, facing inwards, in the order left,
+/// right, bottom, top, near, far.
+///
+/// Plane `[a, b, c, d]` contains the points with `a*x + b*y + c*z + d >= 0`,
+/// `(a, b, c)` has unit length so the value is the distance to the plane.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    planes: [[f64; 4]; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix, such as
+    /// [`FirstPersonCamera::view_proj`].
+    ///
+    /// The near plane is where wgpu clips, at a clip space depth of 0.
+    ///
+    /// [`FirstPersonCamera::view_proj`]: crate::camera::FirstPersonCamera::view_proj
+    pub fn from_view_proj(view_proj: &Matrix4<Float>) -> Self {
+        let m = view_proj.cast::<f64>();
+        let row = |i| m.row(i).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ];
+        Self {
+            planes: planes.map(normalize),
+        }
+    }
+
+    pub fn planes(&self) -> &[[f64; 4]; 6] {
+        &self.planes
+    }
+
+    /// The same frustum in the local space of `transform`, so boxes in that
+    /// space can be tested without transforming them.
+    pub fn transformed(&self, transform: &Matrix4<Float>) -> Self {
+        let transpose = transform.cast::<f64>().transpose();
+        Self {
+            planes: self
+                .planes
+                .map(|plane| normalize(transpose * Vector4::from(plane))),
+        }
+    }
+
+    /// Classifies the box from `min` to `max`.
+    ///
+    /// Conservative: a box near a corner of the frustum may be reported as
+    /// intersecting although it is outside, but never the other way around.
+    pub fn classify(&self, min: [f64; 3], max: [f64; 3]) -> Containment {
+        let mut containment = Containment::Inside;
+        for [a, b, c, d] in self.planes {
+            // the corners furthest along and against the plane normal
+            let corner = |positive: bool| {
+                let pick = |n: f64, i: usize| {
+                    if (n >= 0.0) == positive {
+                        max[i]
+                    } else {
+                        min[i]
+                    }
+                };
+                a * pick(a, 0) + b * pick(b, 1) + c * pick(c, 2) + d
+            };
+            if corner(true) < 0.0 {
+                return Containment::Outside;
+            }
+            if corner(false) < 0.0 {
+                containment = Containment::Intersecting;
+            }
+        }
+        containment
+    }
+
+    pub fn contains_point(&self, point: [f64; 3]) -> bool {
+        self.classify(point, point) != Containment::Outside
+    }
+}
+
+fn normalize(plane: Vector4<f64>) -> [f64; 4] {
+    let length = plane.xyz().norm();
+    if length > 0.0 {
+        (plane / length).into()
+    } else {
+        plane.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::FirstPersonCamera;
+
+    #[test]
+    fn camera_frustum() {
+        // the default camera sits at the origin looking along +z
+        let frustum = FirstPersonCamera::default().frustum();
+        let cube = |center: [f64; 3], half: f64| {
+            frustum.classify(center.map(|c| c - half), center.map(|c| c + half))
+        };
+        assert_eq!(cube([0.0, 0.0, 10.0], 1.0), Containment::Inside);
+        assert_eq!(cube([0.0, 0.0, -10.0], 1.0), Containment::Outside);
+        assert_eq!(cube([100.0, 0.0, 10.0], 1.0), Containment::Outside);
+        assert_eq!(cube([0.0, 0.0, 2000.0], 1.0), Containment::Outside);
+        assert_eq!(cube([0.0, 0.0, 0.0], 1.0), Containment::Intersecting);
+        assert!(frustum.contains_point([0.0, 1.0, 5.0]));
+        assert!(!frustum.contains_point([0.0, 5.0, 1.0]));
+    }
+
+    #[test]
+    fn transformed_frustum() {
+        let frustum = FirstPersonCamera::default().frustum();
+        let translation = Matrix4::new_translation(&[0.0, 0.0, 20.0].into());
+        let local = frustum.transformed(&translation);
+        // local (0, 0, -15) is world (0, 0, 5)
+        assert!(local.contains_point([0.0, 0.0, -15.0]));
+        assert!(!local.contains_point([0.0, 0.0, -25.0]));
+    }
+}
@@ -1,5 +1,7 @@
+mod frustum;
 mod transform;
 
+pub use frustum::{Containment, Frustum};
 pub use transform::Transform;
 
 type Float = f32;
@@ -11,6 +11,8 @@ pub struct MeshManager {
     static_mesh_ids: HashMap<TypeId, MeshId>,
     static_meshes: Vec<MeshBuffers>,
     dynamic_meshes: Vec<MeshBuffers>,
+    /// The layout of every vertex type a mesh was loaded with.
+    vertex_layouts: HashMap<TypeId, VertexLayout>,
     device: Arc<Device>,
 }
 
@@ -20,6 +22,7 @@ impl MeshManager {
             static_mesh_ids: HashMap::new(),
             static_meshes: Vec::new(),
             dynamic_meshes: Vec::new(),
+            vertex_layouts: HashMap::new(),
             device,
         }
     }
@@ -32,9 +35,31 @@ impl MeshManager {
         }
     }
 
+    /// The layout of the vertices of `id`.
+    pub(crate) fn vertex_layout(&self, id: MeshId) -> &VertexLayout {
+        &self.vertex_layouts[&id.vtx_type_id]
+    }
+
     pub fn clear_dynamic(&mut self) {
         self.dynamic_meshes.clear();
     }
+
+    fn load<V: Vertex>(&mut self, mesh: Mesh<V>) -> MeshBuffers {
+        self.vertex_layouts
+            .entry(TypeId::of::<V>())
+            .or_insert(VertexLayout {
+                buffer: V::BUFFER_LAYOUT,
+                topology: V::PRIMITIVE_TOPOLOGY,
+            });
+        load_mesh(&self.device, mesh)
+    }
+}
+
+/// What pipelines need to know about a [`Vertex`] type, without the type.
+#[derive(Clone, Debug)]
+pub(crate) struct VertexLayout {
+    pub(crate) buffer: VertexBufferLayout<'static>,
+    pub(crate) topology: PrimitiveTopology,
 }
 
 fn load_mesh(device: &Device, mesh: Mesh<impl Vertex>) -> MeshBuffers {
@@ -81,6 +106,27 @@ impl Vertex for PNVertex {
         PrimitiveTopology::TriangleList;
 }
 
+/// A [`PNVertex`] with texture coordinates, `(0, 0)` is the top left of
+/// the texture.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Debug)]
+pub struct PNUVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+impl Vertex for PNUVertex {
+    const ATTRIBUTES: &'static [VertexAttribute] = &wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x2,
+    ];
+
+    const PRIMITIVE_TOPOLOGY: PrimitiveTopology =
+        PrimitiveTopology::TriangleList;
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable, Debug)]
 pub struct PDVertex {
@@ -117,20 +163,21 @@ impl<Provider: MeshProvider<Kind = Static> + 'static + Sized> MeshKind<Provider>
     for Static
 {
     fn get_or_insert(manager: &mut MeshManager, provider: Provider) -> MeshId {
-        *manager
-            .static_mesh_ids
-            .entry(TypeId::of::<Provider>())
-            .or_insert_with(|| {
-                let index = manager.static_meshes.len();
-                manager
-                    .static_meshes
-                    .push(load_mesh(&manager.device, provider.create_mesh()));
-                MeshId {
-                    dynamic: false,
-                    index,
-                    vtx_type_id: TypeId::of::<Provider::Vertex>(),
-                }
-            })
+        if let Some(&id) =
+            manager.static_mesh_ids.get(&TypeId::of::<Provider>())
+        {
+            return id;
+        }
+        let index = manager.static_meshes.len();
+        let buffers = manager.load(provider.create_mesh());
+        manager.static_meshes.push(buffers);
+        let id = MeshId {
+            dynamic: false,
+            index,
+            vtx_type_id: TypeId::of::<Provider::Vertex>(),
+        };
+        manager.static_mesh_ids.insert(TypeId::of::<Provider>(), id);
+        id
     }
 }
 pub struct Dynamic;
@@ -138,9 +185,8 @@ pub struct Dynamic;
 impl<Provider: MeshProvider<Kind = Dynamic>> MeshKind<Provider> for Dynamic {
     fn get_or_insert(manager: &mut MeshManager, provider: Provider) -> MeshId {
         let index = manager.dynamic_meshes.len();
-        manager
-            .dynamic_meshes
-            .push(load_mesh(&manager.device, provider.create_mesh()));
+        let buffers = manager.load(provider.create_mesh());
+        manager.dynamic_meshes.push(buffers);
         MeshId {
             dynamic: true,
             index,
@@ -1,23 +1,3 @@
-struct Camera {
-    view_proj: mat4x4<f32>,
-    position: vec3<f32>,
-}
-
-@group(0) @binding(0)
-var<uniform> camera: Camera;
-
-struct InstanceIn {
-    @location(4) model_0: vec4<f32>,
-    @location(5) model_1: vec4<f32>,
-    @location(6) model_2: vec4<f32>,
-    @location(7) model_3: vec4<f32>,
-    @location(8) model_inv_0: vec4<f32>,
-    @location(9) model_inv_1: vec4<f32>,
-    @location(10) model_inv_2: vec4<f32>,
-    @location(11) model_inv_3: vec4<f32>,
-    @location(12) color: vec3<f32>,
-}
-
 struct VertexIn {
     @location(0) position: vec3<f32>,
     @location(1) direction: vec3<f32>,
@@ -28,23 +8,16 @@ struct VertexOut {
     @location(0) normal: vec3<f32>,
     @location(1) view_dir: vec3<f32>,
     @location(2) color: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) emissive: vec3<f32>,
+    @location(5) alpha: f32,
 }
 
 
 @vertex
 fn vs_main(vtx_in: VertexIn, instance_in: InstanceIn) -> VertexOut {
-    let model = mat4x4<f32>(
-        instance_in.model_0,
-        instance_in.model_1,
-        instance_in.model_2,
-        instance_in.model_3,
-    );
-    let model_inv = mat4x4<f32>(
-        instance_in.model_inv_0,
-        instance_in.model_inv_1,
-        instance_in.model_inv_2,
-        instance_in.model_inv_3,
-    );
+    let model = model_matrix(instance_in);
+    let model_inv = inverse_model_matrix(instance_in);
 
     let world_position = model * vec4(vtx_in.position, 1.0);
     let position = camera.view_proj * world_position;
@@ -58,18 +31,29 @@ fn vs_main(vtx_in: VertexIn, instance_in: InstanceIn) -> VertexOut {
         dot(direction, direction) * view_dir
         - dot(direction, view_dir) * direction
     );
-    return VertexOut(position, normal, view_dir, instance_in.color);
+    return VertexOut(
+        position,
+        normal,
+        view_dir,
+        instance_in.color,
+        world_position.xyz / world_position.w,
+        instance_in.emissive,
+        instance_in.material.z,
+    );
 }
 
 @fragment
 fn fs_main(frag_in: VertexOut) -> @location(0) vec4<f32> {
-    let light_dir = normalize(vec3<f32>(1.0, 1.0, 1.0));
     let normal = normalize(frag_in.normal);
     let view_dir = normalize(frag_in.view_dir);
 
-    let diffuse = max(dot(normal, light_dir), 0.0);
-    let ambient = max(dot(normal, view_dir), 0.0);
-
-    let brightness = 0.7 * diffuse + 0.2 * ambient + 0.1;
-    return vec4(brightness * frag_in.color, 1.0);
+    let color = shade(
+        frag_in.world_position,
+        normal,
+        view_dir,
+        frag_in.color,
+        // too thin to get a sensible shadow
+        false,
+    );
+    return vec4(color + frag_in.emissive, frag_in.alpha);
 }
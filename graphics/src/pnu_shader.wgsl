@@ -0,0 +1,57 @@
+struct VertexIn {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+}
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) view_dir: vec3<f32>,
+    @location(2) color: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) receive_shadows: f32,
+    @location(5) emissive: vec3<f32>,
+    @location(6) alpha: f32,
+    @location(7) uv: vec2<f32>,
+}
+
+
+@vertex
+fn vs_main(vtx_in: VertexIn, instance_in: InstanceIn) -> VertexOut {
+    let model = model_matrix(instance_in);
+    let model_inv = inverse_model_matrix(instance_in);
+
+    let world_position = model * vec4(vtx_in.position, 1.0);
+    let view_dir = camera.position * world_position.w - world_position.xyz;
+    let position = camera.view_proj * world_position;
+    let normal = (vec4(vtx_in.normal, 1.0) * model_inv).xyz;
+    return VertexOut(
+        position,
+        normal,
+        view_dir,
+        instance_in.color,
+        world_position.xyz / world_position.w,
+        instance_in.receive_shadows,
+        instance_in.emissive,
+        instance_in.material.z,
+        vtx_in.uv,
+    );
+}
+
+@fragment
+fn fs_main(frag_in: VertexOut) -> @location(0) vec4<f32> {
+    var normal = normalize(frag_in.normal);
+    let view_dir = normalize(frag_in.view_dir);
+    normal *= sign(dot(normal, view_dir));
+    let albedo = textureSample(color_texture, color_sampler, frag_in.uv);
+
+    let color = shade(
+        frag_in.world_position,
+        normal,
+        view_dir,
+        frag_in.color * albedo.rgb,
+        frag_in.receive_shadows > 0.5,
+    );
+    return vec4(color + frag_in.emissive, frag_in.alpha * albedo.a);
+}
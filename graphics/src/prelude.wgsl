@@ -0,0 +1,52 @@
+// Declarations shared by every shader, custom ones included.
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+    position: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// The texture of a drawing, a white pixel if it has none.
+@group(2) @binding(0)
+var color_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var color_sampler: sampler;
+
+// The per instance data of a drawing, filled in by `InstanceData` in
+// `context.rs`.
+struct InstanceIn {
+    @location(4) model_0: vec4<f32>,
+    @location(5) model_1: vec4<f32>,
+    @location(6) model_2: vec4<f32>,
+    @location(7) model_3: vec4<f32>,
+    @location(8) model_inv_0: vec4<f32>,
+    @location(9) model_inv_1: vec4<f32>,
+    @location(10) model_inv_2: vec4<f32>,
+    @location(11) model_inv_3: vec4<f32>,
+    // the base color of the material
+    @location(12) color: vec3<f32>,
+    @location(13) receive_shadows: f32,
+    // metallic, roughness and alpha of the material
+    @location(14) material: vec4<f32>,
+    @location(15) emissive: vec3<f32>,
+}
+
+fn model_matrix(instance: InstanceIn) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_0,
+        instance.model_1,
+        instance.model_2,
+        instance.model_3,
+    );
+}
+
+fn inverse_model_matrix(instance: InstanceIn) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_inv_0,
+        instance.model_inv_1,
+        instance.model_inv_2,
+        instance.model_inv_3,
+    );
+}
@@ -0,0 +1,674 @@
+//! Custom shaders for drawings, see [`Drawing::shader`].
+//!
+//! Shaders are registered like static meshes: the first drawing that uses a
+//! [`ShaderProvider`] compiles it, later ones reuse it.
+//!
+//! During development the shader files can be watched for changes with
+//! [`Context::watch_shaders`], so shaders are recompiled without a rebuild.
+//!
+//! [`Drawing::shader`]: crate::canvas::drawing::Drawing::shader
+//! [`Context::watch_shaders`]: crate::context::Context::watch_shaders
+
+use std::{
+    any::TypeId,
+    borrow::Cow,
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use bytemuck::Pod;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, BufferDescriptor, BufferUsages, Device,
+    Queue, ShaderModule,
+};
+
+use crate::mesh::{PDVertex, PNUVertex, PNVertex, Vertex};
+
+/// Describes a shader, comparable to a [`MeshProvider`] of a static mesh.
+///
+/// The source is WGSL with a `vs_main` and an `fs_main` entry point. It is
+/// appended to `prelude.wgsl` and `lighting.wgsl`, so it can use `camera`,
+/// `InstanceIn` with `model_matrix` and `inverse_model_matrix`,
+/// `color_texture` and `color_sampler` for the texture of the drawing, and
+/// `shade` or `shade_pbr` for the lights of the frame. The vertex
+/// attributes of [`Self::Vertex`] start at location 0.
+///
+/// [`MeshProvider`]: crate::mesh::MeshProvider
+pub trait ShaderProvider: 'static {
+    /// The vertex type of the meshes the shader can draw.
+    type Vertex: Vertex;
+
+    /// Bound at `@group(1) @binding(0)` unless it is zero sized, its layout
+    /// has to follow the WGSL rules for uniform buffers.
+    type Uniforms: Pod;
+
+    /// Only called when the shader is used for the first time.
+    fn source(&self) -> Cow<'static, str>;
+
+    /// The file the source is read from when shaders are watched, e.g. the
+    /// one that [`Self::source`] includes.
+    fn path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Called for every drawing using the shader, the uniforms of the last
+    /// one are used for the whole frame.
+    fn uniforms(&self) -> Self::Uniforms;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShaderId(usize);
+
+impl ShaderId {
+    /// The built-in shader, for meshes of [`PNVertex`], [`PNUVertex`] and
+    /// [`PDVertex`].
+    pub const DEFAULT: ShaderId = ShaderId(0);
+}
+
+/// A shader that failed to compile or was used for a mesh of another
+/// vertex type, with the file and line of the error if it is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderError {
+    pub location: Option<(String, u32, u32)>,
+    pub message: String,
+}
+
+impl Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some((file, line, column)) = &self.location {
+            write!(f, "{file}:{line}:{column}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ShaderError {}
+
+/// The directory of the built-in shaders, watched instead of the copies
+/// compiled into the crate.
+pub(crate) const BUILTIN_DIR: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+pub struct ShaderManager {
+    shader_ids: HashMap<TypeId, ShaderId>,
+    shaders: Vec<ShaderEntry>,
+    uniform_layout: BindGroupLayout,
+    device: Arc<Device>,
+    watching: bool,
+}
+
+pub(crate) struct ShaderEntry {
+    /// The module for each vertex type, only the default shader has more
+    /// than one.
+    pub(crate) modules: HashMap<TypeId, Module>,
+    pub(crate) uniforms: Option<Uniforms>,
+    /// Set when the shader was used for a mesh of another vertex type,
+    /// those drawings are skipped.
+    vertex_mismatch: Option<ShaderError>,
+}
+
+pub(crate) struct Module {
+    /// `None` until the source compiles, drawings with the shader are
+    /// skipped until then.
+    pub(crate) module: Option<ShaderModule>,
+    /// Why the current source does not compile.
+    error: Option<ShaderError>,
+    /// The files the source is joined from.
+    files: Vec<SourceFile>,
+}
+
+struct SourceFile {
+    /// Shown in errors.
+    name: String,
+    source: Cow<'static, str>,
+    /// Where the file is watched.
+    path: Option<PathBuf>,
+    /// When the watched file was last read.
+    modified: Option<SystemTime>,
+}
+
+impl SourceFile {
+    fn builtin(name: &str, source: &'static str) -> Self {
+        Self {
+            name: name.to_string(),
+            source: Cow::Borrowed(source),
+            path: Some(Path::new(BUILTIN_DIR).join(name)),
+            modified: None,
+        }
+    }
+}
+
+pub(crate) struct Uniforms {
+    buffer: Buffer,
+    pub(crate) bind_group: BindGroup,
+    /// Written to the buffer before the next frame.
+    pending: Option<Vec<u8>>,
+}
+
+impl ShaderManager {
+    pub fn new(device: Arc<Device>) -> Self {
+        let uniform_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let default = ShaderEntry {
+            modules: HashMap::from([
+                (
+                    TypeId::of::<PNVertex>(),
+                    Module::new(
+                        &device,
+                        SourceFile::builtin(
+                            "pn_shader.wgsl",
+                            include_str!("pn_shader.wgsl"),
+                        ),
+                    ),
+                ),
+                (
+                    TypeId::of::<PNUVertex>(),
+                    Module::new(
+                        &device,
+                        SourceFile::builtin(
+                            "pnu_shader.wgsl",
+                            include_str!("pnu_shader.wgsl"),
+                        ),
+                    ),
+                ),
+                (
+                    TypeId::of::<PDVertex>(),
+                    Module::new(
+                        &device,
+                        SourceFile::builtin(
+                            "pd_shader.wgsl",
+                            include_str!("pd_shader.wgsl"),
+                        ),
+                    ),
+                ),
+            ]),
+            uniforms: None,
+            vertex_mismatch: None,
+        };
+        Self {
+            shader_ids: HashMap::new(),
+            shaders: vec![default],
+            uniform_layout,
+            device,
+            watching: false,
+        }
+    }
+
+    /// Recompiles shaders when their files change, see
+    /// [`Context::watch_shaders`](crate::context::Context::watch_shaders).
+    pub fn watch(&mut self) {
+        self.watching = true;
+    }
+
+    pub fn get_or_insert<S: ShaderProvider>(&mut self, shader: S) -> ShaderId {
+        let id =
+            *self.shader_ids.entry(TypeId::of::<S>()).or_insert_with(|| {
+                let name = shader.path().map_or_else(
+                    || std::any::type_name::<S>().to_string(),
+                    |path| path.display().to_string(),
+                );
+                let module = Module::new(
+                    &self.device,
+                    SourceFile {
+                        name,
+                        source: shader.source(),
+                        path: shader.path(),
+                        modified: None,
+                    },
+                );
+                let uniforms = (size_of::<S::Uniforms>() != 0).then(|| {
+                    create_uniforms(
+                        &self.device,
+                        &self.uniform_layout,
+                        size_of::<S::Uniforms>(),
+                    )
+                });
+                self.shaders.push(ShaderEntry {
+                    modules: HashMap::from([(
+                        TypeId::of::<S::Vertex>(),
+                        module,
+                    )]),
+                    uniforms,
+                    vertex_mismatch: None,
+                });
+                ShaderId(self.shaders.len() - 1)
+            });
+        if let Some(uniforms) = &mut self.shaders[id.0].uniforms {
+            uniforms.pending =
+                Some(bytemuck::bytes_of(&shader.uniforms()).to_vec());
+        }
+        id
+    }
+
+    pub(crate) fn get_by_id(&self, id: ShaderId) -> &ShaderEntry {
+        &self.shaders[id.0]
+    }
+
+    /// The layout of the uniforms of custom shaders, bind group 1.
+    pub(crate) fn uniform_layout(&self) -> &BindGroupLayout {
+        &self.uniform_layout
+    }
+
+    /// Reports that the shader `id` of `S` was used for a mesh of another
+    /// vertex type than `S::Vertex`, see [`Self::errors`].
+    pub(crate) fn vertex_mismatch<S: ShaderProvider>(&mut self, id: ShaderId) {
+        self.shaders[id.0]
+            .vertex_mismatch
+            .get_or_insert_with(|| ShaderError {
+                location: None,
+                message: format!(
+                    "{} only draws meshes of {}",
+                    std::any::type_name::<S>(),
+                    std::any::type_name::<S::Vertex>()
+                ),
+            });
+    }
+
+    /// Whether `id` has a compiled module for the vertex type `vertex`.
+    /// The default shader has a module for every vertex type it supports,
+    /// custom shaders skip meshes of other vertex types.
+    pub(crate) fn is_compiled(&self, id: ShaderId, vertex: TypeId) -> bool {
+        match self.shaders[id.0].modules.get(&vertex) {
+            Some(module) => module.module.is_some(),
+            None => id == ShaderId::DEFAULT,
+        }
+    }
+
+    /// Why the shaders that do not compile fail, and the shaders used for
+    /// meshes of another vertex type, see
+    /// [`Context::shader_errors`](crate::context::Context::shader_errors).
+    pub fn errors(&self) -> impl Iterator<Item = &ShaderError> {
+        self.shaders.iter().flat_map(|shader| {
+            let modules = shader.modules.values();
+            modules
+                .filter_map(|module| module.error.as_ref())
+                .chain(&shader.vertex_mismatch)
+        })
+    }
+
+    /// Writes the uniforms that changed since the last frame.
+    pub(crate) fn upload_uniforms(&mut self, queue: &Queue) {
+        for uniforms in self.shaders.iter_mut().flat_map(|s| &mut s.uniforms) {
+            if let Some(data) = uniforms.pending.take() {
+                queue.write_buffer(&uniforms.buffer, 0, &data);
+            }
+        }
+    }
+
+    /// Recompiles the shaders whose files changed since the last call if
+    /// they are watched, and returns the ones that compiled. A shader that
+    /// fails to compile keeps its last module and records the error.
+    pub(crate) fn reload(&mut self) -> Vec<ShaderId> {
+        if !self.watching {
+            return Vec::new();
+        }
+        let mut reloaded = Vec::new();
+        for (id, shader) in self.shaders.iter_mut().enumerate() {
+            for module in shader.modules.values_mut() {
+                if !module.read_changes() {
+                    continue;
+                }
+                match compile(&self.device, &module.files) {
+                    Ok(compiled) => {
+                        module.module = Some(compiled);
+                        module.error = None;
+                        reloaded.push(ShaderId(id));
+                    }
+                    Err(e) => module.error = Some(e),
+                }
+            }
+        }
+        reloaded
+    }
+}
+
+impl Module {
+    /// The shared declarations followed by `file`, without a module if
+    /// they do not compile.
+    fn new(device: &Device, file: SourceFile) -> Self {
+        let files = vec![
+            SourceFile::builtin("prelude.wgsl", include_str!("prelude.wgsl")),
+            SourceFile::builtin("lighting.wgsl", include_str!("lighting.wgsl")),
+            file,
+        ];
+        let (module, error) = match compile(device, &files) {
+            Ok(module) => (Some(module), None),
+            Err(e) => (None, Some(e)),
+        };
+        Self {
+            module,
+            error,
+            files,
+        }
+    }
+
+    /// Reads the watched files that were modified, returns whether any
+    /// source changed. Files that cannot be read are skipped.
+    fn read_changes(&mut self) -> bool {
+        let mut changed = false;
+        for file in &mut self.files {
+            let Some(path) = &file.path else {
+                continue;
+            };
+            let Ok(modified) = fs::metadata(path).and_then(|m| m.modified())
+            else {
+                continue;
+            };
+            if file.modified == Some(modified) {
+                continue;
+            }
+            file.modified = Some(modified);
+            let Ok(source) = fs::read_to_string(path) else {
+                continue;
+            };
+            if source != file.source {
+                file.source = Cow::Owned(source);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Compiles the joined `files`.
+fn compile(
+    device: &Device,
+    files: &[SourceFile],
+) -> Result<ShaderModule, ShaderError> {
+    let source = validate(files)?;
+    Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+    }))
+}
+
+/// Joins `files` and validates them with naga, so errors point into the
+/// file they are in instead of failing in wgpu.
+fn validate(files: &[SourceFile]) -> Result<String, ShaderError> {
+    let source = files
+        .iter()
+        .map(|file| &*file.source)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let error = |location: Option<naga::SourceLocation>, message| ShaderError {
+        location: location.map(|l| locate(files, l)),
+        message,
+    };
+    let module = naga::front::wgsl::parse_str(&source)
+        .map_err(|e| error(e.location(&source), e.message().to_string()))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::default(),
+    )
+    .validate(&module)
+    .map_err(|e| {
+        // the outermost error only names the function that is invalid
+        let mut message = e.as_inner().to_string();
+        let mut cause = e.as_inner().source();
+        while let Some(inner) = cause {
+            message += &format!(": {inner}");
+            cause = inner.source();
+        }
+        // the last span is the most specific one
+        let location = e.spans().last().map(|(span, _)| span.location(&source));
+        error(location, message)
+    })?;
+    Ok(source)
+}
+
+/// The file, line and column of `location` in the joined `files`.
+fn locate(
+    files: &[SourceFile],
+    location: naga::SourceLocation,
+) -> (String, u32, u32) {
+    let (last, files) = files.split_last().expect("shaders have files");
+    let mut line = location.line_number;
+    for file in files {
+        let lines = file.source.matches('\n').count() as u32 + 1;
+        if line <= lines {
+            return (file.name.clone(), line, location.line_position);
+        }
+        line -= lines;
+    }
+    (last.name.clone(), line, location.line_position)
+}
+
+fn create_uniforms(
+    device: &Device,
+    layout: &BindGroupLayout,
+    size: usize,
+) -> Uniforms {
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: None,
+        // uniform buffers are read in blocks of 16 bytes
+        size: size.next_multiple_of(16) as u64,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+    Uniforms {
+        buffer,
+        bind_group,
+        pending: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geometry::{Box, BoxLines},
+        headless::HeadlessContext,
+        math::Transform,
+    };
+
+    const FLAT_SHADER: &str = "
+        @group(1) @binding(0)
+        var<uniform> flat_color: vec4<f32>;
+
+        @vertex
+        fn vs_main(
+            @location(0) position: vec3<f32>,
+            instance_in: InstanceIn,
+        ) -> @builtin(position) vec4<f32> {
+            let model = model_matrix(instance_in);
+            return camera.view_proj * model * vec4(position, 1.0);
+        }
+
+        @fragment
+        fn fs_main() -> @location(0) vec4<f32> {
+            return flat_color;
+        }
+    ";
+
+    /// Ignores the lights and fills the mesh with a uniform color.
+    struct Flat([f32; 4]);
+
+    impl ShaderProvider for Flat {
+        type Vertex = PNUVertex;
+        type Uniforms = [f32; 4];
+
+        fn source(&self) -> Cow<'static, str> {
+            Cow::Borrowed(FLAT_SHADER)
+        }
+
+        fn uniforms(&self) -> [f32; 4] {
+            self.0
+        }
+    }
+
+    #[test]
+    fn draws_with_custom_shaders() {
+        let Ok(mut context) = HeadlessContext::new(64, 48) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        let draw = |color| {
+            move |canvas: &mut crate::canvas::Canvas| {
+                canvas
+                    .draw(Box)
+                    .translate(0.0, 0.0, 5.0)
+                    .shader(Flat(color));
+                canvas.draw(Box).translate(0.0, 2.0, 5.0);
+            }
+        };
+        let image = context.render(draw([0.0, 1.0, 0.0, 1.0]));
+        assert_eq!(image.pixel(32, 24), [0, 255, 0, 255]);
+        // the uniforms are updated every frame
+        let image = context.render(draw([0.0, 0.0, 1.0, 1.0]));
+        assert_eq!(image.pixel(32, 24), [0, 0, 255, 255]);
+    }
+
+    /// Like [`Flat`], read from a file and with a constant color.
+    struct Watched(PathBuf);
+
+    impl ShaderProvider for Watched {
+        type Vertex = PNUVertex;
+        type Uniforms = ();
+
+        fn source(&self) -> Cow<'static, str> {
+            Cow::Owned(fs::read_to_string(&self.0).unwrap())
+        }
+
+        fn path(&self) -> Option<PathBuf> {
+            Some(self.0.clone())
+        }
+
+        fn uniforms(&self) {}
+    }
+
+    fn write_shader(path: &Path, source: &str, modified: SystemTime) {
+        let file = fs::File::create(path).unwrap();
+        std::io::Write::write_all(&mut &file, source.as_bytes()).unwrap();
+        // the modification time may be too coarse to tell writes apart
+        file.set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn reloads_watched_shaders() {
+        let Ok(mut context) = HeadlessContext::new(64, 48) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        context.watch_shaders();
+        let path = std::env::temp_dir()
+            .join(format!("graphics-watched-{}.wgsl", std::process::id()));
+        let flat = |color| {
+            FLAT_SHADER.replace(
+                "return flat_color;",
+                &format!("return vec4({color});"),
+            )
+        };
+        let start = SystemTime::now();
+        write_shader(&path, &flat("1.0, 0.0, 0.0, 1.0"), start);
+        let draw = |canvas: &mut crate::canvas::Canvas| {
+            canvas
+                .draw(Box)
+                .translate(0.0, 0.0, 5.0)
+                .shader(Watched(path.clone()));
+        };
+        assert_eq!(context.render(draw).pixel(32, 24), [255, 0, 0, 255]);
+
+        let later = start + std::time::Duration::from_secs(1);
+        write_shader(&path, &flat("0.0, 1.0, 0.0, 1.0"), later);
+        assert_eq!(context.render(draw).pixel(32, 24), [0, 255, 0, 255]);
+
+        // the last shader that compiled is kept
+        let even_later = later + std::time::Duration::from_secs(1);
+        write_shader(&path, &flat("0.0, 1.0"), even_later);
+        assert_eq!(context.render(draw).pixel(32, 24), [0, 255, 0, 255]);
+        assert_eq!(context.shader_errors().count(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skips_drawings_with_shaders_that_do_not_compile() {
+        let Ok(mut context) = HeadlessContext::new(64, 48) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        let path = std::env::temp_dir()
+            .join(format!("graphics-broken-{}.wgsl", std::process::id()));
+        write_shader(&path, "fn broken( {}", SystemTime::now());
+        let image = context.render(|canvas| {
+            canvas
+                .draw(Box)
+                .translate(0.0, 0.0, 5.0)
+                .shader(Watched(path.clone()));
+        });
+        fs::remove_file(&path).unwrap();
+        assert_eq!(image.pixel(32, 24), image.pixel(0, 0));
+        let errors: Vec<_> = context.shader_errors().collect();
+        assert_eq!(errors.len(), 1);
+        let (file, line, _) = errors[0].location.as_ref().unwrap();
+        assert_eq!((file, *line), (&path.display().to_string(), 1));
+    }
+
+    #[test]
+    fn skips_drawings_of_other_vertex_types() {
+        let Ok(mut context) = HeadlessContext::new(64, 48) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        let draw = |canvas: &mut crate::canvas::Canvas| {
+            // box lines have PDVertex, the flat shader PNUVertex
+            canvas
+                .draw(BoxLines)
+                .scale(2.0, 2.0, 2.0)
+                .translate(0.0, 0.0, 5.0)
+                .shader(Flat([0.0, 1.0, 0.0, 1.0]));
+        };
+        let image = context.render(draw);
+        assert!(image.pixels.chunks(4).all(|p| p == image.pixel(0, 0)));
+        context.render(draw);
+        let errors: Vec<_> = context.shader_errors().collect();
+        assert_eq!(errors.len(), 1, "reported once");
+        assert!(errors[0].message.ends_with("PNUVertex"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn reports_errors_in_their_file() {
+        let file = |name: &str, source: &'static str| SourceFile {
+            name: name.to_string(),
+            source: Cow::Borrowed(source),
+            path: None,
+            modified: None,
+        };
+        let prelude = file("prelude.wgsl", "const A = 1.0;\n\n");
+        let broken = file("broken.wgsl", "const B = A;\nconst C = A +;\n");
+        let error = validate(&[prelude, broken]).unwrap_err();
+        assert_eq!(error.location, Some(("broken.wgsl".to_string(), 2, 14)));
+        assert!(error.to_string().starts_with("broken.wgsl:2:14: "));
+
+        let prelude = file("prelude.wgsl", "const A = 1.0;");
+        let invalid =
+            file("invalid.wgsl", "fn f() -> f32 {\n    return 1u;\n}");
+        let error = validate(&[prelude, invalid]).unwrap_err();
+        let (name, line, _) = error.location.as_ref().unwrap();
+        assert_eq!((name.as_str(), *line), ("invalid.wgsl", 2), "{error}");
+    }
+}
@@ -0,0 +1,426 @@
+//! Shadow maps of directional and spot lights.
+//!
+//! Every shadow casting light renders the depth of the drawings that cast
+//! shadows into one layer of a texture array, which the shaders sample with
+//! PCF to find out how much of the light reaches a point.
+
+use std::{any::TypeId, borrow::Cow, collections::HashMap, f32::consts::PI};
+
+use nalgebra::{Matrix4, Orthographic3, Perspective3, Point3, Vector3};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, Buffer, BufferUsages, CommandEncoder, DepthBiasState,
+    DepthStencilState, Device, PipelineLayout, Queue,
+    RenderPassDepthStencilAttachment, RenderPipeline, Sampler, ShaderModule,
+    StencilState, TextureView,
+};
+
+use crate::{
+    camera::FirstPersonCamera,
+    canvas::DrawCommand,
+    context::{batches, create_depth_texture, draw_instances, InstanceData},
+    light::{Light, MAX_SHADOW_MAPS},
+    mesh::{MeshManager, VertexLayout},
+};
+
+/// Width and height of each shadow map.
+const SHADOW_MAP_SIZE: u32 = 1024;
+
+/// Directional lights only shadow what is closer to the camera than this.
+const SHADOW_DISTANCE: f32 = 20.0;
+
+/// How far outside of the shadowed part of the scene directional lights
+/// still find shadow casters, towards the light.
+const CASTER_DISTANCE: f32 = 50.0;
+
+/// The near plane of spot lights.
+const SPOT_NEAR: f32 = 0.05;
+
+/// Maps the depth range -1 to 1 of nalgebra's projections to the range 0
+/// to 1 of wgpu.
+#[rustfmt::skip]
+const DEPTH_TO_WGPU: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.5,
+    0.0, 0.0, 0.0, 1.0,
+);
+
+/// The view projection from world space to the shadow map of `light`, or
+/// `None` for lights that cast no shadows.
+pub(crate) fn view_proj(
+    light: &Light,
+    camera: &FirstPersonCamera,
+) -> Option<Matrix4<f32>> {
+    match *light {
+        Light::Directional(light) => {
+            directional_view_proj(light.direction.into(), camera)
+        }
+        Light::Spot(light) => {
+            let fov = (2.0 * light.outer_angle).clamp(0.01, PI - 0.01);
+            let far = light.range.max(2.0 * SPOT_NEAR);
+            let proj = Perspective3::new(1.0, fov, SPOT_NEAR, far);
+            let view =
+                look_along(light.position.into(), light.direction.into())?;
+            Some(DEPTH_TO_WGPU * proj.to_homogeneous() * view)
+        }
+        Light::Ambient(_) | Light::Point(_) => None,
+    }
+}
+
+/// Covers the sphere around the part of the view frustum closer than
+/// [`SHADOW_DISTANCE`]. The sphere and the snapping of its center to whole
+/// texels keep the shadows from flickering while the camera moves.
+fn directional_view_proj(
+    direction: Vector3<f32>,
+    camera: &FirstPersonCamera,
+) -> Option<Matrix4<f32>> {
+    let corners = camera.frustum_corners(SHADOW_DISTANCE);
+    let center = corners.iter().map(|c| c.coords).sum::<Vector3<f32>>() / 8.0;
+    let radius = corners
+        .iter()
+        .map(|c| (c.coords - center).norm())
+        .fold(0.0, f32::max);
+    let view = look_along(Point3::origin(), direction)?;
+    let texel = 2.0 * radius / SHADOW_MAP_SIZE as f32;
+    let center = view.transform_point(&Point3::from(center));
+    let (x, y) = (
+        (center.x / texel).round() * texel,
+        (center.y / texel).round() * texel,
+    );
+    // the view looks along -z
+    let proj = Orthographic3::new(
+        x - radius,
+        x + radius,
+        y - radius,
+        y + radius,
+        -center.z - radius - CASTER_DISTANCE,
+        -center.z + radius,
+    );
+    Some(DEPTH_TO_WGPU * proj.to_homogeneous() * view)
+}
+
+fn look_along(
+    eye: Point3<f32>,
+    direction: Vector3<f32>,
+) -> Option<Matrix4<f32>> {
+    let direction = direction.try_normalize(f32::EPSILON)?;
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::z()
+    } else {
+        Vector3::y()
+    };
+    Some(Matrix4::look_at_rh(&eye, &(eye + direction), &up))
+}
+
+pub(crate) struct ShadowMaps {
+    /// All layers, for the shaders to sample.
+    pub(crate) view: TextureView,
+    pub(crate) sampler: Sampler,
+    /// One view per layer to render into.
+    layer_views: Vec<TextureView>,
+    /// The view projection of each layer.
+    uniform_buffers: Vec<Buffer>,
+    bind_groups: Vec<BindGroup>,
+    shader: ShaderModule,
+    pipeline_layout: PipelineLayout,
+    /// Created when a vertex type first casts shadows.
+    pipelines: HashMap<TypeId, RenderPipeline>,
+}
+
+impl ShadowMaps {
+    pub(crate) fn new(device: &Device) -> Self {
+        let (texture, view) = create_depth_texture(
+            device,
+            SHADOW_MAP_SIZE,
+            SHADOW_MAP_SIZE,
+            MAX_SHADOW_MAPS as u32,
+            1,
+        );
+        let layer_views = (0..MAX_SHADOW_MAPS as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let uniform_buffers: Vec<Buffer> = (0..MAX_SHADOW_MAPS)
+            .map(|_| {
+                device.create_buffer_init(&BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::bytes_of(&[[0.0f32; 4]; 4]),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
+        let bind_groups = uniform_buffers
+            .iter()
+            .map(|buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                })
+            })
+            .collect();
+
+        let shader =
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
+                    "shadow_shader.wgsl"
+                ))),
+            });
+        let pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        Self {
+            view,
+            sampler,
+            layer_views,
+            uniform_buffers,
+            bind_groups,
+            shader,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Creates the pipelines for the vertex types of the shadow casting
+    /// `commands` that have none yet. The position of every vertex type has
+    /// to be a `Float32x3` at location 0.
+    pub(crate) fn prepare(
+        &mut self,
+        device: &Device,
+        meshes: &MeshManager,
+        commands: &[DrawCommand],
+    ) {
+        for command in commands.iter().filter(|c| c.cast_shadows) {
+            let vertex_type = command.mesh_id.vtx_type_id;
+            if !self.pipelines.contains_key(&vertex_type) {
+                let pipeline = create_shadow_pipeline(
+                    device,
+                    &self.pipeline_layout,
+                    &self.shader,
+                    meshes.vertex_layout(command.mesh_id),
+                );
+                self.pipelines.insert(vertex_type, pipeline);
+            }
+        }
+    }
+
+    /// Renders the shadow casting `commands` into one layer per view
+    /// projection. The commands have to be sorted like for
+    /// [`batches`], with the shadow casters first in each batch, and
+    /// [`Self::prepare`]d.
+    pub(crate) fn render(
+        &self,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        view_projs: &[[[f32; 4]; 4]],
+        meshes: &MeshManager,
+        instance_buffer: &Buffer,
+        commands: &[DrawCommand],
+    ) {
+        for (layer, view_proj) in view_projs.iter().enumerate() {
+            queue.write_buffer(
+                &self.uniform_buffers[layer],
+                0,
+                bytemuck::bytes_of(view_proj),
+            );
+            let mut rpass =
+                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: None,
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(
+                        RenderPassDepthStencilAttachment {
+                            view: &self.layer_views[layer],
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: wgpu::StoreOp::Store,
+                            }),
+                            stencil_ops: None,
+                        },
+                    ),
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+            rpass.set_bind_group(0, &self.bind_groups[layer], &[]);
+            for (batch, instances) in batches(commands) {
+                let casting =
+                    batch.iter().take_while(|c| c.cast_shadows).count();
+                if casting == 0 {
+                    continue;
+                }
+                let mesh_id = batch[0].mesh_id;
+                rpass.set_pipeline(&self.pipelines[&mesh_id.vtx_type_id]);
+                draw_instances(
+                    &mut rpass,
+                    meshes,
+                    instance_buffer,
+                    mesh_id,
+                    instances.start..instances.start + casting as u32,
+                );
+            }
+        }
+    }
+}
+
+fn create_shadow_pipeline(
+    device: &Device,
+    pipeline_layout: &PipelineLayout,
+    shader: &ShaderModule,
+    vertex: &VertexLayout,
+) -> RenderPipeline {
+    // wgpu only allows a depth bias for triangles
+    let bias = match vertex.topology {
+        wgpu::PrimitiveTopology::TriangleList
+        | wgpu::PrimitiveTopology::TriangleStrip => DepthBiasState {
+            constant: 4,
+            slope_scale: 4.0,
+            clamp: 0.0,
+        },
+        _ => DepthBiasState::default(),
+    };
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[vertex.buffer.clone(), InstanceData::BUFFER_LAYOUT],
+            compilation_options: Default::default(),
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: vertex.topology,
+            ..wgpu::PrimitiveState::default()
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: StencilState::default(),
+            bias,
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        fragment: None,
+        multiview: None,
+        cache: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+
+    use crate::canvas::Canvas;
+    use crate::geometry::Box;
+    use crate::headless::HeadlessContext;
+    use crate::image::Image;
+    use crate::light::{AmbientLight, DirectionalLight, Light, SpotLight};
+    use crate::math::Transform;
+
+    /// A floor seen from above with a small box floating over it, lit at
+    /// an angle so the shadow is not hidden by the box.
+    fn render(
+        context: &mut HeadlessContext,
+        light: impl Into<Light>,
+        cast: bool,
+        receive: bool,
+    ) -> Image {
+        let camera = context.camera_mut();
+        camera.set_position(Point3::new(0.0, 5.0, 0.0));
+        camera.set_orientation(0.0, 89.9);
+        context.render(|canvas: &mut Canvas| {
+            canvas.light(AmbientLight {
+                color: [1.0, 1.0, 1.0],
+                intensity: 0.1,
+            });
+            canvas.light(light);
+            canvas
+                .draw(Box)
+                .scale(10.0, 0.1, 10.0)
+                .receive_shadows(receive);
+            canvas
+                .draw(Box)
+                .scale(0.5, 0.5, 0.5)
+                .translate(0.0, 1.0, 0.0)
+                .cast_shadows(cast);
+        })
+    }
+
+    fn dark_pixels(image: &Image) -> usize {
+        let dark = |p: &[u8]| p[..3].iter().all(|&c| c < 128);
+        image.pixels.chunks(4).filter(|p| dark(p)).count()
+    }
+
+    #[test]
+    fn casts_and_receives_shadows() {
+        let Ok(mut context) = HeadlessContext::new(64, 64) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        let sun = DirectionalLight {
+            direction: [1.0, -2.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+        };
+        let shadow = dark_pixels(&render(&mut context, sun, true, true));
+        // the shadow of the box covers about as many pixels as the box
+        assert!((20..200).contains(&shadow), "{shadow}");
+        assert_eq!(dark_pixels(&render(&mut context, sun, false, true)), 0);
+        assert_eq!(dark_pixels(&render(&mut context, sun, true, false)), 0);
+    }
+
+    #[test]
+    fn spot_lights_cast_shadows() {
+        let Ok(mut context) = HeadlessContext::new(64, 64) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        let spot = SpotLight {
+            position: [-1.0, 3.0, 0.0],
+            direction: [1.0, -3.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 20.0,
+            range: 10.0,
+            inner_angle: 0.6,
+            outer_angle: 0.7,
+        };
+        // outside of the cone is dark as well
+        let unshadowed = dark_pixels(&render(&mut context, spot, false, true));
+        let shadowed = dark_pixels(&render(&mut context, spot, true, true));
+        assert!(shadowed > unshadowed + 20, "{shadowed} {unshadowed}");
+    }
+}
@@ -0,0 +1,25 @@
+// Renders the depth of the shadow casters as seen from a light.
+
+@group(0) @binding(0)
+var<uniform> view_proj: mat4x4<f32>;
+
+struct InstanceIn {
+    @location(4) model_0: vec4<f32>,
+    @location(5) model_1: vec4<f32>,
+    @location(6) model_2: vec4<f32>,
+    @location(7) model_3: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    instance_in: InstanceIn,
+) -> @builtin(position) vec4<f32> {
+    let model = mat4x4<f32>(
+        instance_in.model_0,
+        instance_in.model_1,
+        instance_in.model_2,
+        instance_in.model_3,
+    );
+    return view_proj * model * vec4(position, 1.0);
+}
@@ -0,0 +1,287 @@
+//! Textures for drawings, see [`Drawing::texture`].
+//!
+//! Textures are registered like static meshes: the first drawing that uses
+//! a [`TextureProvider`] loads its image into a texture with mipmaps, later
+//! ones reuse it.
+//!
+//! [`Drawing::texture`]: crate::canvas::drawing::Drawing::texture
+
+use std::{any::TypeId, collections::HashMap, sync::Arc};
+
+use wgpu::{
+    BindGroup, BindGroupLayout, Device, Extent3d, Queue, SamplerDescriptor,
+    Texture, TextureDescriptor, TextureFormat, TextureUsages,
+};
+
+use crate::image::Image;
+
+/// Describes a texture, comparable to a [`MeshProvider`] of a static mesh.
+///
+/// [`MeshProvider`]: crate::mesh::MeshProvider
+pub trait TextureProvider: 'static {
+    /// Only called when the texture is used for the first time, e.g. with
+    /// [`Image::load`].
+    fn create_image(&self) -> Image;
+
+    /// Repeats the texture and filters it linearly by default.
+    fn sampler(&self) -> SamplerDescriptor<'static> {
+        SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextureId(usize);
+
+impl TextureId {
+    /// A single white pixel, used by drawings without a texture.
+    pub const WHITE: TextureId = TextureId(0);
+}
+
+pub struct TextureManager {
+    texture_ids: HashMap<TypeId, TextureId>,
+    textures: Vec<TextureEntry>,
+    layout: BindGroupLayout,
+    device: Arc<Device>,
+}
+
+struct TextureEntry {
+    texture: Texture,
+    bind_group: BindGroup,
+    /// The mip levels still to be written to the texture.
+    pending: Vec<Image>,
+}
+
+impl TextureManager {
+    pub fn new(device: Arc<Device>) -> Self {
+        let layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float {
+                                filterable: true,
+                            },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(
+                            wgpu::SamplerBindingType::Filtering,
+                        ),
+                        count: None,
+                    },
+                ],
+            });
+        let mut manager = Self {
+            texture_ids: HashMap::new(),
+            textures: Vec::new(),
+            layout,
+            device,
+        };
+        let white = Image::new(1, 1, vec![255; 4]);
+        manager.insert(white, &SamplerDescriptor::default());
+        manager
+    }
+
+    pub fn get_or_insert<T: TextureProvider>(
+        &mut self,
+        texture: T,
+    ) -> TextureId {
+        if let Some(&id) = self.texture_ids.get(&TypeId::of::<T>()) {
+            return id;
+        }
+        let id = self.insert(texture.create_image(), &texture.sampler());
+        self.texture_ids.insert(TypeId::of::<T>(), id);
+        id
+    }
+
+    fn insert(
+        &mut self,
+        image: Image,
+        sampler: &SamplerDescriptor<'static>,
+    ) -> TextureId {
+        let mip_levels = mipmaps(image);
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: mip_levels[0].width,
+                height: mip_levels[0].height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = self.device.create_sampler(sampler);
+        let bind_group =
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+            });
+        self.textures.push(TextureEntry {
+            texture,
+            bind_group,
+            pending: mip_levels,
+        });
+        TextureId(self.textures.len() - 1)
+    }
+
+    pub(crate) fn bind_group(&self, id: TextureId) -> &BindGroup {
+        &self.textures[id.0].bind_group
+    }
+
+    /// The layout of the texture and sampler of a drawing, bind group 2.
+    pub(crate) fn layout(&self) -> &BindGroupLayout {
+        &self.layout
+    }
+
+    /// Writes the images of the textures added since the last frame.
+    pub(crate) fn upload(&mut self, queue: &Queue) {
+        for entry in &mut self.textures {
+            for (level, image) in entry.pending.drain(..).enumerate() {
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        texture: &entry.texture,
+                        mip_level: level as u32,
+                        origin: wgpu::Origin3d::ZERO,
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    &image.pixels,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(image.width * 4),
+                        rows_per_image: None,
+                    },
+                    Extent3d {
+                        width: image.width,
+                        height: image.height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// `image` followed by its mip levels down to a single pixel, each half
+/// the size of the one before.
+///
+/// The pixels are averaged in linear color space, like the GPU blends sRGB
+/// textures.
+///
+/// # Panics
+/// If the image is empty, wgpu has no textures without pixels.
+fn mipmaps(image: Image) -> Vec<Image> {
+    assert!(
+        image.width > 0 && image.height > 0,
+        "cannot create a texture from an empty {}x{} image",
+        image.width,
+        image.height
+    );
+    let mut levels = vec![image];
+    loop {
+        let last = levels.last().unwrap();
+        if (last.width, last.height) == (1, 1) {
+            return levels;
+        }
+        let (width, height) =
+            ((last.width / 2).max(1), (last.height / 2).max(1));
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                // the last pixel of odd sizes is dropped
+                let xs = [2 * x, (2 * x + 1).min(last.width - 1)];
+                let ys = [2 * y, (2 * y + 1).min(last.height - 1)];
+                let mut sum = [0.0; 4];
+                for (x, y) in xs.into_iter().flat_map(|x| ys.map(|y| (x, y))) {
+                    let pixel = last.pixel(x, y);
+                    for c in 0..3 {
+                        sum[c] += srgb_to_linear(pixel[c]);
+                    }
+                    sum[3] += f32::from(pixel[3]) / 255.0;
+                }
+                pixels.extend_from_slice(&[
+                    linear_to_srgb(sum[0] / 4.0),
+                    linear_to_srgb(sum[1] / 4.0),
+                    linear_to_srgb(sum[2] / 4.0),
+                    (sum[3] / 4.0 * 255.0).round() as u8,
+                ]);
+            }
+        }
+        levels.push(Image::new(width, height, pixels));
+    }
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = f32::from(c) / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halves_mip_levels_down_to_one_pixel() {
+        #[rustfmt::skip]
+        let pixels = [
+            [255, 0, 0, 255], [255, 0, 0, 255], [0, 0, 0, 0],
+            [0, 0, 255, 255], [0, 0, 255, 255], [0, 0, 0, 0],
+        ];
+        let image = Image::new(3, 2, pixels.concat());
+        let levels = mipmaps(image.clone());
+        let sizes: Vec<_> =
+            levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(3, 2), (1, 1)]);
+        assert_eq!(levels[0], image);
+        // half red and half blue, brighter than averaging the bytes
+        assert_eq!(levels[1].pixel(0, 0), [188, 0, 188, 255]);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty 0x4 image")]
+    fn rejects_empty_images() {
+        mipmaps(Image::new(0, 4, Vec::new()));
+    }
+}
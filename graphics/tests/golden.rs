@@ -0,0 +1,288 @@
+//! Renders reference scenes offscreen and compares them against the PNGs in
+//! `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write the current output as the new
+//! references. On a mismatch the output and a diff image are written to
+//! `golden` in the target directory.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use graphics::canvas::Canvas;
+use graphics::geometry::{Box, BoxLines, Ellipsoid, ParametricSquare};
+use graphics::headless::HeadlessContext;
+use graphics::image::Image;
+use graphics::light::{AmbientLight, DirectionalLight, PointLight, SpotLight};
+use graphics::material::{Material, Pbr};
+use graphics::math::Transform;
+use graphics::texture::TextureProvider;
+use nalgebra::Point3;
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 72;
+
+/// Colors closer than this count as equal, see [`Image::compare`].
+const THRESHOLD: f32 = 0.1;
+/// Fraction of the pixels that may differ, for rasterization differences
+/// along edges between adapters.
+const MAX_DIFFERING: f64 = 0.01;
+
+struct Pose {
+    name: &'static str,
+    position: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+}
+
+/// Both look at `(0, 0, 3)`, where the scenes are placed.
+const POSES: [Pose; 2] = [
+    Pose {
+        name: "front",
+        position: [0.0, 0.0, 0.0],
+        yaw: 0.0,
+        pitch: 0.0,
+    },
+    Pose {
+        name: "above",
+        position: [2.0, 2.0, 1.0],
+        yaw: -45.0,
+        pitch: 35.26,
+    },
+];
+
+type Scene = (&'static str, fn(&mut Canvas));
+
+const SCENES: [Scene; 8] = [
+    ("box", |canvas| {
+        canvas
+            .draw(Box)
+            .rotate_y(0.5)
+            .translate(0.0, 0.0, 3.0)
+            .color([0.8, 0.3, 0.1]);
+    }),
+    ("box_lines", |canvas| {
+        canvas
+            .draw(BoxLines)
+            .rotate_y(0.5)
+            .translate(0.0, 0.0, 3.0)
+            .color([0.0, 0.0, 0.0]);
+    }),
+    ("ellipsoid", |canvas| {
+        canvas
+            .draw(Ellipsoid)
+            .scale(1.5, 1.0, 1.0)
+            .translate(0.0, 0.0, 3.0)
+            .color([0.2, 0.7, 0.3]);
+    }),
+    ("parametric_square", |canvas| {
+        let wave = |x: f32, z: f32| {
+            let (x, z) = (x * 6.0, z * 6.0);
+            (
+                0.1 * x.sin() * z.cos(),
+                0.6 * x.cos() * z.cos(),
+                -0.6 * x.sin() * z.sin(),
+            )
+        };
+        canvas
+            .draw(ParametricSquare::new(32, wave))
+            .scale(2.0, 2.0, 2.0)
+            .rotate_x(-0.6)
+            .translate(0.0, 0.0, 3.0)
+            .color([0.3, 0.4, 0.9]);
+    }),
+    ("lights", |canvas| {
+        canvas.light(AmbientLight {
+            color: [1.0, 1.0, 1.0],
+            intensity: 0.05,
+        });
+        canvas.light(DirectionalLight {
+            direction: [0.0, -1.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 0.2,
+        });
+        canvas.light(PointLight {
+            position: [-0.6, 0.3, 2.5],
+            color: [1.0, 0.2, 0.1],
+            intensity: 0.5,
+            range: 3.0,
+        });
+        canvas.light(SpotLight {
+            position: [1.5, 0.8, 3.0],
+            direction: [-1.0, -0.8, 0.0],
+            color: [0.2, 0.4, 1.0],
+            intensity: 3.0,
+            range: 5.0,
+            inner_angle: 0.3,
+            outer_angle: 0.5,
+        });
+        // a floor and a ball on it
+        canvas
+            .draw(Box)
+            .scale(4.0, 0.1, 4.0)
+            .translate(0.0, -0.6, 3.0)
+            .color([0.9, 0.9, 0.9]);
+        canvas
+            .draw(Ellipsoid)
+            .scale(0.5, 0.5, 0.5)
+            .translate(0.3, -0.3, 3.0)
+            .color([0.9, 0.9, 0.9]);
+    }),
+    ("textured", |canvas| {
+        canvas
+            .draw(Box)
+            .rotate_y(0.5)
+            .translate(-0.7, 0.0, 3.0)
+            .texture(Checker);
+        canvas
+            .draw(Ellipsoid)
+            .scale(0.6, 0.6, 0.6)
+            .rotate_y(0.5)
+            .translate(0.7, 0.0, 3.0)
+            .color([1.0, 0.8, 0.8])
+            .texture(Checker);
+    }),
+    ("materials", |canvas| {
+        canvas.light(AmbientLight {
+            color: [1.0, 1.0, 1.0],
+            intensity: 0.2,
+        });
+        canvas.light(DirectionalLight {
+            direction: [0.4, -0.6, 1.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+        });
+        // rough to smooth from left to right, plastic below metal
+        for i in 0..4 {
+            for (metallic, y) in [(0.0, -0.4), (1.0, 0.2)] {
+                canvas
+                    .draw(Ellipsoid)
+                    .scale(0.25, 0.25, 0.25)
+                    .translate(0.9 - i as f32 * 0.6, y, 3.0)
+                    .material(Material {
+                        base_color: [0.9, 0.6, 0.2],
+                        metallic,
+                        roughness: 1.0 - i as f32 * 0.3,
+                        ..Material::DEFAULT
+                    })
+                    .shader(Pbr);
+            }
+        }
+        canvas
+            .draw(Box)
+            .scale(0.3, 0.3, 0.3)
+            .translate(0.0, 0.75, 3.0)
+            .material(Material {
+                base_color: [0.1, 0.1, 0.1],
+                emissive: [0.2, 0.8, 0.4],
+                ..Material::DEFAULT
+            });
+    }),
+    ("transparent", |canvas| {
+        // the nearer box is drawn first, the sorting has to blend it last
+        canvas
+            .draw(Box)
+            .scale(0.8, 0.8, 0.2)
+            .translate(0.2, -0.2, 2.2)
+            .color([0.2, 0.4, 1.0])
+            .alpha(0.5);
+        canvas
+            .draw(Box)
+            .scale(0.8, 0.8, 0.2)
+            .translate(-0.2, 0.2, 2.6)
+            .color([1.0, 0.3, 0.1])
+            .alpha(0.5);
+        canvas
+            .draw(Ellipsoid)
+            .translate(0.0, 0.0, 3.4)
+            .color([0.9, 0.9, 0.9]);
+    }),
+];
+
+/// 4x4 squares of orange and blue, with a white top left square.
+struct Checker;
+
+impl TextureProvider for Checker {
+    fn create_image(&self) -> Image {
+        let pixels = (0..16 * 16)
+            .flat_map(|i| {
+                let (x, y) = (i % 16 / 4, i / 16 / 4);
+                if (x, y) == (0, 0) {
+                    [255, 255, 255, 255]
+                } else if (x + y) % 2 == 0 {
+                    [230, 120, 20, 255]
+                } else {
+                    [30, 60, 200, 255]
+                }
+            })
+            .collect();
+        Image::new(16, 16, pixels)
+    }
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+#[test]
+fn reference_scenes() {
+    let Ok(mut context) = HeadlessContext::new(WIDTH, HEIGHT) else {
+        eprintln!("no graphics adapter, skipping");
+        return;
+    };
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = vec![];
+    for (scene, draw) in SCENES {
+        for pose in &POSES {
+            let camera = context.camera_mut();
+            camera.set_position(Point3::from(pose.position));
+            camera.set_orientation(pose.yaw, pose.pitch);
+            let image = context.render(draw);
+            let name = format!("{scene}_{}", pose.name);
+            if let Err(failure) = check(&name, &image, update) {
+                failures.push(failure);
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn check(name: &str, image: &Image, update: bool) -> Result<(), String> {
+    let path = golden_dir().join(format!("{name}.png"));
+    if update {
+        image.save_png(&path).unwrap();
+        return Ok(());
+    }
+    let failure = match Image::load_png(&path) {
+        Err(e) => format!("{name}: cannot load {}: {e}", path.display()),
+        Ok(reference) => match reference.compare(image, THRESHOLD) {
+            None => format!(
+                "{name}: the reference is {}x{}, the output {}x{}",
+                reference.width, reference.height, image.width, image.height
+            ),
+            Some(comparison)
+                if comparison.differing_fraction() > MAX_DIFFERING =>
+            {
+                let diff_path = output_dir().join(format!("{name}.diff.png"));
+                std::fs::create_dir_all(output_dir()).unwrap();
+                comparison.diff.save_png(&diff_path).unwrap();
+                format!(
+                    "{name}: {} pixels differ, see {}",
+                    comparison.differing,
+                    diff_path.display()
+                )
+            }
+            Some(_) => return Ok(()),
+        },
+    };
+    std::fs::create_dir_all(output_dir()).unwrap();
+    let actual_path = output_dir().join(format!("{name}.png"));
+    image.save_png(&actual_path).unwrap();
+    Err(format!(
+        "{failure}, the output is {}",
+        actual_path.display()
+    ))
+}
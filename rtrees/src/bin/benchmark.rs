@@ -0,0 +1,307 @@
+//! Headless benchmark of the OMT and the dynamic R-tree.
+//!
+//! ```text
+//! benchmark [--count N] [--queries N] [--seed N] [--format csv|json]
+//!           [--distributions uniform,clustered,skewed,line,plane]
+//!           [--files FILE,...]
+//! ```
+//!
+//! Files are loaded with [`rtrees::dataset::load`] and scaled to the same
+//! bounds as the generated distributions, pass `--distributions ''` to only
+//! run the files.
+
+use std::env;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use rtrees::dataset;
+use rtrees::generate::{uniform_aabbs, Distribution, Generator};
+use rtrees::omt::{Leaf, RTree as Omt, AABB};
+use rtrees::rtree::RTree;
+
+const BOUNDS: AABB = AABB {
+    min: [-10.0, -10.0, -10.0],
+    max: [10.0, 10.0, 10.0],
+};
+
+const CLUSTER_COUNT: usize = 20;
+
+struct Config {
+    count: usize,
+    queries: usize,
+    seed: u64,
+    json: bool,
+    distributions: Vec<String>,
+    files: Vec<String>,
+}
+
+struct Record {
+    distribution: String,
+    tree: &'static str,
+    count: usize,
+    build_ms: f64,
+    queries_per_sec: f64,
+    hits_per_query: f64,
+    memory_bytes: usize,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: benchmark [--count N] [--queries N] [--seed N] \
+         [--format csv|json] \
+         [--distributions uniform,clustered,skewed,line,plane] \
+         [--files FILE,...]"
+    );
+    std::process::exit(1)
+}
+
+fn parse_args() -> Config {
+    let mut config = Config {
+        count: 10000,
+        queries: 10000,
+        seed: 0,
+        json: false,
+        distributions: ["uniform", "clustered", "skewed", "line", "plane"]
+            .map(String::from)
+            .to_vec(),
+        files: Vec::new(),
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--count" => {
+                config.count = value.parse().unwrap_or_else(|_| usage())
+            }
+            "--queries" => {
+                config.queries = value.parse().unwrap_or_else(|_| usage())
+            }
+            "--seed" => config.seed = value.parse().unwrap_or_else(|_| usage()),
+            "--format" => {
+                config.json = match value.as_str() {
+                    "csv" => false,
+                    "json" => true,
+                    _ => usage(),
+                }
+            }
+            "--distributions" => config.distributions = split_list(&value),
+            "--files" => config.files = split_list(&value),
+            _ => usage(),
+        }
+    }
+    config
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn load(path: &str) -> Vec<AABB> {
+    let mut aabbs = dataset::load(path).unwrap_or_else(|e| {
+        eprintln!("{path}: {e}");
+        std::process::exit(1)
+    });
+    dataset::fit_into(&mut aabbs, BOUNDS);
+    aabbs
+}
+
+fn generate(distribution: &str, count: usize, seed: u64) -> Vec<AABB> {
+    let distribution = match distribution {
+        "uniform" => Distribution::Uniform { size: 0.01..0.1 },
+        "clustered" => Distribution::Clusters {
+            clusters: CLUSTER_COUNT,
+            std_dev: 0.5,
+            size: 0.01..0.1,
+        },
+        "skewed" => Distribution::Zipf {
+            max_size: 2.0,
+            ranks: 200,
+            exponent: 1.0,
+        },
+        "line" => Distribution::Line { size: 0.01..0.1 },
+        "plane" => Distribution::Plane { size: 0.01..0.1 },
+        _ => usage(),
+    };
+    Generator::seeded(seed, BOUNDS, distribution)
+        .expect("the benchmark distributions are valid")
+        .take(count)
+        .collect()
+}
+
+fn main() {
+    let config = parse_args();
+    let mut records = Vec::new();
+    let generated = config.distributions.iter().map(|distribution| {
+        (
+            distribution,
+            generate(distribution, config.count, config.seed),
+        )
+    });
+    let loaded = config.files.iter().map(|path| (path, load(path)));
+    for (distribution, aabbs) in generated.chain(loaded) {
+        let queries =
+            uniform_aabbs(config.seed + 1, config.queries, BOUNDS, 0.1..0.4);
+
+        let start = Instant::now();
+        let omt = Omt::new(aabbs.iter().map(|&a| Leaf::new_empty(a)).collect());
+        let build_ms = start.elapsed().as_secs_f64() * 1000.0;
+        let start = Instant::now();
+        let hits: usize = queries
+            .iter()
+            .map(|&q| omt.query(q).filter(|item| item.is_leaf()).count())
+            .sum();
+        let query_time = start.elapsed().as_secs_f64();
+        records.push(Record {
+            distribution: distribution.clone(),
+            tree: "omt",
+            count: aabbs.len(),
+            build_ms,
+            queries_per_sec: queries.len() as f64 / query_time,
+            hits_per_query: hits as f64 / queries.len() as f64,
+            memory_bytes: omt.memory_usage(),
+        });
+
+        let start = Instant::now();
+        let mut rtree = RTree::new();
+        for &aabb in &aabbs {
+            rtree.insert(aabb, ());
+        }
+        let build_ms = start.elapsed().as_secs_f64() * 1000.0;
+        let start = Instant::now();
+        let hits: usize = queries.iter().map(|q| rtree.search(q).len()).sum();
+        let query_time = start.elapsed().as_secs_f64();
+        records.push(Record {
+            distribution: distribution.clone(),
+            tree: "rtree",
+            count: aabbs.len(),
+            build_ms,
+            queries_per_sec: queries.len() as f64 / query_time,
+            hits_per_query: hits as f64 / queries.len() as f64,
+            memory_bytes: rtree.memory_usage(),
+        });
+    }
+    let mut stdout = io::stdout().lock();
+    let written = if config.json {
+        write_json(&mut stdout, &records)
+    } else {
+        write_csv(&mut stdout, &records)
+    };
+    written.expect("cannot write the results");
+}
+
+fn write_csv(out: &mut impl Write, records: &[Record]) -> io::Result<()> {
+    writeln!(
+        out,
+        "distribution,tree,count,build_ms,queries_per_sec,hits_per_query,\
+         memory_bytes"
+    )?;
+    for r in records {
+        writeln!(
+            out,
+            "{},{},{},{:.3},{:.1},{:.3},{}",
+            csv_field(&r.distribution),
+            r.tree,
+            r.count,
+            r.build_ms,
+            r.queries_per_sec,
+            r.hits_per_query,
+            r.memory_bytes
+        )?;
+    }
+    Ok(())
+}
+
+fn write_json(out: &mut impl Write, records: &[Record]) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (i, r) in records.iter().enumerate() {
+        let separator = if i + 1 < records.len() { "," } else { "" };
+        writeln!(
+            out,
+            "  {{\"distribution\": {}, \"tree\": {}, \"count\": {}, \
+             \"build_ms\": {:.3}, \"queries_per_sec\": {:.1}, \
+             \"hits_per_query\": {:.3}, \"memory_bytes\": {}}}{separator}",
+            json_string(&r.distribution),
+            json_string(r.tree),
+            r.count,
+            r.build_ms,
+            r.queries_per_sec,
+            r.hits_per_query,
+            r.memory_bytes
+        )?;
+    }
+    writeln!(out, "]")
+}
+
+/// Quotes `field` if it contains a separator, quote or line break, with
+/// the quotes inside doubled.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// `value` as a quoted JSON string.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(distribution: &str) -> Record {
+        Record {
+            distribution: distribution.to_string(),
+            tree: "omt",
+            count: 10,
+            build_ms: 1.0,
+            queries_per_sec: 2.0,
+            hits_per_query: 3.0,
+            memory_bytes: 4,
+        }
+    }
+
+    #[test]
+    fn escapes_file_names() {
+        let records = [record("data/a,\"b\"\\c.csv"), record("plain")];
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &records).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[1],
+            "\"data/a,\"\"b\"\"\\c.csv\",omt,10,1.000,2.0,3.000,4"
+        );
+        assert!(lines[2].starts_with("plain,omt,"));
+
+        let mut json = Vec::new();
+        write_json(&mut json, &records).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains(r#""distribution": "data/a,\"b\"\\c.csv""#));
+        assert!(json.contains(r#""distribution": "plain""#));
+        assert_eq!(json_string("a\nb\u{1}"), r#""a\nb\u0001""#);
+    }
+}
@@ -2,13 +2,17 @@ use std::time::Instant;
 
 use graphics::app::{App, AppState};
 use graphics::canvas::Canvas;
+use graphics::context::ContextConfig;
 use graphics::geometry::{Box, BoxLines};
 use graphics::math::Transform;
 use rtrees::omt::{rand_aabbs, Leaf, RTree as Omt, AABB};
 use rtrees::rtree::RTree;
 
 fn main() {
-    App::run_with(State::new());
+    let config = ContextConfig::default()
+        .sample_count(4)
+        .title("build_comparison");
+    App::run_with_config(State::new(), config);
 }
 
 struct State {
@@ -0,0 +1,61 @@
+use std::env;
+use std::time::Instant;
+
+use rtrees::generate::uniform_aabbs;
+use rtrees::omt::{Leaf, RTree as Omt, AABB};
+use rtrees::rtree::RTree;
+
+const BOUNDS: AABB = AABB {
+    min: [-10.0, -10.0, -10.0],
+    max: [10.0, 10.0, 10.0],
+};
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let leaf_count = args.next().map_or(5000, |a| a.parse().unwrap());
+    let seed = args.next().map_or(0, |a| a.parse().unwrap());
+    let a = uniform_aabbs(seed, leaf_count, BOUNDS, 0.01..0.1);
+    let b = uniform_aabbs(seed + 1, leaf_count, BOUNDS, 0.01..0.1);
+
+    let omt = |aabbs: &[AABB]| {
+        Omt::new(aabbs.iter().map(|&aabb| Leaf::new_empty(aabb)).collect())
+    };
+    let rtree = |aabbs: &[AABB]| {
+        let mut tree = RTree::new();
+        for &aabb in aabbs {
+            tree.insert(aabb, ());
+        }
+        tree
+    };
+    let (omt_a, omt_b) = (omt(&a), omt(&b));
+    let (rtree_a, rtree_b) = (rtree(&a), rtree(&b));
+
+    println!("{:<22} {:>10} {:>12}", "join", "pairs", "time (ms)");
+    report("brute force", || {
+        a.iter()
+            .map(|a| b.iter().filter(|b| a.overlaps(b)).count())
+            .sum()
+    });
+    report("omt", || omt_a.join(&omt_b).count());
+    report("rtree", || rtree_a.join(&rtree_b).count());
+    report("omt x rtree", || omt_a.join(&rtree_b).count());
+    report("brute force self join", || {
+        (0..a.len())
+            .map(|i| a[i + 1..].iter().filter(|b| a[i].overlaps(b)).count())
+            .sum()
+    });
+    report("omt self join", || omt_a.self_join().count());
+    report("rtree self join", || rtree_a.self_join().count());
+}
+
+fn report(name: &str, join: impl Fn() -> usize) {
+    let start = Instant::now();
+    let pairs = join();
+    let time = start.elapsed();
+    println!(
+        "{:<22} {:>10} {:>12.2}",
+        name,
+        pairs,
+        time.as_secs_f64() * 1000.0
+    );
+}
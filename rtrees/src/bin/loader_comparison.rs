@@ -0,0 +1,69 @@
+use std::env;
+use std::time::Instant;
+
+use rtrees::generate::uniform_aabbs;
+use rtrees::omt::{Leaf, Loader, RTree, AABB};
+use rtrees::stats::QueryStats;
+
+const BOUNDS: AABB = AABB {
+    min: [-10.0, -10.0, -10.0],
+    max: [10.0, 10.0, 10.0],
+};
+
+const FLAT_BOUNDS: AABB = AABB {
+    min: [-10.0, -2.0, -10.0],
+    max: [10.0, 2.0, 10.0],
+};
+
+const LONG_BOUNDS: AABB = AABB {
+    min: [-100.0, -1.0, -1.0],
+    max: [100.0, 1.0, 1.0],
+};
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let leaf_count = args.next().map_or(10000, |a| a.parse().unwrap());
+    let query_count = args.next().map_or(1000, |a| a.parse().unwrap());
+    let seed = args.next().map_or(0, |a| a.parse().unwrap());
+
+    let datasets = [
+        ("uniform", BOUNDS),
+        ("flat", FLAT_BOUNDS),
+        ("long", LONG_BOUNDS),
+    ];
+    println!(
+        "{:<8} {:<8} {:>10} {:>7} {:>14} {:>14}",
+        "dataset",
+        "loader",
+        "build (ms)",
+        "height",
+        "nodes/query",
+        "leaves/query"
+    );
+    for (name, bounds) in datasets {
+        let leaves: Vec<Leaf<()>> =
+            uniform_aabbs(seed, leaf_count, bounds, 0.01..0.1)
+                .into_iter()
+                .map(Leaf::new_empty)
+                .collect();
+        let queries = uniform_aabbs(seed + 1, query_count, bounds, 0.1..0.4);
+        for loader in Loader::ALL {
+            let start = Instant::now();
+            let tree = RTree::with_loader(leaves.clone(), loader);
+            let build_time = start.elapsed();
+            let mut total = QueryStats::default();
+            for query in &queries {
+                total += &tree.query_stats(query);
+            }
+            println!(
+                "{:<8} {:<8} {:>10.2} {:>7} {:>14.2} {:>14.2}",
+                name,
+                format!("{loader:?}"),
+                build_time.as_secs_f64() * 1000.0,
+                tree.height(),
+                total.total_nodes_visited() as f64 / query_count as f64,
+                total.leaf_tests as f64 / query_count as f64,
+            );
+        }
+    }
+}
@@ -1,20 +1,46 @@
-use std::collections::{HashMap, HashSet};
+//! Shows the nodes an OMT query visits, on random boxes or on a dataset.
+//!
+//! ```text
+//! omt_query [FILE.csv|FILE.xyz|FILE.ply|FILE.obj]
+//! ```
+
+use std::env;
+use std::ops::ControlFlow;
 use std::time::{Duration, Instant};
 
 use graphics::app::{App, AppState};
+use graphics::canvas::drawing::Drawing;
 use graphics::canvas::Canvas;
+use graphics::context::ContextConfig;
 use graphics::geometry::{Box, BoxLines};
 use graphics::math::Transform;
-use rtrees::omt::{rand_aabb, rand_aabbs, Leaf, QueryData, RTree as Omt, AABB};
+use rtrees::dataset;
+use rtrees::omt::{rand_aabb, rand_aabbs, Leaf, RTree as Omt, Visit, AABB};
+use rtrees::stats::QueryStats;
 
 fn main() {
-    App::run_with(State::new());
+    let aabbs = match env::args().nth(1) {
+        Some(path) => {
+            let mut aabbs = dataset::load(&path).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                std::process::exit(1)
+            });
+            dataset::fit_into(&mut aabbs, BOUNDS);
+            aabbs
+        }
+        None => rand_aabbs(10000, BOUNDS, 0.01..0.1),
+    };
+    let config = ContextConfig::default().sample_count(4).title("omt_query");
+    App::run_with_config(State::new(aabbs), config);
 }
 
 struct State {
     prev: Instant,
     omt: Omt<()>,
     query_aabb: AABB,
+    /// The nodes the query visits by depth, to highlight the depths where
+    /// it has to look at more than one node
+    stats: QueryStats,
 }
 
 const BOUNDS: AABB = AABB {
@@ -23,15 +49,16 @@ const BOUNDS: AABB = AABB {
 };
 
 impl State {
-    fn new() -> Self {
-        let leaves: Vec<Leaf<()>> = rand_aabbs(10000, BOUNDS, 0.01..0.1)
-            .into_iter()
-            .map(Leaf::new_empty)
-            .collect();
+    fn new(aabbs: Vec<AABB>) -> Self {
+        let leaves: Vec<Leaf<()>> =
+            aabbs.into_iter().map(Leaf::new_empty).collect();
+        let omt = Omt::new(leaves);
+        let query_aabb = rand_aabb(BOUNDS, 0.02..0.2);
         Self {
             prev: Instant::now(),
-            omt: Omt::new(leaves),
-            query_aabb: rand_aabb(BOUNDS, 0.02..0.2),
+            stats: omt.query_stats(&query_aabb),
+            omt,
+            query_aabb,
         }
     }
 }
@@ -51,44 +78,78 @@ impl AppState for State {
             self.query_aabb.max[1] -= pos[1] / 100.0;
             self.query_aabb.max[2] -= pos[2] / 100.0;
         }
+        self.stats = self.omt.query_stats(&self.query_aabb);
     }
 
     fn draw(&self, canvas: &mut Canvas) {
         let query_aabb = self.query_aabb;
-        let size = query_aabb.size().map(|f| f as f32);
-        let pos = query_aabb.pos().map(|f| f as f32);
-        canvas
-            .draw(BoxLines)
-            .scale(size[0], size[1], size[2])
-            .translate(pos[0], pos[1], pos[2])
-            .color([1.0, 0.0, 0.0]);
-        let query: Vec<_> = self.omt.query(query_aabb).collect();
-        let mut levels = HashMap::new();
-        for q in &query {
-            if let QueryData::Node { depth } = q.data {
-                *levels.entry(depth).or_insert(0usize) += 1;
-            }
-        }
-        let levels: HashSet<_> = levels
-            .into_iter()
-            .filter(|(_, i)| i >= &2)
-            .map(|(l, _)| l)
-            .collect();
-        for item in query {
-            let size = item.aabb.size().map(|f| f as f32);
-            let pos = item.aabb.pos().map(|f| f as f32);
-            let drawing = if let QueryData::Node { depth } = item.data {
-                if levels.contains(&depth) {
-                    canvas.draw(BoxLines).color([0.0, 0.0, 0.0])
-                } else {
-                    canvas.draw(BoxLines)
+        place(canvas.draw(BoxLines), &query_aabb).color([1.0, 0.0, 0.0]);
+        let nodes_visited = &self.stats.nodes_visited;
+        let _ = self.omt.visit::<()>(&query_aabb, |item| {
+            match item {
+                Visit::Node { aabb, depth } => {
+                    // translucent, so the nodes and leaves inside stay
+                    // visible
+                    if nodes_visited.get(depth).is_some_and(|&n| n >= 2) {
+                        place(canvas.draw(BoxLines), aabb)
+                            .color([0.0, 0.0, 0.0]);
+                        place(canvas.draw(Box), aabb)
+                            .color([1.0, 0.5, 0.0])
+                            .alpha(0.15);
+                    } else {
+                        place(canvas.draw(BoxLines), aabb);
+                        place(canvas.draw(Box), aabb)
+                            .color([0.2, 0.4, 1.0])
+                            .alpha(0.1);
+                    }
                 }
-            } else {
-                canvas.draw(Box)
-            };
-            drawing
-                .scale(size[0], size[1], size[2])
-                .translate(pos[0], pos[1], pos[2]);
-        }
+                Visit::Leaf(leaf) => {
+                    place(canvas.draw(Box), &leaf.aabb);
+                }
+            }
+            ControlFlow::Continue(())
+        });
+    }
+}
+
+/// The smallest edge length [`place`] draws, points and flat nodes would
+/// otherwise give a transform without an inverse.
+const MIN_SIZE: f32 = 1e-3;
+
+/// Scales and moves the unit box or box lines of `drawing` onto `aabb`.
+fn place<'c, 'cref>(
+    drawing: Drawing<'c, 'cref>,
+    aabb: &AABB,
+) -> Drawing<'c, 'cref> {
+    let size = aabb.size().map(|f| (f as f32).max(MIN_SIZE));
+    let pos = aabb.pos().map(|f| f as f32);
+    drawing
+        .scale(size[0], size[1], size[2])
+        .translate(pos[0], pos[1], pos[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use graphics::headless::HeadlessContext;
+
+    use super::*;
+
+    #[test]
+    fn places_zero_size_aabbs() {
+        let Ok(mut context) = HeadlessContext::new(64, 48) else {
+            eprintln!("no graphics adapter, skipping");
+            return;
+        };
+        let point = AABB::point([0.0, 0.0, 5.0]);
+        let flat = AABB {
+            min: [-1.0, 0.0, 4.0],
+            max: [1.0, 0.0, 6.0],
+        };
+        context.render(|canvas| {
+            for aabb in [point, flat] {
+                place(canvas.draw(Box), &aabb);
+                place(canvas.draw(BoxLines), &aabb);
+            }
+        });
     }
 }
@@ -0,0 +1,83 @@
+//! Prints the stats of every loader and of the R-tree, on random boxes, a
+//! saved OMT or a dataset read with [`rtrees::dataset::load`].
+
+use std::{env, path::Path};
+
+use rtrees::dataset;
+use rtrees::generate::uniform_aabbs;
+use rtrees::mapped::MappedRTree;
+use rtrees::omt::{Leaf, Loader, RTree as Omt, AABB};
+use rtrees::rtree::RTree;
+use rtrees::stats::TreeStats;
+
+const BOUNDS: AABB = AABB {
+    min: [-10.0, -10.0, -10.0],
+    max: [10.0, 10.0, 10.0],
+};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: tree_stats [random [COUNT [SEED]] | FILE.omt \
+         | FILE.{{csv,xyz,ply,obj}}]"
+    );
+    std::process::exit(1)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let aabbs = match args.next().as_deref() {
+        None | Some("random") => {
+            let mut number = |default| {
+                args.next()
+                    .map_or(default, |a| a.parse().unwrap_or_else(|_| usage()))
+            };
+            let count = number(10000);
+            let seed = number(0);
+            uniform_aabbs(seed as u64, count, BOUNDS, 0.01..0.1)
+        }
+        Some(path)
+            if Path::new(path).extension().is_some_and(|e| e == "omt") =>
+        {
+            let tree = MappedRTree::<()>::open(path).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                std::process::exit(1)
+            });
+            print_stats(path, &tree.stats());
+            tree.leaf_aabbs().to_vec()
+        }
+        Some(path) => {
+            let mut aabbs = dataset::load(path).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                std::process::exit(1)
+            });
+            dataset::fit_into(&mut aabbs, BOUNDS);
+            aabbs
+        }
+    };
+
+    let leaves: Vec<_> = aabbs.iter().map(|&a| Leaf::new_empty(a)).collect();
+    for loader in Loader::ALL {
+        let tree = Omt::with_loader(leaves.clone(), loader);
+        print_stats(&format!("{loader:?}"), &tree.stats());
+    }
+    let mut rtree = RTree::new();
+    for aabb in aabbs {
+        rtree.insert(aabb, ());
+    }
+    print_stats("RTree", &rtree.stats());
+}
+
+fn print_stats(name: &str, stats: &TreeStats) {
+    println!("{name}");
+    println!("  height        {}", stats.height);
+    println!("  nodes         {}", stats.node_count);
+    println!("  leaves        {}", stats.leaf_count);
+    println!(
+        "  children      {}..={} (fill factor {:.3})",
+        stats.min_children, stats.max_children, stats.fill_factor
+    );
+    println!("  node volume   {:.3}", stats.node_volume);
+    println!("  overlap       {:.3}", stats.overlap);
+    println!("  dead space    {:.3}", stats.dead_space);
+    println!("  SAH cost      {:.3}", stats.sah_cost);
+}
@@ -0,0 +1,274 @@
+//! An [`omt::RTree`] that accepts inserts and removes after it is built.
+//!
+//! New leaves go into a small dynamic [`rtree::RTree`], the delta, and
+//! removed leaves of the packed tree are only marked. Queries look at both
+//! parts, and once the delta plus the removed leaves grow beyond a threshold
+//! everything is bulk loaded into a new packed tree.
+//!
+//! [`omt::RTree`]: crate::omt::RTree
+//! [`rtree::RTree`]: crate::rtree::RTree
+
+use crate::join::Traverse;
+use crate::omt::{self, Leaf, Loader, Query, AABB};
+use crate::rtree;
+
+/// The default of [`BufferedRTree::max_pending`].
+pub const DEFAULT_MAX_PENDING: usize = 1024;
+
+pub struct BufferedRTree<T> {
+    packed: omt::RTree<T>,
+    /// Parallel to the leaves of `packed`.
+    removed: Vec<bool>,
+    removed_count: usize,
+    delta: rtree::RTree<T>,
+    delta_count: usize,
+    loader: Loader,
+    max_pending: usize,
+}
+
+impl<T> BufferedRTree<T> {
+    pub fn new(leaves: Vec<Leaf<T>>) -> Self {
+        Self::with_loader(leaves, Loader::Omt)
+    }
+
+    /// Uses `loader` for the initial tree and every rebuild.
+    pub fn with_loader(leaves: Vec<Leaf<T>>, loader: Loader) -> Self {
+        let packed = omt::RTree::with_loader(leaves, loader);
+        Self {
+            removed: vec![false; packed.leaves.len()],
+            packed,
+            removed_count: 0,
+            delta: rtree::RTree::new(),
+            delta_count: 0,
+            loader,
+            max_pending: DEFAULT_MAX_PENDING,
+        }
+    }
+
+    /// Rebuild once more than `max_pending` inserts and removes are
+    /// buffered, or more than a quarter of the packed leaves if that is
+    /// larger.
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    pub fn insert(&mut self, leaf: Leaf<T>) {
+        self.delta.insert(leaf.aabb, leaf.data);
+        self.delta_count += 1;
+        self.rebuild_if_needed();
+    }
+
+    /// Removes a leaf with exactly `aabb` whose data `matches`, returns
+    /// whether there was one.
+    pub fn remove(
+        &mut self,
+        aabb: &AABB,
+        mut matches: impl FnMut(&T) -> bool,
+    ) -> bool {
+        if self.delta.remove(aabb, &mut matches).is_some() {
+            self.delta_count -= 1;
+            return true;
+        }
+        let Some(index) = self.find_packed(aabb, &mut matches) else {
+            return false;
+        };
+        self.removed[index] = true;
+        self.removed_count += 1;
+        self.rebuild_if_needed();
+        true
+    }
+
+    /// The index of a leaf of the packed tree that is not removed yet.
+    fn find_packed(
+        &self,
+        aabb: &AABB,
+        matches: &mut impl FnMut(&T) -> bool,
+    ) -> Option<usize> {
+        let tree = &self.packed;
+        let leaf_layer = tree.layers.len();
+        let mut stack = vec![(0, 0)];
+        let mut children = Vec::new();
+        while let Some(node) = stack.pop() {
+            if !tree.aabb(node).overlaps(aabb) {
+                continue;
+            }
+            children.clear();
+            if tree.children(node, &mut children) {
+                stack.extend_from_slice(&children);
+                continue;
+            }
+            let (layer, index) = node;
+            debug_assert_eq!(layer, leaf_layer);
+            let leaf = &tree.leaves[index];
+            if !self.removed[index] && leaf.aabb == *aabb && matches(&leaf.data)
+            {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn rebuild_if_needed(&mut self) {
+        let threshold = self.max_pending.max(self.packed.leaves.len() / 4);
+        if self.delta_count + self.removed_count > threshold {
+            self.rebuild();
+        }
+    }
+
+    /// Bulk loads the remaining packed leaves and the delta into a new
+    /// packed tree.
+    pub fn rebuild(&mut self) {
+        let packed_leaves = std::mem::take(&mut self.packed.leaves);
+        let delta = std::mem::replace(&mut self.delta, rtree::RTree::new());
+        let mut leaves: Vec<Leaf<T>> = packed_leaves
+            .into_iter()
+            .zip(&self.removed)
+            .filter(|(_, &removed)| !removed)
+            .map(|(leaf, _)| leaf)
+            .collect();
+        leaves.extend(
+            delta
+                .into_leaves()
+                .into_iter()
+                .map(|(aabb, data)| Leaf::new(aabb, data)),
+        );
+        *self = Self::with_loader(leaves, self.loader)
+            .max_pending(self.max_pending);
+    }
+
+    /// All nodes and leaves overlapping `aabb`, the nodes are those of the
+    /// packed tree, the leaves come from both parts.
+    pub fn query(&self, aabb: AABB) -> Query<'_, T> {
+        self.packed
+            .query(aabb)
+            .with_delta(&self.removed, &self.delta)
+    }
+
+    /// All leaves, in no particular order.
+    pub fn leaves(&self) -> impl Iterator<Item = (&AABB, &T)> {
+        let packed = self
+            .packed
+            .leaves
+            .iter()
+            .zip(&self.removed)
+            .filter(|(_, &removed)| !removed)
+            .map(|(leaf, _)| (&leaf.aabb, &leaf.data));
+        let everything = AABB {
+            min: [f64::NEG_INFINITY; 3],
+            max: [f64::INFINITY; 3],
+        };
+        packed.chain(self.delta.search_iter(&everything))
+    }
+
+    pub fn len(&self) -> usize {
+        self.packed.leaves.len() - self.removed_count + self.delta_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Inserts and removes buffered since the last rebuild.
+    pub fn pending(&self) -> usize {
+        self.delta_count + self.removed_count
+    }
+
+    /// The packed part, including leaves that are marked as removed.
+    pub fn packed(&self) -> &omt::RTree<T> {
+        &self.packed
+    }
+
+    /// Heap memory used by both parts, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.packed.memory_usage()
+            + self.removed.capacity()
+            + self.delta.memory_usage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::generate::{rng, uniform_aabbs};
+
+    const BOUNDS: AABB = AABB {
+        min: [-10.0, -10.0, -10.0],
+        max: [10.0, 10.0, 10.0],
+    };
+
+    fn sorted_query(tree: &BufferedRTree<usize>, query: AABB) -> Vec<usize> {
+        let mut found: Vec<usize> = tree
+            .query(query)
+            .filter_map(|item| match item.data {
+                omt::QueryData::Leaf { data } => Some(*data),
+                omt::QueryData::Node { .. } => None,
+            })
+            .collect();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut rng = rng(1);
+        let aabbs = uniform_aabbs(2, 3000, BOUNDS, 0.2..1.0);
+        let queries = uniform_aabbs(3, 20, BOUNDS, 1.0..4.0);
+        let leaves = (0..1000).map(|i| Leaf::new(aabbs[i], i)).collect();
+        let mut tree = BufferedRTree::new(leaves).max_pending(100);
+        // whether each of the aabbs should be in the tree
+        let mut present: Vec<bool> =
+            (0..aabbs.len()).map(|i| i < 1000).collect();
+        let mut next = 1000;
+        let mut rebuilds = 0;
+        for step in 0..2000 {
+            let pending = tree.pending();
+            if rng.gen_bool(0.6) && next < aabbs.len() {
+                tree.insert(Leaf::new(aabbs[next], next));
+                present[next] = true;
+                next += 1;
+            } else {
+                let i = rng.gen_range(0..next);
+                let removed = tree.remove(&aabbs[i], |&data| data == i);
+                assert_eq!(removed, present[i]);
+                present[i] = false;
+            }
+            rebuilds += (tree.pending() + 1 < pending) as usize;
+            assert_eq!(tree.len(), present.iter().filter(|&&p| p).count());
+            if step % 100 == 0 {
+                for &query in &queries {
+                    let expected: Vec<usize> = (0..aabbs.len())
+                        .filter(|&i| present[i] && aabbs[i].overlaps(&query))
+                        .collect();
+                    assert_eq!(sorted_query(&tree, query), expected);
+                }
+            }
+        }
+        assert!(rebuilds > 0);
+        let mut all: Vec<usize> = tree.leaves().map(|(_, &i)| i).collect();
+        all.sort_unstable();
+        let expected: Vec<usize> =
+            (0..aabbs.len()).filter(|&i| present[i]).collect();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn rebuild_keeps_leaves() {
+        let aabbs = uniform_aabbs(4, 50, BOUNDS, 0.2..1.0);
+        let mut tree = BufferedRTree::new(vec![]);
+        for (i, &aabb) in aabbs.iter().enumerate() {
+            tree.insert(Leaf::new(aabb, i));
+        }
+        assert!(tree.remove(&aabbs[3], |&i| i == 3));
+        assert!(!tree.remove(&aabbs[3], |&i| i == 3));
+        assert_eq!(tree.pending(), 49);
+        tree.rebuild();
+        assert_eq!(tree.pending(), 0);
+        assert_eq!(tree.packed().leaves().count(), 49);
+        assert!(tree.remove(&aabbs[4], |&i| i == 4));
+        assert_eq!(tree.len(), 48);
+        assert_eq!(sorted_query(&tree, BOUNDS).len(), 48);
+    }
+}
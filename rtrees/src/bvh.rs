@@ -0,0 +1,640 @@
+//! A bounding volume hierarchy over the triangles of a mesh, built with the
+//! binned surface area heuristic.
+//!
+//! The BVH keeps its own copy of the triangle positions, after the vertices
+//! of the mesh move [`Bvh::refit`] updates the bounds without rebuilding
+//! the hierarchy.
+
+use std::ops::Range;
+
+use graphics::mesh::{Mesh, PNUVertex, PNVertex, Vertex};
+
+use crate::omt::AABB;
+
+/// Triangles per leaf below which a node is never split.
+const MAX_LEAF_SIZE: usize = 4;
+const BIN_COUNT: usize = 16;
+/// Cost of testing a node's AABB relative to testing a triangle.
+const TRAVERSAL_COST: f64 = 1.0;
+
+type Triangle = [[f64; 3]; 3];
+
+/// A vertex of a triangle mesh that a [`Bvh`] can index.
+pub trait HasPosition: Vertex {
+    fn position(&self) -> [f32; 3];
+}
+
+impl HasPosition for PNVertex {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+impl HasPosition for PNUVertex {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+pub struct Bvh {
+    /// `nodes[0]` is the root, if there is a triangle at all.
+    nodes: Vec<BvhNode>,
+    /// Triangle positions in leaf order.
+    triangles: Vec<Triangle>,
+    /// For every entry of `triangles`, its index in the mesh, the
+    /// `triangle`th group of three indices.
+    mesh_triangles: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct BvhNode {
+    aabb: AABB,
+    /// For leaves the first triangle, for inner nodes the first of the two
+    /// children, the other one is at `start + 1`.
+    start: usize,
+    /// Number of triangles, 0 for inner nodes.
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub start: [f64; 3],
+    /// Need not be normalized, distances along the ray are measured in
+    /// multiples of it.
+    pub direction: [f64; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// Index of the triangle in the mesh.
+    pub triangle: usize,
+    /// The hit is at `start + t * direction`.
+    pub t: f64,
+    /// Weights of the three corners of the triangle at the hit, they add up
+    /// to 1.
+    pub barycentric: [f64; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClosestPoint {
+    /// Index of the triangle in the mesh.
+    pub triangle: usize,
+    pub point: [f64; 3],
+    pub distance: f64,
+    /// Weights of the three corners of the triangle at `point`.
+    pub barycentric: [f64; 3],
+}
+
+impl Bvh {
+    pub fn new<V: HasPosition>(mesh: &Mesh<V>) -> Self {
+        let triangles = mesh_triangles(mesh);
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * triangles.len()),
+            mesh_triangles: (0..triangles.len()).collect(),
+            triangles,
+        };
+        if !bvh.triangles.is_empty() {
+            bvh.nodes.push(BvhNode {
+                aabb: AABB::merge([]),
+                start: 0,
+                count: bvh.triangles.len(),
+            });
+            bvh.build(0);
+        }
+        bvh
+    }
+
+    /// Splits node `index` recursively.
+    fn build(&mut self, index: usize) {
+        let BvhNode { start, count, .. } = self.nodes[index];
+        let range = start..start + count;
+        let aabb = AABB::from_points(
+            self.triangles[range.clone()].iter().flatten().copied(),
+        );
+        self.nodes[index].aabb = aabb;
+        if count <= MAX_LEAF_SIZE {
+            return;
+        }
+        let Some(mid) = self.split(range.clone(), &aabb) else {
+            return;
+        };
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb,
+            start,
+            count: mid - start,
+        });
+        self.nodes.push(BvhNode {
+            aabb,
+            start: mid,
+            count: range.end - mid,
+        });
+        self.nodes[index].start = left;
+        self.nodes[index].count = 0;
+        self.build(left);
+        self.build(left + 1);
+    }
+
+    /// Partitions the triangles in `range` along the cheapest binned SAH
+    /// split, returns where the second half starts, or `None` if keeping
+    /// them in one leaf is cheaper.
+    fn split(&mut self, range: Range<usize>, aabb: &AABB) -> Option<usize> {
+        let centroids = AABB::from_points(
+            self.triangles[range.clone()].iter().map(centroid),
+        );
+        let mut best: Option<(f64, usize, f64)> = None;
+        for axis in 0..3 {
+            let (low, high) = (centroids.min[axis], centroids.max[axis]);
+            if high <= low {
+                continue;
+            }
+            let bin_of = |triangle: &Triangle| {
+                let t = (centroid(triangle)[axis] - low) / (high - low);
+                ((t * BIN_COUNT as f64) as usize).min(BIN_COUNT - 1)
+            };
+            let mut bins = [(AABB::merge([]), 0usize); BIN_COUNT];
+            for triangle in &self.triangles[range.clone()] {
+                let bin = &mut bins[bin_of(triangle)];
+                bin.0 = AABB::merge([&bin.0, &triangle_aabb(triangle)]);
+                bin.1 += 1;
+            }
+            // areas and counts of everything right of every plane
+            let mut right = [(0.0, 0); BIN_COUNT];
+            let (mut aabb, mut count) = (AABB::merge([]), 0);
+            for i in (1..BIN_COUNT).rev() {
+                aabb = AABB::merge([&aabb, &bins[i].0]);
+                count += bins[i].1;
+                right[i] = (area(&aabb), count);
+            }
+            let (mut aabb, mut count) = (AABB::merge([]), 0);
+            for i in 1..BIN_COUNT {
+                aabb = AABB::merge([&aabb, &bins[i - 1].0]);
+                count += bins[i - 1].1;
+                let cost =
+                    area(&aabb) * count as f64 + right[i].0 * right[i].1 as f64;
+                if best.is_none_or(|(c, ..)| cost < c) {
+                    let plane =
+                        low + (high - low) * i as f64 / BIN_COUNT as f64;
+                    best = Some((cost, axis, plane));
+                }
+            }
+        }
+        let (cost, axis, plane) = best?;
+        let leaf_cost = range.len() as f64;
+        let parent_area = area(aabb);
+        if parent_area > 0.0 && TRAVERSAL_COST + cost / parent_area >= leaf_cost
+        {
+            return None;
+        }
+        // partition in place, keeping the mesh indices in step
+        let mut mid = range.start;
+        for i in range.clone() {
+            if centroid(&self.triangles[i])[axis] < plane {
+                self.triangles.swap(i, mid);
+                self.mesh_triangles.swap(i, mid);
+                mid += 1;
+            }
+        }
+        (mid != range.start && mid != range.end).then_some(mid)
+    }
+
+    /// Updates the bounds after the vertex positions of `mesh` changed.
+    ///
+    /// # Panics
+    /// If `mesh` has a different number of triangles than the one the BVH
+    /// was built from.
+    pub fn refit<V: HasPosition>(&mut self, mesh: &Mesh<V>) {
+        let positions = mesh_triangles(mesh);
+        assert_eq!(
+            positions.len(),
+            self.triangles.len(),
+            "the mesh topology changed"
+        );
+        for (triangle, &index) in
+            self.triangles.iter_mut().zip(&self.mesh_triangles)
+        {
+            *triangle = positions[index];
+        }
+        // children are always stored after their parent
+        for index in (0..self.nodes.len()).rev() {
+            let BvhNode { start, count, .. } = self.nodes[index];
+            self.nodes[index].aabb = if count > 0 {
+                AABB::from_points(
+                    self.triangles[start..start + count]
+                        .iter()
+                        .flatten()
+                        .copied(),
+                )
+            } else {
+                AABB::merge([
+                    &self.nodes[start].aabb,
+                    &self.nodes[start + 1].aabb,
+                ])
+            };
+        }
+    }
+
+    /// The bounds of the whole mesh.
+    pub fn aabb(&self) -> Option<&AABB> {
+        self.nodes.first().map(|node| &node.aabb)
+    }
+
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    /// The nearest hit of `ray` with `t` in `0..=max_t`.
+    pub fn intersect_ray(&self, ray: &Ray, max_t: f64) -> Option<RayHit> {
+        let inv_direction = ray.direction.map(|d| 1.0 / d);
+        let mut best: Option<RayHit> = None;
+        let mut stack = Vec::new();
+        if let Some(root) = self.nodes.first() {
+            if let Some(t) = ray_aabb(ray, &inv_direction, &root.aabb, max_t) {
+                stack.push((0, t));
+            }
+        }
+        while let Some((index, t_enter)) = stack.pop() {
+            let max_t = best.map_or(max_t, |hit| hit.t);
+            if t_enter > max_t {
+                continue;
+            }
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                for i in node.start..node.start + node.count {
+                    if let Some((t, barycentric)) =
+                        ray_triangle(ray, &self.triangles[i])
+                    {
+                        if t <= best.map_or(max_t, |hit| hit.t) {
+                            best = Some(RayHit {
+                                triangle: self.mesh_triangles[i],
+                                t,
+                                barycentric,
+                            });
+                        }
+                    }
+                }
+                continue;
+            }
+            let [first, second] = [node.start, node.start + 1].map(|child| {
+                ray_aabb(ray, &inv_direction, &self.nodes[child].aabb, max_t)
+                    .map(|t| (child, t))
+            });
+            // visit the nearer child first, it is pushed last
+            match (first, second) {
+                (Some(a), Some(b)) if a.1 < b.1 => stack.extend([b, a]),
+                (Some(a), Some(b)) => stack.extend([a, b]),
+                (Some(a), None) | (None, Some(a)) => stack.push(a),
+                (None, None) => {}
+            }
+        }
+        best
+    }
+
+    /// The point on the mesh closest to `point`.
+    pub fn closest_point(&self, point: [f64; 3]) -> Option<ClosestPoint> {
+        let mut best: Option<ClosestPoint> = None;
+        let mut stack = vec![];
+        if let Some(root) = self.nodes.first() {
+            stack.push((0, aabb_distance_squared(&root.aabb, point)));
+        }
+        while let Some((index, distance_squared)) = stack.pop() {
+            if best.is_some_and(|b| distance_squared > b.distance * b.distance)
+            {
+                continue;
+            }
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                for i in node.start..node.start + node.count {
+                    let (closest, barycentric) =
+                        closest_on_triangle(point, &self.triangles[i]);
+                    let distance = length(sub(closest, point));
+                    if best.is_none_or(|b| distance < b.distance) {
+                        best = Some(ClosestPoint {
+                            triangle: self.mesh_triangles[i],
+                            point: closest,
+                            distance,
+                            barycentric,
+                        });
+                    }
+                }
+                continue;
+            }
+            let [a, b] = [node.start, node.start + 1].map(|child| {
+                (child, aabb_distance_squared(&self.nodes[child].aabb, point))
+            });
+            if a.1 < b.1 {
+                stack.extend([b, a]);
+            } else {
+                stack.extend([a, b]);
+            }
+        }
+        best
+    }
+}
+
+fn mesh_triangles<V: HasPosition>(mesh: &Mesh<V>) -> Vec<Triangle> {
+    mesh.indices
+        .chunks_exact(3)
+        .map(|corners| {
+            [0, 1, 2].map(|i| {
+                mesh.vertices[corners[i] as usize].position().map(f64::from)
+            })
+        })
+        .collect()
+}
+
+fn triangle_aabb(triangle: &Triangle) -> AABB {
+    AABB::from_points(*triangle)
+}
+
+fn centroid(triangle: &Triangle) -> [f64; 3] {
+    [0, 1, 2].map(|i| (triangle[0][i] + triangle[1][i] + triangle[2][i]) / 3.0)
+}
+
+/// Half the surface area, 0 for the empty AABB.
+fn area(aabb: &AABB) -> f64 {
+    let [w, h, d] = aabb.size().map(|s| s.max(0.0));
+    w * h + h * d + d * w
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| a[i] - b[i])
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn length(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+/// Where `ray` enters `aabb`, if it does so before `max_t`, slab test.
+fn ray_aabb(
+    ray: &Ray,
+    inv_direction: &[f64; 3],
+    aabb: &AABB,
+    max_t: f64,
+) -> Option<f64> {
+    let (mut t_min, mut t_max) = (0.0f64, max_t);
+    for (i, inv_direction) in inv_direction.iter().enumerate() {
+        let t1 = (aabb.min[i] - ray.start[i]) * inv_direction;
+        let t2 = (aabb.max[i] - ray.start[i]) * inv_direction;
+        // NaN from 0 * inf, a ray in the plane of a face, is ignored by
+        // min/max
+        t_min = t_min.max(t1.min(t2));
+        t_max = t_max.min(t1.max(t2));
+    }
+    (t_min <= t_max).then_some(t_min)
+}
+
+/// Möller–Trumbore, returns `t` and the barycentric coordinates of the hit,
+/// both sides of the triangle are hit.
+fn ray_triangle(ray: &Ray, [a, b, c]: &Triangle) -> Option<(f64, [f64; 3])> {
+    let edge1 = sub(*b, *a);
+    let edge2 = sub(*c, *a);
+    let p = cross(ray.direction, edge2);
+    let determinant = dot(edge1, p);
+    if determinant.abs() < f64::EPSILON {
+        return None;
+    }
+    let inv_determinant = 1.0 / determinant;
+    let s = sub(ray.start, *a);
+    let u = dot(s, p) * inv_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = cross(s, edge1);
+    let v = dot(ray.direction, q) * inv_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = dot(edge2, q) * inv_determinant;
+    (t >= 0.0).then_some((t, [1.0 - u - v, u, v]))
+}
+
+fn aabb_distance_squared(aabb: &AABB, point: [f64; 3]) -> f64 {
+    (0..3)
+        .map(|i| {
+            let d = (aabb.min[i] - point[i]).max(point[i] - aabb.max[i]);
+            d.max(0.0).powi(2)
+        })
+        .sum()
+}
+
+/// The point of the triangle closest to `p` and its barycentric
+/// coordinates, from Ericson's Real-Time Collision Detection.
+fn closest_on_triangle(
+    p: [f64; 3],
+    [a, b, c]: &Triangle,
+) -> ([f64; 3], [f64; 3]) {
+    let (a, b, c) = (*a, *b, *c);
+    let at = |w: [f64; 3]| {
+        let point = [0, 1, 2].map(|i| w[0] * a[i] + w[1] * b[i] + w[2] * c[i]);
+        (point, w)
+    };
+    let ab = sub(b, a);
+    let ac = sub(c, a);
+    let ap = sub(p, a);
+    let d1 = dot(ab, ap);
+    let d2 = dot(ac, ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return at([1.0, 0.0, 0.0]);
+    }
+    let bp = sub(p, b);
+    let d3 = dot(ab, bp);
+    let d4 = dot(ac, bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return at([0.0, 1.0, 0.0]);
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return at([1.0 - v, v, 0.0]);
+    }
+    let cp = sub(p, c);
+    let d5 = dot(ab, cp);
+    let d6 = dot(ac, cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return at([0.0, 0.0, 1.0]);
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return at([1.0 - w, 0.0, w]);
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return at([0.0, 1.0 - w, w]);
+    }
+    let denominator = 1.0 / (va + vb + vc);
+    let v = vb * denominator;
+    let w = vc * denominator;
+    at([1.0 - v - w, v, w])
+}
+
+#[cfg(test)]
+mod tests {
+    use graphics::geometry::{Ellipsoid, ParametricSquare};
+    use graphics::mesh::MeshProvider;
+
+    use super::*;
+    use crate::generate::rng;
+    use rand::Rng;
+
+    fn terrain() -> Mesh<PNUVertex> {
+        ParametricSquare::new(30, |x, y| {
+            let (x, y) = (x * 10.0 - 5.0, y * 10.0 - 5.0);
+            (x, (x * 1.3).sin() + (y * 0.7).cos(), y)
+        })
+        .create_mesh()
+    }
+
+    fn brute_force_ray(
+        mesh: &Mesh<impl HasPosition>,
+        ray: &Ray,
+    ) -> Option<RayHit> {
+        mesh_triangles(mesh)
+            .iter()
+            .enumerate()
+            .filter_map(|(triangle, positions)| {
+                let (t, barycentric) = ray_triangle(ray, positions)?;
+                Some(RayHit {
+                    triangle,
+                    t,
+                    barycentric,
+                })
+            })
+            .min_by(|a, b| a.t.total_cmp(&b.t))
+    }
+
+    fn random_point(rng: &mut impl Rng, extent: f64) -> [f64; 3] {
+        [(); 3].map(|_| rng.gen_range(-extent..extent))
+    }
+
+    #[test]
+    fn rays_match_brute_force() {
+        let mut rng = rng(1);
+        for mesh in [terrain(), Ellipsoid.create_mesh()] {
+            let bvh = Bvh::new(&mesh);
+            assert_eq!(bvh.len(), mesh.indices.len() / 3);
+            let leaves = bvh.nodes.iter().filter(|node| node.count > 0);
+            assert!(leaves.clone().count() > 1);
+            assert!(leaves.clone().all(|node| node.count <= 16));
+            for _ in 0..200 {
+                let start = random_point(&mut rng, 8.0);
+                let target = random_point(&mut rng, 1.0);
+                let ray = Ray {
+                    start,
+                    direction: sub(target, start),
+                };
+                let expected = brute_force_ray(&mesh, &ray);
+                let hit = bvh.intersect_ray(&ray, f64::INFINITY);
+                assert_eq!(hit.map(|h| h.t), expected.map(|h| h.t));
+                if let Some(hit) = hit {
+                    let weights: f64 = hit.barycentric.iter().sum();
+                    assert!((weights - 1.0).abs() < 1e-9);
+                    assert!(hit.barycentric.iter().all(|&w| w >= -1e-9));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn indexes_meshes_without_texture_coordinates() {
+        let mut rng = rng(3);
+        let terrain = terrain();
+        let mesh = Mesh {
+            vertices: terrain
+                .vertices
+                .iter()
+                .map(|v| PNVertex {
+                    position: v.position,
+                    normal: v.normal,
+                })
+                .collect(),
+            indices: terrain.indices.clone(),
+        };
+        let bvh = Bvh::new(&mesh);
+        assert_eq!(bvh.len(), Bvh::new(&terrain).len());
+        for _ in 0..100 {
+            let start = random_point(&mut rng, 8.0);
+            let ray = Ray {
+                start,
+                direction: sub(random_point(&mut rng, 1.0), start),
+            };
+            let hit = bvh.intersect_ray(&ray, f64::INFINITY);
+            let expected = brute_force_ray(&mesh, &ray);
+            assert_eq!(hit.map(|h| h.t), expected.map(|h| h.t));
+        }
+    }
+
+    #[test]
+    fn closest_points_match_brute_force() {
+        let mut rng = rng(2);
+        let mesh = terrain();
+        let bvh = Bvh::new(&mesh);
+        let triangles = mesh_triangles(&mesh);
+        for _ in 0..200 {
+            let point = random_point(&mut rng, 8.0);
+            let expected = triangles
+                .iter()
+                .map(|t| length(sub(closest_on_triangle(point, t).0, point)))
+                .fold(f64::INFINITY, f64::min);
+            let closest = bvh.closest_point(point).unwrap();
+            assert!((closest.distance - expected).abs() < 1e-9);
+            let [a, b, c] = triangles[closest.triangle];
+            let w = closest.barycentric;
+            let from_barycentric =
+                [0, 1, 2].map(|i| w[0] * a[i] + w[1] * b[i] + w[2] * c[i]);
+            assert!(length(sub(from_barycentric, closest.point)) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn refit_follows_moved_vertices() {
+        let mut mesh = Ellipsoid.create_mesh();
+        let mut bvh = Bvh::new(&mesh);
+        let ray = Ray {
+            start: [0.0, 0.0, -10.0],
+            direction: [0.0, 0.0, 1.0],
+        };
+        let before = bvh.intersect_ray(&ray, f64::INFINITY).unwrap();
+        for vertex in &mut mesh.vertices {
+            vertex.position[2] += 3.0;
+        }
+        bvh.refit(&mesh);
+        let after = bvh.intersect_ray(&ray, f64::INFINITY).unwrap();
+        assert!((after.t - before.t - 3.0).abs() < 1e-6);
+        let root = bvh.aabb().unwrap();
+        assert!(root.min[2] > 1.5);
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let bvh = Bvh::new(&Mesh::<PNVertex> {
+            vertices: vec![],
+            indices: vec![],
+        });
+        assert!(bvh.is_empty() && bvh.aabb().is_none());
+        assert!(bvh.closest_point([0.0; 3]).is_none());
+        let ray = Ray {
+            start: [0.0; 3],
+            direction: [1.0, 0.0, 0.0],
+        };
+        assert!(bvh.intersect_ray(&ray, f64::INFINITY).is_none());
+    }
+}
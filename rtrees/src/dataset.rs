@@ -0,0 +1,731 @@
+//! Loading real datasets from files, as boxes, points or triangles.
+//!
+//! | extension | contents                                          |
+//! |-----------|---------------------------------------------------|
+//! | `csv`     | boxes, `min_x,min_y,min_z,max_x,max_y,max_z`      |
+//! | `xyz`     | points, `x y z`, further columns are ignored      |
+//! | `ply`     | points, the `vertex` element, ascii or binary     |
+//! | `obj`     | triangles, the faces split into triangle fans     |
+//!
+//! Empty lines and lines starting with `#` are skipped in the line based
+//! formats.
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+use crate::omt::AABB;
+
+pub type Point = [f64; 3];
+pub type Triangle = [Point; 3];
+
+#[derive(Debug)]
+pub enum DatasetError {
+    Io(io::Error),
+    /// The file extension is not one of the supported formats.
+    UnknownFormat(String),
+    /// A line of the file could not be parsed, lines start at 1.
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// The file is not valid, without a specific line to blame.
+    Invalid(String),
+    /// The file ended before all of the data it declares.
+    UnexpectedEof,
+}
+
+impl Display for DatasetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::UnknownFormat(extension) => {
+                write!(f, "unknown dataset format {extension:?}")
+            }
+            Self::Parse { line, message } => {
+                write!(f, "line {line}: {message}")
+            }
+            Self::Invalid(message) => write!(f, "{message}"),
+            Self::UnexpectedEof => write!(f, "unexpected end of file"),
+        }
+    }
+}
+
+impl Error for DatasetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DatasetError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            Self::UnexpectedEof
+        } else {
+            Self::Io(e)
+        }
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> DatasetError {
+    DatasetError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Loads the AABBs of a dataset, picking the format by the file extension.
+///
+/// Points become zero sized AABBs and triangles their bounding boxes.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<AABB>, DatasetError> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let open = || File::open(path).map(BufReader::new);
+    let aabbs = match extension.as_str() {
+        "csv" => read_csv(open()?)?,
+        "xyz" => read_xyz(open()?)?.into_iter().map(AABB::point).collect(),
+        "ply" => read_ply(open()?)?.into_iter().map(AABB::point).collect(),
+        "obj" => read_obj(open()?)?
+            .into_iter()
+            .map(AABB::from_points)
+            .collect(),
+        _ => return Err(DatasetError::UnknownFormat(extension)),
+    };
+    Ok(aabbs)
+}
+
+/// The non-empty, non-comment lines with their line numbers.
+fn lines(
+    reader: impl BufRead,
+) -> impl Iterator<Item = Result<(usize, String), DatasetError>> {
+    reader
+        .lines()
+        .enumerate()
+        .map(|(i, line)| Ok((i + 1, line?)))
+        .filter(|line| {
+            line.as_ref().map_or(true, |(_, line)| {
+                let line = line.trim();
+                !line.is_empty() && !line.starts_with('#')
+            })
+        })
+}
+
+fn parse_f64(line: usize, token: &str) -> Result<f64, DatasetError> {
+    match token.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(parse_error(line, format!("invalid number {token:?}"))),
+    }
+}
+
+/// Reads boxes from comma separated `min_x,min_y,min_z,max_x,max_y,max_z`
+/// lines, an optional header line is skipped.
+pub fn read_csv(reader: impl BufRead) -> Result<Vec<AABB>, DatasetError> {
+    let mut aabbs = Vec::new();
+    for (index, line) in lines(reader).enumerate() {
+        let (line_number, line) = line?;
+        let fields: Vec<&str> = line.split(',').collect();
+        let is_header = index == 0
+            && fields.iter().all(|f| f.trim().parse::<f64>().is_err());
+        if is_header {
+            continue;
+        }
+        if fields.len() != 6 {
+            return Err(parse_error(
+                line_number,
+                format!("expected 6 fields, found {}", fields.len()),
+            ));
+        }
+        let mut values = [0.0; 6];
+        for (value, field) in values.iter_mut().zip(&fields) {
+            *value = parse_f64(line_number, field)?;
+        }
+        let aabb = AABB {
+            min: [values[0], values[1], values[2]],
+            max: [values[3], values[4], values[5]],
+        };
+        if (0..3).any(|i| aabb.min[i] > aabb.max[i]) {
+            return Err(parse_error(line_number, "min is greater than max"));
+        }
+        aabbs.push(aabb);
+    }
+    Ok(aabbs)
+}
+
+/// Reads points from whitespace separated `x y z` lines, further columns
+/// such as colours or normals are ignored.
+pub fn read_xyz(reader: impl BufRead) -> Result<Vec<Point>, DatasetError> {
+    let mut points = Vec::new();
+    for line in lines(reader) {
+        let (line_number, line) = line?;
+        let mut tokens = line.split_whitespace();
+        let mut point = [0.0; 3];
+        for coordinate in &mut point {
+            let token = tokens.next().ok_or_else(|| {
+                parse_error(line_number, "expected 3 coordinates")
+            })?;
+            *coordinate = parse_f64(line_number, token)?;
+        }
+        points.push(point);
+    }
+    Ok(points)
+}
+
+/// Reads the triangles of the faces of a Wavefront OBJ mesh, polygons are
+/// split into a fan of triangles.
+///
+/// Only `v` and `f` statements are used, texture coordinates and normals
+/// in the face indices are ignored.
+pub fn read_obj(reader: impl BufRead) -> Result<Vec<Triangle>, DatasetError> {
+    let mut vertices: Vec<Point> = Vec::new();
+    let mut triangles = Vec::new();
+    let mut face = Vec::new();
+    for line in lines(reader) {
+        let (line_number, line) = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let mut vertex = [0.0; 3];
+                for coordinate in &mut vertex {
+                    let token = tokens.next().ok_or_else(|| {
+                        parse_error(line_number, "expected 3 coordinates")
+                    })?;
+                    *coordinate = parse_f64(line_number, token)?;
+                }
+                vertices.push(vertex);
+            }
+            Some("f") => {
+                face.clear();
+                for token in tokens {
+                    let index = obj_index(line_number, token, vertices.len())?;
+                    face.push(vertices[index]);
+                }
+                if face.len() < 3 {
+                    return Err(parse_error(
+                        line_number,
+                        "a face needs at least 3 vertices",
+                    ));
+                }
+                for i in 1..face.len() - 1 {
+                    triangles.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+/// The zero based vertex index of a `v`, `v/vt`, `v//vn` or `v/vt/vn`
+/// face token, negative indices count back from the last vertex.
+fn obj_index(
+    line: usize,
+    token: &str,
+    vertex_count: usize,
+) -> Result<usize, DatasetError> {
+    let index = token.split('/').next().unwrap_or_default();
+    let index: i64 = index
+        .parse()
+        .map_err(|_| parse_error(line, format!("invalid index {token:?}")))?;
+    let resolved = match index {
+        1.. => index - 1,
+        ..=-1 => vertex_count as i64 + index,
+        0 => -1,
+    };
+    if resolved < 0 || resolved >= vertex_count as i64 {
+        return Err(parse_error(
+            line,
+            format!("index {index} out of range of {vertex_count} vertices"),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(line: usize, name: &str) -> Result<Self, DatasetError> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => {
+                return Err(parse_error(line, format!("unknown type {name:?}")))
+            }
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn read(
+        self,
+        reader: &mut impl Read,
+        format: PlyFormat,
+    ) -> Result<f64, DatasetError> {
+        let mut bytes = [0; 8];
+        let bytes = &mut bytes[..self.size()];
+        reader.read_exact(bytes)?;
+        if format == PlyFormat::BigEndian {
+            bytes.reverse();
+        }
+        let array = |bytes: &[u8]| {
+            let mut array = [0; 8];
+            array[..bytes.len()].copy_from_slice(bytes);
+            array
+        };
+        let [b0, b1, b2, b3, ..] = array(bytes);
+        let value = match self {
+            Self::I8 => b0 as i8 as f64,
+            Self::U8 => b0 as f64,
+            Self::I16 => i16::from_le_bytes([b0, b1]) as f64,
+            Self::U16 => u16::from_le_bytes([b0, b1]) as f64,
+            Self::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Self::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Self::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Self::F64 => f64::from_le_bytes(array(bytes)),
+        };
+        if !value.is_finite() {
+            return Err(DatasetError::Invalid(format!(
+                "invalid number {value}"
+            )));
+        }
+        Ok(value)
+    }
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar(PlyType),
+    List { count: PlyType, item: PlyType },
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<(String, PlyProperty)>,
+}
+
+/// Reads the points of the `vertex` element of a PLY file, faces and other
+/// elements are skipped.
+pub fn read_ply(mut reader: impl BufRead) -> Result<Vec<Point>, DatasetError> {
+    let (format, elements, mut line_number) = read_ply_header(&mut reader)?;
+    let mut points = Vec::new();
+    let mut values = Vec::new();
+    let mut line = String::new();
+    for element in &elements {
+        let is_vertex = element.name == "vertex";
+        let xyz = if is_vertex {
+            let index = |axis: &str| {
+                element.properties.iter().position(|(name, property)| {
+                    name == axis && matches!(property, PlyProperty::Scalar(_))
+                })
+            };
+            match (index("x"), index("y"), index("z")) {
+                (Some(x), Some(y), Some(z)) => [x, y, z],
+                _ => {
+                    return Err(DatasetError::Invalid(
+                        "vertex element without x, y and z properties".into(),
+                    ))
+                }
+            }
+        } else {
+            [0; 3]
+        };
+        if is_vertex {
+            // the count is untrusted, the points arrive with the data
+            points.reserve(element.count.min(1 << 16));
+        }
+        for _ in 0..element.count {
+            values.clear();
+            if format == PlyFormat::Ascii {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 {
+                    return Err(DatasetError::UnexpectedEof);
+                }
+                line_number += 1;
+                read_ply_ascii(line_number, &line, element, &mut values)?;
+            } else {
+                for (_, property) in &element.properties {
+                    match *property {
+                        PlyProperty::Scalar(ty) => {
+                            values.push(ty.read(&mut reader, format)?)
+                        }
+                        PlyProperty::List { count, item } => {
+                            // lists are counted as a single value, like in
+                            // the ascii format
+                            let count = count.read(&mut reader, format)?;
+                            values.push(f64::NAN);
+                            for _ in 0..count as usize {
+                                item.read(&mut reader, format)?;
+                            }
+                        }
+                    }
+                }
+            }
+            if is_vertex {
+                points.push(xyz.map(|i| values[i]));
+            }
+        }
+    }
+    Ok(points)
+}
+
+/// Parses one element line of an ascii PLY file, lists are counted as a
+/// single value.
+fn read_ply_ascii(
+    line_number: usize,
+    line: &str,
+    element: &PlyElement,
+    values: &mut Vec<f64>,
+) -> Result<(), DatasetError> {
+    let mut tokens = line.split_whitespace();
+    let mut next = || {
+        let token = tokens.next().ok_or_else(|| {
+            parse_error(
+                line_number,
+                format!("too few values for {}", element.name),
+            )
+        })?;
+        parse_f64(line_number, token)
+    };
+    for (_, property) in &element.properties {
+        match property {
+            PlyProperty::Scalar(_) => values.push(next()?),
+            PlyProperty::List { .. } => {
+                let count = next()?;
+                if count < 0.0 || count.fract() != 0.0 {
+                    return Err(parse_error(
+                        line_number,
+                        format!("invalid list length {count}"),
+                    ));
+                }
+                for _ in 0..count as usize {
+                    next()?;
+                }
+                values.push(f64::NAN);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_ply_header(
+    reader: &mut impl BufRead,
+) -> Result<(PlyFormat, Vec<PlyElement>, usize), DatasetError> {
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    let mut line = String::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(DatasetError::UnexpectedEof);
+        }
+        line_number += 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if line_number == 1 {
+            if tokens != ["ply"] {
+                return Err(DatasetError::Invalid("not a PLY file".into()));
+            }
+            continue;
+        }
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::LittleEndian,
+                    "binary_big_endian" => PlyFormat::BigEndian,
+                    _ => {
+                        return Err(parse_error(
+                            line_number,
+                            format!("unknown format {name:?}"),
+                        ))
+                    }
+                })
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| {
+                    parse_error(
+                        line_number,
+                        format!("invalid element count {count:?}"),
+                    )
+                })?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let property = PlyProperty::List {
+                    count: PlyType::parse(line_number, count)?,
+                    item: PlyType::parse(line_number, item)?,
+                };
+                let Some(element) = elements.last_mut() else {
+                    return Err(parse_error(
+                        line_number,
+                        "property before any element",
+                    ));
+                };
+                element.properties.push((name.to_string(), property));
+            }
+            ["property", ty, name] => {
+                let property =
+                    PlyProperty::Scalar(PlyType::parse(line_number, ty)?);
+                let Some(element) = elements.last_mut() else {
+                    return Err(parse_error(
+                        line_number,
+                        "property before any element",
+                    ));
+                };
+                element.properties.push((name.to_string(), property));
+            }
+            ["end_header"] => break,
+            _ => {
+                return Err(parse_error(
+                    line_number,
+                    format!("invalid header line {:?}", line.trim()),
+                ))
+            }
+        }
+    }
+    let format = format.ok_or_else(|| {
+        DatasetError::Invalid("PLY header without a format".into())
+    })?;
+    Ok((format, elements, line_number))
+}
+
+/// Uniformly scales and moves `aabbs` so that together they fill `bounds`
+/// along their largest extent, centered in the other two.
+///
+/// Handy to show or query datasets of any scale with the same camera and
+/// query sizes.
+pub fn fit_into(aabbs: &mut [AABB], bounds: AABB) {
+    if aabbs.is_empty() {
+        return;
+    }
+    let current = AABB::merge(aabbs.iter());
+    let size = current.size();
+    let target = bounds.size();
+    let scale = (0..3)
+        .filter(|&i| size[i] > 0.0)
+        .map(|i| target[i] / size[i])
+        .fold(f64::INFINITY, f64::min);
+    let scale = if scale.is_finite() { scale } else { 1.0 };
+    let (from, to) = (current.pos(), bounds.pos());
+    for aabb in aabbs {
+        for i in 0..3 {
+            aabb.min[i] = (aabb.min[i] - from[i]) * scale + to[i];
+            aabb.max[i] = (aabb.max[i] - from[i]) * scale + to[i];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv() {
+        let input = "min_x,min_y,min_z,max_x,max_y,max_z\n\
+                     # a comment\n\
+                     0,0,0,1,1,1\n\
+                     \n\
+                     -1.5, 2, 3, -1, 2.5, 4\n";
+        let aabbs = read_csv(input.as_bytes()).unwrap();
+        assert_eq!(
+            aabbs,
+            [
+                AABB {
+                    min: [0.0; 3],
+                    max: [1.0; 3]
+                },
+                AABB {
+                    min: [-1.5, 2.0, 3.0],
+                    max: [-1.0, 2.5, 4.0]
+                },
+            ]
+        );
+        for (input, line) in [
+            ("0,0,0,1,1\n", 1),
+            ("0,0,0,1,1,1\n0,0,0,1,x,1\n", 2),
+            ("0,0,0,1,1,1\n1,0,0,0,1,1\n", 2),
+            ("0,0,0,1,1,NaN\n", 1),
+        ] {
+            assert!(
+                matches!(
+                    read_csv(input.as_bytes()),
+                    Err(DatasetError::Parse { line: l, .. }) if l == line
+                ),
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn xyz() {
+        let input = "1 2 3\n4.5 5 6 255 0 0\n";
+        assert_eq!(
+            read_xyz(input.as_bytes()).unwrap(),
+            [[1.0, 2.0, 3.0], [4.5, 5.0, 6.0]]
+        );
+        assert!(matches!(
+            read_xyz("1 2 3\n4 5\n".as_bytes()),
+            Err(DatasetError::Parse { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn obj() {
+        let input = "# a quad and a triangle\n\
+                     v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                     vn 0 0 1\n\
+                     f 1//1 2//1 3//1 4//1\n\
+                     f -1 -2 -3\n";
+        let triangles = read_obj(input.as_bytes()).unwrap();
+        assert_eq!(triangles.len(), 3);
+        assert_eq!(
+            triangles[2],
+            [[0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0]]
+        );
+        for input in ["v 0 0 0\nf 1 2 3\n", "v 0 0 0\nf 1 1\n", "f 0 0 0\n"] {
+            assert!(
+                matches!(
+                    read_obj(input.as_bytes()),
+                    Err(DatasetError::Parse { .. })
+                ),
+                "{input:?}"
+            );
+        }
+    }
+
+    const PLY_HEADER: &str = "ply\n\
+        format {format} 1.0\n\
+        comment made by hand\n\
+        element vertex 2\n\
+        property float x\n\
+        property float y\n\
+        property double z\n\
+        property uchar red\n\
+        element face 1\n\
+        property list uchar int vertex_indices\n\
+        end_header\n";
+
+    #[test]
+    fn ply() {
+        let expected = [[1.0, 2.0, 3.0], [-4.0, 5.5, 6.0]];
+        let ascii = PLY_HEADER.replace("{format}", "ascii")
+            + "1 2 3 255\n-4 5.5 6 0\n3 0 1 1\n";
+        assert_eq!(read_ply(ascii.as_bytes()).unwrap(), expected);
+
+        for (format, big_endian) in
+            [("binary_little_endian", false), ("binary_big_endian", true)]
+        {
+            let mut bytes = PLY_HEADER.replace("{format}", format).into_bytes();
+            let mut push = |value: &[u8]| {
+                let mut value = value.to_vec();
+                if big_endian {
+                    value.reverse();
+                }
+                bytes.extend(value);
+            };
+            for [x, y, z] in expected {
+                push(&(x as f32).to_le_bytes());
+                push(&(y as f32).to_le_bytes());
+                push(&z.to_le_bytes());
+                push(&[7]);
+            }
+            push(&[3]);
+            for index in [0i32, 1, 1] {
+                push(&index.to_le_bytes());
+            }
+            assert_eq!(read_ply(bytes.as_slice()).unwrap(), expected);
+            let mut infinite = bytes.clone();
+            let x = PLY_HEADER.replace("{format}", format).len();
+            let mut value = f32::INFINITY.to_le_bytes();
+            if big_endian {
+                value.reverse();
+            }
+            infinite[x..x + 4].copy_from_slice(&value);
+            assert!(matches!(
+                read_ply(infinite.as_slice()),
+                Err(DatasetError::Invalid(_))
+            ));
+            bytes.truncate(bytes.len() - 1);
+            assert!(matches!(
+                read_ply(bytes.as_slice()),
+                Err(DatasetError::UnexpectedEof)
+            ));
+        }
+
+        let bad_value = PLY_HEADER.replace("{format}", "ascii") + "1 2 x 0\n";
+        assert!(matches!(
+            read_ply(bad_value.as_bytes()),
+            Err(DatasetError::Parse { line: 12, .. })
+        ));
+        let no_z = PLY_HEADER
+            .replace("{format}", "ascii")
+            .replace("double z", "double w");
+        assert!(matches!(
+            read_ply(no_z.as_bytes()),
+            Err(DatasetError::Invalid(_))
+        ));
+        assert!(matches!(
+            read_ply("obj\n".as_bytes()),
+            Err(DatasetError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn fit() {
+        let bounds = AABB {
+            min: [-10.0; 3],
+            max: [10.0; 3],
+        };
+        let mut aabbs = [
+            AABB::point([100.0, 0.0, 0.0]),
+            AABB::point([300.0, 10.0, 0.0]),
+        ];
+        fit_into(&mut aabbs, bounds);
+        assert_eq!(aabbs[0], AABB::point([-10.0, -0.5, 0.0]));
+        assert_eq!(aabbs[1], AABB::point([10.0, 0.5, 0.0]));
+    }
+}
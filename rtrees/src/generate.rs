@@ -0,0 +1,388 @@
+//! Reproducible random AABBs for tests and benchmarks.
+//!
+//! ```
+//! use rtrees::generate::{Distribution, Generator};
+//! use rtrees::omt::AABB;
+//!
+//! let bounds = AABB { min: [-10.0; 3], max: [10.0; 3] };
+//! let clusters = Distribution::Clusters {
+//!     clusters: 5,
+//!     std_dev: 1.0,
+//!     size: 0.01..0.1,
+//! };
+//! let generate = |seed| -> Vec<_> {
+//!     Generator::seeded(seed, bounds, clusters.clone())
+//!         .unwrap()
+//!         .take(10)
+//!         .collect()
+//! };
+//! assert_eq!(generate(42), generate(42));
+//! ```
+
+use std::{error::Error, fmt::Display, ops::Range};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution as _, Normal, Zipf};
+
+use crate::omt::AABB;
+
+/// How the generated AABBs are placed and sized, every size is the full
+/// edge length of the box.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distribution {
+    /// Uniform positions and uniform sizes.
+    Uniform { size: Range<f64> },
+    /// Positions normally distributed around `clusters` uniformly placed
+    /// centers.
+    Clusters {
+        clusters: usize,
+        std_dev: f64,
+        size: Range<f64>,
+    },
+    /// Uniform positions, with a size of `max_size / k` where `k` follows a
+    /// Zipf distribution over `1..=ranks`, so most boxes are small and a few
+    /// are large.
+    Zipf {
+        max_size: f64,
+        ranks: u64,
+        exponent: f64,
+    },
+    /// Boxes flattened onto the line through the center of the bounds
+    /// along the x axis.
+    Line { size: Range<f64> },
+    /// Boxes flattened onto the plane through the center of the bounds
+    /// normal to the y axis.
+    Plane { size: Range<f64> },
+}
+
+/// The parameters of a [`Distribution`] that cannot be sampled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidDistribution {
+    /// The standard deviation of the clusters is negative or not finite.
+    StdDev(f64),
+    /// Zipf needs at least one rank and a finite, non-negative exponent.
+    Zipf { ranks: u64, exponent: f64 },
+}
+
+impl Display for InvalidDistribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StdDev(std_dev) => {
+                write!(f, "invalid cluster standard deviation {std_dev}")
+            }
+            Self::Zipf { ranks, exponent } => write!(
+                f,
+                "invalid zipf distribution with {ranks} ranks and exponent \
+                 {exponent}"
+            ),
+        }
+    }
+}
+
+impl Error for InvalidDistribution {}
+
+/// An endless stream of random AABBs inside `bounds`.
+pub struct Generator<R> {
+    rng: R,
+    bounds: AABB,
+    sampler: Sampler,
+    cluster_centers: Vec<[f64; 3]>,
+}
+
+/// A [`Distribution`] with its random distributions built once.
+enum Sampler {
+    Uniform {
+        size: Range<f64>,
+    },
+    Clusters {
+        normal: Normal<f64>,
+        size: Range<f64>,
+    },
+    Zipf {
+        max_size: f64,
+        zipf: Zipf<f64>,
+    },
+    Line {
+        size: Range<f64>,
+    },
+    Plane {
+        size: Range<f64>,
+    },
+}
+
+impl TryFrom<Distribution> for Sampler {
+    type Error = InvalidDistribution;
+
+    fn try_from(distribution: Distribution) -> Result<Self, Self::Error> {
+        Ok(match distribution {
+            Distribution::Uniform { size } => Self::Uniform { size },
+            Distribution::Clusters { std_dev, size, .. } => Self::Clusters {
+                normal: Normal::new(0.0, std_dev)
+                    .ok()
+                    .filter(|_| std_dev >= 0.0)
+                    .ok_or(InvalidDistribution::StdDev(std_dev))?,
+                size,
+            },
+            Distribution::Zipf {
+                max_size,
+                ranks,
+                exponent,
+            } => Self::Zipf {
+                max_size,
+                zipf: Zipf::new(ranks, exponent)
+                    .ok()
+                    .filter(|_| exponent.is_finite())
+                    .ok_or(InvalidDistribution::Zipf { ranks, exponent })?,
+            },
+            Distribution::Line { size } => Self::Line { size },
+            Distribution::Plane { size } => Self::Plane { size },
+        })
+    }
+}
+
+/// A portable, seedable RNG, the same seed gives the same numbers on every
+/// platform.
+pub fn rng(seed: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+/// `n` uniformly distributed AABBs inside `bounds`, the same for the same
+/// seed.
+pub fn uniform_aabbs(
+    seed: u64,
+    n: usize,
+    bounds: AABB,
+    size: Range<f64>,
+) -> Vec<AABB> {
+    Generator::seeded(seed, bounds, Distribution::Uniform { size })
+        .expect("a uniform distribution is always valid")
+        .take(n)
+        .collect()
+}
+
+impl Generator<ChaCha8Rng> {
+    pub fn seeded(
+        seed: u64,
+        bounds: AABB,
+        distribution: Distribution,
+    ) -> Result<Self, InvalidDistribution> {
+        Self::new(rng(seed), bounds, distribution)
+    }
+}
+
+impl<R: Rng> Generator<R> {
+    /// Fails if the parameters of `distribution` cannot be sampled.
+    pub fn new(
+        mut rng: R,
+        bounds: AABB,
+        distribution: Distribution,
+    ) -> Result<Self, InvalidDistribution> {
+        let cluster_centers = match distribution {
+            Distribution::Clusters { clusters, .. } => (0..clusters)
+                .map(|_| {
+                    [0, 1, 2]
+                        .map(|i| lerp(bounds.min[i], bounds.max[i], rng.gen()))
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            rng,
+            bounds,
+            sampler: distribution.try_into()?,
+            cluster_centers,
+        })
+    }
+
+    /// Takes the random number generator back, for example to generate
+    /// the queries after the leaves.
+    pub fn into_rng(self) -> R {
+        self.rng
+    }
+}
+
+impl<R: Rng> Iterator for Generator<R> {
+    type Item = AABB;
+
+    fn next(&mut self) -> Option<AABB> {
+        let Self {
+            rng,
+            bounds,
+            sampler,
+            cluster_centers,
+        } = self;
+        let center = bounds.pos();
+        let aabb = match sampler {
+            Sampler::Uniform { size } => {
+                let size = uniform_size(rng, size);
+                let pos = uniform_pos(rng, bounds, size);
+                place(bounds, pos, size)
+            }
+            Sampler::Clusters { normal, size } => {
+                let size = uniform_size(rng, size);
+                let pos = match cluster_centers.len() {
+                    0 => uniform_pos(rng, bounds, size),
+                    n => {
+                        let cluster = cluster_centers[rng.gen_range(0..n)];
+                        cluster.map(|c| c + normal.sample(rng))
+                    }
+                };
+                place(bounds, pos, size)
+            }
+            Sampler::Zipf { max_size, zipf } => {
+                let size = [*max_size / zipf.sample(rng); 3];
+                let pos = uniform_pos(rng, bounds, size);
+                place(bounds, pos, size)
+            }
+            Sampler::Line { size } => {
+                let [x, _, _] = uniform_size(rng, size);
+                let [x_pos, _, _] = uniform_pos(rng, bounds, [x, 0.0, 0.0]);
+                place(bounds, [x_pos, center[1], center[2]], [x, 0.0, 0.0])
+            }
+            Sampler::Plane { size } => {
+                let [x, _, z] = uniform_size(rng, size);
+                let [x_pos, _, z_pos] = uniform_pos(rng, bounds, [x, 0.0, z]);
+                place(bounds, [x_pos, center[1], z_pos], [x, 0.0, z])
+            }
+        };
+        Some(aabb)
+    }
+}
+
+fn uniform_size(rng: &mut impl Rng, size: &Range<f64>) -> [f64; 3] {
+    [(); 3].map(|_| lerp(size.start, size.end, rng.gen()))
+}
+
+/// A uniform position that keeps a box of `size` inside `bounds`.
+fn uniform_pos(rng: &mut impl Rng, bounds: &AABB, size: [f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| {
+        let half = (size[i] / 2.0).min((bounds.max[i] - bounds.min[i]) / 2.0);
+        lerp(bounds.min[i] + half, bounds.max[i] - half, rng.gen())
+    })
+}
+
+/// The box of `size` at `pos`, moved inside `bounds` if it sticks out.
+fn place(bounds: &AABB, pos: [f64; 3], size: [f64; 3]) -> AABB {
+    let size = [0, 1, 2].map(|i| size[i].min(bounds.max[i] - bounds.min[i]));
+    let min = [0, 1, 2].map(|i| {
+        (pos[i] - size[i] / 2.0).clamp(bounds.min[i], bounds.max[i] - size[i])
+    });
+    AABB {
+        min,
+        max: [0, 1, 2].map(|i| min[i] + size[i]),
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: AABB = AABB {
+        min: [-10.0, -10.0, -10.0],
+        max: [10.0, 10.0, 10.0],
+    };
+
+    fn distributions() -> [Distribution; 5] {
+        [
+            Distribution::Uniform { size: 0.1..0.5 },
+            Distribution::Clusters {
+                clusters: 4,
+                std_dev: 3.0,
+                size: 0.1..0.5,
+            },
+            Distribution::Zipf {
+                max_size: 40.0,
+                ranks: 1000,
+                exponent: 1.0,
+            },
+            Distribution::Line { size: 0.1..0.5 },
+            Distribution::Plane { size: 0.1..0.5 },
+        ]
+    }
+
+    fn contains(outer: &AABB, inner: &AABB) -> bool {
+        (0..3).all(|i| {
+            outer.min[i] <= inner.min[i] && inner.max[i] <= outer.max[i]
+        })
+    }
+
+    #[test]
+    fn same_seed_same_aabbs() {
+        for distribution in distributions() {
+            let generate = |seed| -> Vec<_> {
+                Generator::seeded(seed, BOUNDS, distribution.clone())
+                    .unwrap()
+                    .take(100)
+                    .collect()
+            };
+            assert_eq!(generate(1), generate(1));
+            assert_ne!(generate(1), generate(2));
+        }
+    }
+
+    #[test]
+    fn aabbs_inside_bounds() {
+        for distribution in distributions() {
+            for aabb in Generator::seeded(3, BOUNDS, distribution)
+                .unwrap()
+                .take(1000)
+            {
+                assert!(contains(&BOUNDS, &aabb), "{aabb:?}");
+                assert!((0..3).all(|i| aabb.min[i] <= aabb.max[i]));
+            }
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        for aabb in uniform_aabbs(4, 1000, BOUNDS, 0.1..0.5) {
+            let size = [0, 1, 2].map(|i| aabb.max[i] - aabb.min[i]);
+            assert!(size.iter().all(|s| (0.1..0.5).contains(s)), "{size:?}");
+        }
+        let line = Distribution::Line { size: 0.1..0.5 };
+        for aabb in Generator::seeded(5, BOUNDS, line).unwrap().take(100) {
+            assert!(aabb.max[0] > aabb.min[0]);
+            assert_eq!(aabb.min[1..], aabb.max[1..]);
+        }
+        let plane = Distribution::Plane { size: 0.1..0.5 };
+        for aabb in Generator::seeded(6, BOUNDS, plane).unwrap().take(100) {
+            assert!(aabb.max[0] > aabb.min[0] && aabb.max[2] > aabb.min[2]);
+            assert_eq!(aabb.min[1], aabb.max[1]);
+        }
+        // a box larger than the bounds is shrunk to fit
+        let large = uniform_aabbs(7, 10, BOUNDS, 30.0..40.0);
+        assert!(large.iter().all(|aabb| aabb.min == BOUNDS.min));
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let clusters = |std_dev| Distribution::Clusters {
+            clusters: 4,
+            std_dev,
+            size: 0.1..0.5,
+        };
+        for std_dev in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                Generator::seeded(8, BOUNDS, clusters(std_dev)),
+                Err(InvalidDistribution::StdDev(_))
+            ));
+        }
+        let zipf = |ranks, exponent| Distribution::Zipf {
+            max_size: 1.0,
+            ranks,
+            exponent,
+        };
+        for (ranks, exponent) in [(0, 1.0), (10, -1.0), (10, f64::NAN)] {
+            assert!(matches!(
+                Generator::seeded(8, BOUNDS, zipf(ranks, exponent)),
+                Err(InvalidDistribution::Zipf { .. })
+            ));
+        }
+        assert!(Generator::seeded(8, BOUNDS, zipf(1, 0.0)).is_ok());
+    }
+}
@@ -0,0 +1,268 @@
+//! Spatial joins, finding every overlapping pair of leaves between two trees
+//! or within a single tree, by descending both trees at the same time.
+
+use crate::omt::AABB;
+
+/// A tree that can be walked node by node.
+///
+/// Implemented for references to both [`omt::RTree`] and [`rtree::RTree`],
+/// so any pair of them can be joined.
+///
+/// [`omt::RTree`]: crate::omt::RTree
+/// [`rtree::RTree`]: crate::rtree::RTree
+pub trait Traverse<'a>: Copy {
+    type Node: Copy;
+    type Data: 'a;
+
+    fn root(self) -> Option<Self::Node>;
+
+    fn aabb(self, node: Self::Node) -> &'a AABB;
+
+    /// Pushes the children of `node` into `children`, returns `false` if
+    /// `node` is a leaf.
+    fn children(self, node: Self::Node, children: &mut Vec<Self::Node>)
+        -> bool;
+
+    /// # Panics
+    /// If `node` is not a leaf.
+    fn data(self, node: Self::Node) -> &'a Self::Data;
+
+    fn same(node1: Self::Node, node2: Self::Node) -> bool;
+}
+
+/// Iterates over all overlapping pairs of leaves of two trees.
+pub struct Join<'a, A: Traverse<'a>, B: Traverse<'a>> {
+    a: A,
+    b: B,
+    stack: Vec<(A::Node, B::Node)>,
+    a_children: Vec<A::Node>,
+    b_children: Vec<B::Node>,
+}
+
+impl<'a, A: Traverse<'a>, B: Traverse<'a>> Join<'a, A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        let mut stack = Vec::new();
+        if let (Some(root_a), Some(root_b)) = (a.root(), b.root()) {
+            if a.aabb(root_a).overlaps(b.aabb(root_b)) {
+                stack.push((root_a, root_b));
+            }
+        }
+        Self {
+            a,
+            b,
+            stack,
+            a_children: Vec::new(),
+            b_children: Vec::new(),
+        }
+    }
+}
+
+impl<'a, A: Traverse<'a>, B: Traverse<'a>> Iterator for Join<'a, A, B> {
+    type Item = (&'a A::Data, &'a B::Data);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(pair) = self.stack.pop() {
+            let leaves = expand(
+                self.a,
+                self.b,
+                pair,
+                &mut self.a_children,
+                &mut self.b_children,
+                &mut self.stack,
+            );
+            if leaves.is_some() {
+                return leaves;
+            }
+        }
+        None
+    }
+}
+
+/// Pushes the overlapping pairs of children of an overlapping pair of nodes,
+/// returns the data of the pair if both nodes are leaves.
+///
+/// If only one of them is a leaf, the other one is paired with the leaf.
+fn expand<'a, A: Traverse<'a>, B: Traverse<'a>>(
+    a: A,
+    b: B,
+    (node_a, node_b): (A::Node, B::Node),
+    a_children: &mut Vec<A::Node>,
+    b_children: &mut Vec<B::Node>,
+    stack: &mut Vec<(A::Node, B::Node)>,
+) -> Option<(&'a A::Data, &'a B::Data)> {
+    a_children.clear();
+    b_children.clear();
+    let a_inner = a.children(node_a, a_children);
+    let b_inner = b.children(node_b, b_children);
+    let aabb_a = a.aabb(node_a);
+    let aabb_b = b.aabb(node_b);
+    match (a_inner, b_inner) {
+        (false, false) => return Some((a.data(node_a), b.data(node_b))),
+        (true, false) => {
+            for &child in a_children.iter() {
+                if a.aabb(child).overlaps(aabb_b) {
+                    stack.push((child, node_b));
+                }
+            }
+        }
+        (false, true) => {
+            for &child in b_children.iter() {
+                if b.aabb(child).overlaps(aabb_a) {
+                    stack.push((node_a, child));
+                }
+            }
+        }
+        (true, true) => {
+            for &child_a in a_children.iter() {
+                let child_aabb = a.aabb(child_a);
+                if !child_aabb.overlaps(aabb_b) {
+                    continue;
+                }
+                for &child_b in b_children.iter() {
+                    if child_aabb.overlaps(b.aabb(child_b)) {
+                        stack.push((child_a, child_b));
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Iterates over all overlapping pairs of distinct leaves of a tree, every
+/// pair is reported once.
+pub struct SelfJoin<'a, A: Traverse<'a>> {
+    tree: A,
+    stack: Vec<(A::Node, A::Node)>,
+    children1: Vec<A::Node>,
+    children2: Vec<A::Node>,
+}
+
+impl<'a, A: Traverse<'a>> SelfJoin<'a, A> {
+    pub fn new(tree: A) -> Self {
+        Self {
+            tree,
+            stack: tree.root().map(|root| (root, root)).into_iter().collect(),
+            children1: Vec::new(),
+            children2: Vec::new(),
+        }
+    }
+}
+
+impl<'a, A: Traverse<'a>> Iterator for SelfJoin<'a, A> {
+    type Item = (&'a A::Data, &'a A::Data);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = self.tree;
+        while let Some((node1, node2)) = self.stack.pop() {
+            if !A::same(node1, node2) {
+                let leaves = expand(
+                    tree,
+                    tree,
+                    (node1, node2),
+                    &mut self.children1,
+                    &mut self.children2,
+                    &mut self.stack,
+                );
+                if leaves.is_some() {
+                    return leaves;
+                }
+                continue;
+            }
+            self.children1.clear();
+            if !tree.children(node1, &mut self.children1) {
+                // a leaf does not pair with itself
+                continue;
+            }
+            // only pairs (i, j) with i <= j, so every pair of subtrees is
+            // visited once
+            for (i, &child1) in self.children1.iter().enumerate() {
+                self.stack.push((child1, child1));
+                for &child2 in &self.children1[i + 1..] {
+                    if tree.aabb(child1).overlaps(tree.aabb(child2)) {
+                        self.stack.push((child1, child2));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::uniform_aabbs;
+    use crate::omt::{Leaf, RTree as Omt};
+    use crate::rtree::RTree;
+
+    const BOUNDS: AABB = AABB {
+        min: [-10.0, -10.0, -10.0],
+        max: [10.0, 10.0, 10.0],
+    };
+
+    fn sorted(
+        pairs: impl Iterator<Item = (usize, usize)>,
+    ) -> Vec<(usize, usize)> {
+        let mut pairs: Vec<_> = pairs.collect();
+        pairs.sort_unstable();
+        pairs
+    }
+
+    fn brute_force(a: &[AABB], b: &[AABB]) -> Vec<(usize, usize)> {
+        sorted((0..a.len()).flat_map(|i| {
+            (0..b.len())
+                .filter(move |&j| a[i].overlaps(&b[j]))
+                .map(move |j| (i, j))
+        }))
+    }
+
+    fn omt(aabbs: &[AABB]) -> Omt<usize> {
+        Omt::new(
+            aabbs
+                .iter()
+                .enumerate()
+                .map(|(i, &aabb)| Leaf::new(aabb, i))
+                .collect(),
+        )
+    }
+
+    fn rtree(aabbs: &[AABB]) -> RTree<usize> {
+        let mut tree = RTree::new();
+        for (i, &aabb) in aabbs.iter().enumerate() {
+            tree.insert(aabb, i);
+        }
+        tree
+    }
+
+    #[test]
+    fn join_matches_brute_force() {
+        for (count_a, count_b) in [(0, 10), (1, 1), (5, 40), (300, 200)] {
+            let a = uniform_aabbs(1, count_a, BOUNDS, 0.2..1.0);
+            let b = uniform_aabbs(2, count_b, BOUNDS, 0.2..1.0);
+            let expected = brute_force(&a, &b);
+            let (omt_a, omt_b) = (omt(&a), omt(&b));
+            let (rtree_a, rtree_b) = (rtree(&a), rtree(&b));
+            let pairs = |(i, j): (&usize, &usize)| (*i, *j);
+            assert_eq!(sorted(omt_a.join(&omt_b).map(pairs)), expected);
+            assert_eq!(sorted(rtree_a.join(&rtree_b).map(pairs)), expected);
+            assert_eq!(sorted(omt_a.join(&rtree_b).map(pairs)), expected);
+            assert_eq!(sorted(rtree_a.join(&omt_b).map(pairs)), expected);
+        }
+    }
+
+    #[test]
+    fn self_join_matches_brute_force() {
+        for count in [0, 1, 2, 7, 500] {
+            let aabbs = uniform_aabbs(3, count, BOUNDS, 0.2..1.0);
+            let expected: Vec<_> = brute_force(&aabbs, &aabbs)
+                .into_iter()
+                .filter(|(i, j)| i < j)
+                .collect();
+            // pairs come in no particular order
+            let pairs = |(i, j): (&usize, &usize)| (*i.min(j), *i.max(j));
+            assert_eq!(sorted(omt(&aabbs).self_join().map(pairs)), expected);
+            assert_eq!(sorted(rtree(&aabbs).self_join().map(pairs)), expected);
+        }
+    }
+}
@@ -1,2 +1,12 @@
+pub mod buffered;
+pub mod bvh;
+pub mod dataset;
+pub mod generate;
+pub mod join;
+pub mod mapped;
 pub mod omt;
+mod packing;
+pub mod points;
 pub mod rtree;
+pub mod stats;
+pub mod validate;
@@ -3,12 +3,14 @@ use std::time::{Duration, Instant};
 
 use graphics::app::{App, AppState};
 use graphics::canvas::Canvas;
+use graphics::context::ContextConfig;
 use graphics::geometry::{Box, BoxLines};
 use graphics::math::Transform;
 use rtrees::omt::{rand_aabb, rand_aabbs, Leaf, QueryData, RTree as Omt, AABB};
 
 fn main() {
-    App::run_with(State::new());
+    let config = ContextConfig::default().sample_count(4).title("rtrees");
+    App::run_with_config(State::new(), config);
 }
 
 struct State {
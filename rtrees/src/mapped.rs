@@ -0,0 +1,547 @@
+//! A compact on-disk format for [`omt::RTree`] and a memory-mapped reader
+//! that queries it in place.
+//!
+//! The file is written in native byte order and every section is 8 byte
+//! aligned, so the mapped bytes can be reinterpreted directly. A marker in
+//! the header rejects files written on a machine with the other byte order:
+//!
+//! | section      | contents                                   |
+//! |--------------|--------------------------------------------|
+//! | header       | [`Header`]                                 |
+//! | layer table  | node count of every layer, `u64` each      |
+//! | nodes        | all layers, top to bottom, as [`FileNode`] |
+//! | leaf AABBs   | one [`AABB`] per leaf                      |
+//! | payloads     | one `T` per leaf                           |
+//!
+//! The checksum is a CRC-32 over everything after the header.
+//!
+//! [`omt::RTree`]: crate::omt::RTree
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Write},
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ops::Range,
+    path::Path,
+};
+
+use bytemuck::{Pod, Zeroable};
+use memmap2::Mmap;
+
+use crate::join::Traverse;
+use crate::omt::{RTree, AABB, MAX_NODE_SIZE};
+use crate::stats::{self, QueryStats, TreeStats};
+
+const MAGIC: [u8; 8] = *b"OMTREE\0\0";
+pub const VERSION: u32 = 2;
+/// Reads back as `0x04030201` when the byte order does not match.
+const BYTE_ORDER: u32 = 0x01020304;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct Header {
+    magic: [u8; 8],
+    byte_order: u32,
+    version: u32,
+    checksum: u32,
+    _padding: u32,
+    payload_size: u64,
+    layer_count: u64,
+    node_count: u64,
+    leaf_count: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct FileNode {
+    aabb: AABB,
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file does not start with the expected magic bytes.
+    Magic,
+    /// The file was written on a machine with a different byte order.
+    ByteOrder,
+    /// The file was written by an incompatible version of the format.
+    Version(u32),
+    /// The payload type does not match the one the file was written with.
+    PayloadSize {
+        expected: usize,
+        found: u64,
+    },
+    /// The file is shorter than its header claims.
+    Truncated,
+    /// The file contents do not match the checksum in the header.
+    Checksum,
+    /// The layer table does not add up to the nodes in the file, or a node
+    /// points past the end of the layer below it.
+    Malformed,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Magic => write!(f, "not an OMT file"),
+            Self::ByteOrder => write!(f, "written with another byte order"),
+            Self::Version(v) => {
+                write!(f, "unsupported version {v}, expected {VERSION}")
+            }
+            Self::PayloadSize { expected, found } => write!(
+                f,
+                "payload size mismatch, expected {expected}, found {found}"
+            ),
+            Self::Truncated => write!(f, "file is truncated"),
+            Self::Checksum => write!(f, "checksum mismatch"),
+            Self::Malformed => write!(f, "malformed node layers"),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl<T: Pod> RTree<T> {
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        assert_payload_align::<T>();
+        let layer_sizes: Vec<u64> =
+            self.layers.iter().map(|l| l.len() as u64).collect();
+        let nodes: Vec<FileNode> = self
+            .layers
+            .iter()
+            .flatten()
+            .map(|n| FileNode {
+                aabb: n.aabb,
+                start: n.start as u64,
+                end: n.end as u64,
+            })
+            .collect();
+        let aabbs: Vec<AABB> = self.leaves.iter().map(|l| l.aabb).collect();
+        let payloads: Vec<T> = self.leaves.iter().map(|l| l.data).collect();
+        let sections: [&[u8]; 4] = [
+            bytemuck::cast_slice(&layer_sizes),
+            bytemuck::cast_slice(&nodes),
+            bytemuck::cast_slice(&aabbs),
+            payload_bytes(&payloads),
+        ];
+        let mut hasher = crc32fast::Hasher::new();
+        for section in sections {
+            hasher.update(section);
+        }
+        let header = Header {
+            magic: MAGIC,
+            byte_order: BYTE_ORDER,
+            version: VERSION,
+            checksum: hasher.finalize(),
+            _padding: 0,
+            payload_size: size_of::<T>() as u64,
+            layer_count: layer_sizes.len() as u64,
+            node_count: nodes.len() as u64,
+            leaf_count: aabbs.len() as u64,
+        };
+        writer.write_all(bytemuck::bytes_of(&header))?;
+        for section in sections {
+            writer.write_all(section)?;
+        }
+        writer.flush()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+}
+
+/// An [`RTree`] read straight from a memory-mapped file.
+pub struct MappedRTree<T> {
+    mmap: Mmap,
+    layers: Vec<Range<usize>>,
+    nodes: Range<usize>,
+    aabbs: Range<usize>,
+    payloads: Range<usize>,
+    _data: PhantomData<T>,
+}
+
+impl<T: Pod> MappedRTree<T> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        assert_payload_align::<T>();
+        let file = File::open(path)?;
+        // SAFETY: the file is only ever read through the map, modifying it
+        // while it is mapped is not supported
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header: Header = bytemuck::pod_read_unaligned(
+            mmap.get(..size_of::<Header>())
+                .ok_or(LoadError::Truncated)?,
+        );
+        if header.magic != MAGIC {
+            return Err(LoadError::Magic);
+        }
+        if header.byte_order != BYTE_ORDER {
+            return Err(LoadError::ByteOrder);
+        }
+        if header.version != VERSION {
+            return Err(LoadError::Version(header.version));
+        }
+        if header.payload_size != size_of::<T>() as u64 {
+            return Err(LoadError::PayloadSize {
+                expected: size_of::<T>(),
+                found: header.payload_size,
+            });
+        }
+        let section = |start: usize, count: u64, size: usize| {
+            usize::try_from(count)
+                .ok()
+                .and_then(|c| c.checked_mul(size))
+                .and_then(|len| start.checked_add(len))
+                .and_then(|end| (end <= mmap.len()).then_some(start..end))
+                .ok_or(LoadError::Truncated)
+        };
+        let layer_table =
+            section(size_of::<Header>(), header.layer_count, size_of::<u64>())?;
+        let nodes =
+            section(layer_table.end, header.node_count, size_of::<FileNode>())?;
+        let aabbs = section(nodes.end, header.leaf_count, size_of::<AABB>())?;
+        let payloads = section(aabbs.end, header.leaf_count, size_of::<T>())?;
+        if crc32fast::hash(&mmap[layer_table.start..payloads.end])
+            != header.checksum
+        {
+            return Err(LoadError::Checksum);
+        }
+        let mut start = 0usize;
+        let layers = bytemuck::cast_slice::<_, u64>(&mmap[layer_table])
+            .iter()
+            .map(|&size| {
+                let end = usize::try_from(size)
+                    .ok()
+                    .and_then(|size| start.checked_add(size))
+                    .ok_or(LoadError::Malformed)?;
+                let layer = start..end;
+                start = end;
+                Ok(layer)
+            })
+            .collect::<Result<Vec<_>, LoadError>>()?;
+        if layers.first().is_none_or(|root| root.len() != 1)
+            || start as u64 != header.node_count
+        {
+            return Err(LoadError::Malformed);
+        }
+        let file_nodes: &[FileNode] =
+            bytemuck::cast_slice(&mmap[nodes.clone()]);
+        for (i, layer) in layers.iter().enumerate() {
+            let below = match layers.get(i + 1) {
+                Some(next) => next.len() as u64,
+                None => header.leaf_count,
+            };
+            if file_nodes[layer.clone()]
+                .iter()
+                .any(|node| node.start > node.end || node.end > below)
+            {
+                return Err(LoadError::Malformed);
+            }
+        }
+        Ok(Self {
+            mmap,
+            layers,
+            nodes,
+            aabbs,
+            payloads,
+            _data: PhantomData,
+        })
+    }
+
+    fn layer(&self, layer: usize) -> &[FileNode] {
+        let nodes: &[FileNode] =
+            bytemuck::cast_slice(&self.mmap[self.nodes.clone()]);
+        &nodes[self.layers[layer].clone()]
+    }
+
+    pub fn leaf_aabbs(&self) -> &[AABB] {
+        bytemuck::cast_slice(&self.mmap[self.aabbs.clone()])
+    }
+
+    pub fn data(&self, leaf: usize) -> &T {
+        let start = self.payloads.start + leaf * size_of::<T>();
+        bytemuck::from_bytes(&self.mmap[start..start + size_of::<T>()])
+    }
+
+    pub fn height(&self) -> usize {
+        self.layers.len() + 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaf_aabbs().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn query_stats(&self, aabb: &AABB) -> QueryStats {
+        stats::query_stats(self, aabb)
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        stats::tree_stats(self, MAX_NODE_SIZE)
+    }
+
+    pub fn query(&self, aabb: AABB) -> MappedQuery<'_, T> {
+        MappedQuery {
+            tree: self,
+            aabb,
+            stack: vec![(0, 0)],
+            leaves: 0..0,
+        }
+    }
+}
+
+/// Iterates over the leaves of a [`MappedRTree`] that overlap an AABB.
+pub struct MappedQuery<'a, T> {
+    tree: &'a MappedRTree<T>,
+    aabb: AABB,
+    /// `(layer, index)` of the nodes that still have to be checked
+    stack: Vec<(usize, usize)>,
+    leaves: Range<usize>,
+}
+
+impl<'a, T: Pod> Iterator for MappedQuery<'a, T> {
+    type Item = (&'a AABB, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for i in self.leaves.by_ref() {
+                let aabb = &self.tree.leaf_aabbs()[i];
+                if aabb.overlaps(&self.aabb) {
+                    return Some((aabb, self.tree.data(i)));
+                }
+            }
+            let (layer, index) = self.stack.pop()?;
+            let node = &self.tree.layer(layer)[index];
+            if !node.aabb.overlaps(&self.aabb) {
+                continue;
+            }
+            let children = node.start as usize..node.end as usize;
+            if layer + 1 == self.tree.layers.len() {
+                self.leaves = children;
+            } else {
+                self.stack.extend(children.rev().map(|i| (layer + 1, i)));
+            }
+        }
+    }
+}
+
+impl<'a, T: Pod> Traverse<'a> for &'a MappedRTree<T> {
+    /// `(layer, index)`, leaves are on the layer after the last one
+    type Node = (usize, usize);
+    type Data = T;
+
+    fn root(self) -> Option<Self::Node> {
+        Some((0, 0))
+    }
+
+    fn aabb(self, (layer, index): Self::Node) -> &'a AABB {
+        if layer < self.layers.len() {
+            &self.layer(layer)[index].aabb
+        } else {
+            &self.leaf_aabbs()[index]
+        }
+    }
+
+    fn children(
+        self,
+        (layer, index): Self::Node,
+        children: &mut Vec<Self::Node>,
+    ) -> bool {
+        if layer == self.layers.len() {
+            return false;
+        }
+        let node = &self.layer(layer)[index];
+        children.extend(
+            (node.start as usize..node.end as usize).map(|i| (layer + 1, i)),
+        );
+        true
+    }
+
+    fn data(self, (layer, index): Self::Node) -> &'a T {
+        assert_eq!(layer, self.layers.len(), "not a leaf");
+        MappedRTree::data(self, index)
+    }
+
+    fn same(node1: Self::Node, node2: Self::Node) -> bool {
+        node1 == node2
+    }
+}
+
+/// The payloads follow 8 byte aligned sections, anything with a larger
+/// alignment could not be read back in place.
+fn assert_payload_align<T>() {
+    assert!(align_of::<T>() <= 8, "payload alignment must be at most 8");
+}
+
+fn payload_bytes<T: Pod>(payloads: &[T]) -> &[u8] {
+    if size_of::<T>() == 0 {
+        &[]
+    } else {
+        bytemuck::cast_slice(payloads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::generate::uniform_aabbs;
+    use crate::omt::{Leaf, QueryData};
+
+    const BOUNDS: AABB = AABB {
+        min: [-10.0, -10.0, -10.0],
+        max: [10.0, 10.0, 10.0],
+    };
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("rtrees-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn round_trip() {
+        for count in [0, 1, 6, 7, 1000] {
+            let leaves: Vec<_> = uniform_aabbs(1, count, BOUNDS, 0.1..0.5)
+                .into_iter()
+                .enumerate()
+                .map(|(i, aabb)| Leaf::new(aabb, i as u32))
+                .collect();
+            let tree = RTree::new(leaves);
+            let path = temp_path(&format!("round-trip-{count}"));
+            tree.save(&path).unwrap();
+            let mapped = MappedRTree::<u32>::open(&path).unwrap();
+            assert_eq!(mapped.height(), tree.height());
+            assert_eq!(mapped.len(), count);
+            for query in uniform_aabbs(2, 20, BOUNDS, 1.0..2.0) {
+                let mut expected: Vec<u32> = tree
+                    .query(query)
+                    .filter_map(|item| match item.data {
+                        QueryData::Leaf { data } => Some(*data),
+                        QueryData::Node { .. } => None,
+                    })
+                    .collect();
+                let mut found: Vec<u32> =
+                    mapped.query(query).map(|(_, data)| *data).collect();
+                expected.sort_unstable();
+                found.sort_unstable();
+                assert_eq!(found, expected);
+            }
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let leaves = uniform_aabbs(3, 100, BOUNDS, 0.1..0.5)
+            .into_iter()
+            .map(|aabb| Leaf::new(aabb, 0u64))
+            .collect();
+        let path = temp_path("bad-files");
+        RTree::new(leaves).save(&path).unwrap();
+        assert!(matches!(
+            MappedRTree::<u32>::open(&path),
+            Err(LoadError::PayloadSize { .. })
+        ));
+        let mut bytes = fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            MappedRTree::<u64>::open(&path),
+            Err(LoadError::Checksum)
+        ));
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(
+            MappedRTree::<u64>::open(&path),
+            Err(LoadError::Truncated)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    /// Rewrites the header of `bytes` with `edit` applied to it and the node
+    /// at `node` changed by `edit_node`, and recomputes the checksum.
+    fn edit_file(
+        bytes: &mut [u8],
+        edit: impl FnOnce(&mut Header),
+        node: usize,
+        edit_node: impl FnOnce(&mut FileNode),
+    ) {
+        let header_size = size_of::<Header>();
+        let mut header: Header =
+            bytemuck::pod_read_unaligned(&bytes[..header_size]);
+        let start = header_size
+            + header.layer_count as usize * size_of::<u64>()
+            + node * size_of::<FileNode>();
+        let range = start..start + size_of::<FileNode>();
+        let mut file_node: FileNode =
+            bytemuck::pod_read_unaligned(&bytes[range.clone()]);
+        edit_node(&mut file_node);
+        bytes[range].copy_from_slice(bytemuck::bytes_of(&file_node));
+        header.checksum = crc32fast::hash(&bytes[header_size..]);
+        edit(&mut header);
+        bytes[..header_size].copy_from_slice(bytemuck::bytes_of(&header));
+    }
+
+    #[test]
+    fn rejects_malformed_nodes() {
+        let leaves = uniform_aabbs(4, 100, BOUNDS, 0.1..0.5)
+            .into_iter()
+            .map(|aabb| Leaf::new(aabb, 0u32))
+            .collect();
+        let path = temp_path("malformed");
+        let tree = RTree::new(leaves);
+        tree.save(&path).unwrap();
+        let original = fs::read(&path).unwrap();
+        let last_node = tree.layers.iter().map(Vec::len).sum::<usize>() - 1;
+        let open_edited =
+            |edit: fn(&mut Header),
+             node: usize,
+             edit_node: fn(&mut FileNode)| {
+                let mut bytes = original.clone();
+                edit_file(&mut bytes, edit, node, edit_node);
+                fs::write(&path, &bytes).unwrap();
+                MappedRTree::<u32>::open(&path)
+            };
+        for (node, edit_node) in [
+            (0, (|node| node.end = u64::MAX) as fn(&mut FileNode)),
+            (0, |node| node.end = 7),
+            (last_node, |node| node.end = 101),
+            (last_node, |node| node.start = node.end + 1),
+        ] {
+            assert!(matches!(
+                open_edited(|_| {}, node, edit_node),
+                Err(LoadError::Malformed)
+            ));
+        }
+        assert!(matches!(
+            open_edited(
+                |header| header.byte_order = header.byte_order.swap_bytes(),
+                0,
+                |_| {}
+            ),
+            Err(LoadError::ByteOrder)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,18 +1,26 @@
-use std::{any::Any, iter, ops::Range};
+use std::ops::{ControlFlow, Range};
 
-use rand::distributions::uniform::SampleRange;
+use graphics::canvas::FrustumIndex;
+use graphics::math::{Containment, Frustum};
+
+use crate::generate::{Distribution, Generator};
+use crate::join::{Join, SelfJoin, Traverse};
+use crate::packing;
+use crate::rtree;
+use crate::stats::{self, QueryStats, TreeStats};
+use crate::validate::{self, InvalidTree};
 
 #[derive(Debug)]
 pub struct RTree<T> {
-    layers: Vec<Vec<Node>>,
-    leaves: Vec<Leaf<T>>,
+    pub(crate) layers: Vec<Vec<Node>>,
+    pub(crate) leaves: Vec<Leaf<T>>,
 }
 
 #[derive(Debug)]
-struct Node {
-    aabb: AABB,
-    start: usize,
-    end: usize,
+pub(crate) struct Node {
+    pub(crate) aabb: AABB,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -21,90 +29,45 @@ pub struct Leaf<T> {
     pub data: T,
 }
 
-const MAX_NODE_SIZE: usize = 6;
+pub(crate) const MAX_NODE_SIZE: usize = 6;
+/// Only the root of a tree with a single leaf has fewer children.
+pub(crate) const MIN_NODE_SIZE: usize = 2;
+
+/// The bulk-loading algorithm used to build an [`RTree`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loader {
+    /// Overlap Minimising Top-down
+    Omt,
+    /// Sort-Tile-Recursive
+    Str,
+    /// Hilbert curve packing
+    Hilbert,
+    /// Morton (Z-order) curve packing
+    ZOrder,
+}
+
+impl Loader {
+    pub const ALL: [Loader; 4] =
+        [Loader::Omt, Loader::Str, Loader::Hilbert, Loader::ZOrder];
+}
 
 impl<T> RTree<T> {
     pub fn new(mut leaves: Vec<Leaf<T>>) -> Self {
-        let height = if leaves.len() <= MAX_NODE_SIZE {
-            1
-        } else {
-            (leaves.len() - 1).ilog(MAX_NODE_SIZE) as usize + 1
-        };
-        let mut layers = Vec::with_capacity(height);
-        layers.push(vec![Node {
-            start: 0,
-            end: leaves.len(),
-            aabb: AABB::merge(leaves.iter().map(|l| &l.aabb)),
-        }]);
-        for _ in 1..height {
-            let new_layer =
-                Self::omt_new_layer(layers.last_mut().unwrap(), &mut leaves);
-            layers.push(new_layer);
-        }
+        let layers = omt_layers(
+            &mut leaves,
+            |leaf| leaf.aabb.pos(),
+            |leaves| AABB::merge(leaves.iter().map(|l| &l.aabb)),
+        );
         Self { layers, leaves }
     }
 
-    fn omt_new_layer(
-        old_layer: &mut [Node],
-        leaves: &mut [Leaf<T>],
-    ) -> Vec<Node> {
-        let mut new_nodes = Vec::new();
-        for node in old_layer {
-            Self::omt_split(
-                node,
-                &mut leaves[node.start..node.end],
-                &mut new_nodes,
-            )
-        }
-        new_nodes
-    }
-
-    fn omt_split(
-        node: &mut Node,
-        node_leaves: &mut [Leaf<T>],
-        new_nodes: &mut Vec<Node>,
-    ) {
-        let node_count = {
-            let height = (node_leaves.len() as f64)
-                .log(MAX_NODE_SIZE as f64)
-                .ceil() as u32;
-            (node_leaves.len() as f64 / MAX_NODE_SIZE.pow(height - 1) as f64)
-                .ceil() as usize
-        };
-        let splits = calculate_splits(node_count, node_leaves.len(), 3);
-        node_leaves.sort_unstable_by(|l1, l2| {
-            l1.aabb.pos()[0].total_cmp(&l2.aabb.pos()[0])
-        });
-        let mut i = 0;
-        for &size in &splits[0] {
-            node_leaves[i..][..size].sort_unstable_by(|l1, l2| {
-                l1.aabb.pos()[1].total_cmp(&l2.aabb.pos()[1])
-            });
-            i += size;
-        }
-        let mut i = 0;
-        for &size in &splits[1] {
-            node_leaves[i..][..size].sort_unstable_by(|l1, l2| {
-                l1.aabb.pos()[2].total_cmp(&l2.aabb.pos()[2])
-            });
-            i += size;
+    pub fn with_loader(leaves: Vec<Leaf<T>>, loader: Loader) -> Self {
+        match loader {
+            Loader::Omt => Self::new(leaves),
+            Loader::Str => packing::str(leaves),
+            Loader::Hilbert => packing::hilbert(leaves),
+            Loader::ZOrder => packing::z_order(leaves),
         }
-
-        let mut child_start = node.start;
-        let mut i = 0;
-        node.start = new_nodes.len();
-        for &size in &splits[2] {
-            new_nodes.push(Node {
-                start: child_start,
-                end: child_start + size,
-                aabb: AABB::merge(
-                    node_leaves[i..][..size].iter().map(|l| &l.aabb),
-                ),
-            });
-            i += size;
-            child_start += size
-        }
-        node.end = new_nodes.len();
     }
 
     pub fn aabbs(&self) -> AABBS<'_, T> {
@@ -115,13 +78,199 @@ impl<T> RTree<T> {
         self.layers.len() + 1
     }
 
+    /// Heap memory used by the tree, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.layers.capacity() * size_of::<Vec<Node>>()
+            + self
+                .layers
+                .iter()
+                .map(|l| l.capacity() * size_of::<Node>())
+                .sum::<usize>()
+            + self.leaves.capacity() * size_of::<Leaf<T>>()
+    }
+
     pub fn query(&self, aabb: AABB) -> Query<'_, T> {
         Query::new(self, aabb)
     }
 
+    /// The leaves overlapping `aabb`, without the nodes [`query`] also
+    /// yields.
+    ///
+    /// [`query`]: Self::query
+    pub fn query_leaves(&self, aabb: AABB) -> LeafQuery<'_, T> {
+        LeafQuery::new(self, aabb, Relation::Overlaps)
+    }
+
+    /// The leaves completely inside `aabb`.
+    pub fn query_contained(&self, aabb: AABB) -> LeafQuery<'_, T> {
+        LeafQuery::new(self, aabb, Relation::ContainedIn)
+    }
+
+    /// Replaces the contents of `buffer` with the leaves overlapping
+    /// `aabb`, reusing a buffer avoids allocating for every query.
+    pub fn query_into<'a>(
+        &'a self,
+        aabb: &AABB,
+        buffer: &mut Vec<&'a Leaf<T>>,
+    ) {
+        buffer.clear();
+        let _ = self.visit::<()>(aabb, |item| {
+            if let Visit::Leaf(leaf) = item {
+                buffer.push(leaf);
+            }
+            ControlFlow::Continue(())
+        });
+    }
+
+    /// Calls `visitor` with every node and leaf overlapping `aabb`, parents
+    /// before their children, until it breaks.
+    ///
+    /// Does not allocate.
+    pub fn visit<'a, B>(
+        &'a self,
+        aabb: &AABB,
+        mut visitor: impl FnMut(Visit<'a, T>) -> ControlFlow<B>,
+    ) -> ControlFlow<B> {
+        self.visit_node(0, 0, aabb, &mut visitor)
+    }
+
+    fn visit_node<'a, B>(
+        &'a self,
+        layer: usize,
+        index: usize,
+        aabb: &AABB,
+        visitor: &mut impl FnMut(Visit<'a, T>) -> ControlFlow<B>,
+    ) -> ControlFlow<B> {
+        let Some(nodes) = self.layers.get(layer) else {
+            let leaf = &self.leaves[index];
+            if leaf.aabb.overlaps(aabb) {
+                visitor(Visit::Leaf(leaf))?;
+            }
+            return ControlFlow::Continue(());
+        };
+        let node = &nodes[index];
+        if node.start == node.end || !node.aabb.overlaps(aabb) {
+            return ControlFlow::Continue(());
+        }
+        visitor(Visit::Node {
+            aabb: &node.aabb,
+            depth: layer,
+        })?;
+        for child in node.start..node.end {
+            self.visit_node(layer + 1, child, aabb, visitor)?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// The leaves that may be inside `frustum`, see [`FrustumQuery`].
+    pub fn query_frustum<'a, 'f>(
+        &'a self,
+        frustum: &'f Frustum,
+    ) -> FrustumQuery<'a, 'f, T> {
+        FrustumQuery::new(self, frustum)
+    }
+
+    /// The leaves below node `index` of `layer`, which are contiguous.
+    fn leaf_range(&self, layer: usize, index: usize) -> Range<usize> {
+        leaf_range(&self.layers, layer, index)
+    }
+
     pub fn leaves(&self) -> impl Iterator<Item = &Leaf<T>> {
         self.leaves.iter()
     }
+
+    pub fn query_stats(&self, aabb: &AABB) -> QueryStats {
+        stats::query_stats(self, aabb)
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        stats::tree_stats(self, MAX_NODE_SIZE)
+    }
+
+    /// Checks the invariants of [`validate::validate`], and that every
+    /// layer covers the layer below, or the leaves, exactly once and in
+    /// order.
+    pub fn validate(&self) -> Result<(), InvalidTree> {
+        if self.layers.first().is_none_or(|root| root.len() != 1) {
+            return Err(InvalidTree::Layout { depth: 0 });
+        }
+        for (depth, layer) in self.layers.iter().enumerate() {
+            let child_count = self
+                .layers
+                .get(depth + 1)
+                .map_or(self.leaves.len(), Vec::len);
+            let mut next = 0;
+            for node in layer {
+                if node.start != next || node.end < node.start {
+                    return Err(InvalidTree::Layout { depth });
+                }
+                next = node.end;
+            }
+            if next != child_count {
+                return Err(InvalidTree::Layout { depth });
+            }
+        }
+        validate::validate(
+            self,
+            MIN_NODE_SIZE,
+            MAX_NODE_SIZE,
+            self.height(),
+            self.leaves.len(),
+        )
+    }
+
+    /// All pairs of overlapping leaves of `self` and `other`.
+    pub fn join<'a, Other: Traverse<'a>>(
+        &'a self,
+        other: Other,
+    ) -> Join<'a, &'a Self, Other> {
+        Join::new(self, other)
+    }
+
+    /// All pairs of overlapping leaves of `self`, without reporting a pair
+    /// twice or a leaf with itself.
+    pub fn self_join(&self) -> SelfJoin<'_, &Self> {
+        SelfJoin::new(self)
+    }
+}
+
+impl<'a, T> Traverse<'a> for &'a RTree<T> {
+    /// `(layer, index)`, leaves are on the layer after the last one
+    type Node = (usize, usize);
+    type Data = T;
+
+    fn root(self) -> Option<Self::Node> {
+        Some((0, 0))
+    }
+
+    fn aabb(self, (layer, index): Self::Node) -> &'a AABB {
+        match self.layers.get(layer) {
+            Some(nodes) => &nodes[index].aabb,
+            None => &self.leaves[index].aabb,
+        }
+    }
+
+    fn children(
+        self,
+        (layer, index): Self::Node,
+        children: &mut Vec<Self::Node>,
+    ) -> bool {
+        let Some(nodes) = self.layers.get(layer) else {
+            return false;
+        };
+        let node = &nodes[index];
+        children.extend((node.start..node.end).map(|i| (layer + 1, i)));
+        true
+    }
+
+    fn data(self, (layer, index): Self::Node) -> &'a T {
+        assert_eq!(layer, self.layers.len(), "not a leaf");
+        &self.leaves[index].data
+    }
+
+    fn same(node1: Self::Node, node2: Self::Node) -> bool {
+        node1 == node2
+    }
 }
 
 pub struct AABBS<'a, T> {
@@ -195,6 +344,10 @@ pub struct Query<'rtree, T> {
     aabb: AABB,
     indices: Vec<(usize, usize)>,
     ret_root: bool,
+    /// leaves of `tree` to skip, indexed like `tree.leaves`
+    removed: &'rtree [bool],
+    /// more leaves to report after the ones of `tree`
+    delta: Option<rtree::Search<'rtree, T>>,
 }
 
 impl<'rtree, T> Query<'rtree, T> {
@@ -208,8 +361,22 @@ impl<'rtree, T> Query<'rtree, T> {
             aabb,
             indices,
             ret_root: true,
+            removed: &[],
+            delta: None,
         }
     }
+
+    /// Skips the leaves marked in `removed` and reports the leaves of
+    /// `delta` at the end.
+    pub(crate) fn with_delta(
+        mut self,
+        removed: &'rtree [bool],
+        delta: &'rtree rtree::RTree<T>,
+    ) -> Self {
+        self.removed = removed;
+        self.delta = Some(delta.search_iter(&self.aabb));
+        self
+    }
 }
 
 impl<'rtree, T> Iterator for Query<'rtree, T> {
@@ -228,8 +395,9 @@ impl<'rtree, T> Iterator for Query<'rtree, T> {
             let index = self.indices.last_mut().unwrap();
             while index.0 < index.1 {
                 let leaf = &self.tree.leaves[index.0];
+                let removed = self.removed.get(index.0).is_some_and(|&r| r);
                 index.0 += 1;
-                if leaf.aabb.overlaps(&self.aabb) {
+                if !removed && leaf.aabb.overlaps(&self.aabb) {
                     return Some(QueryItem {
                         aabb: leaf.aabb,
                         data: QueryData::Leaf { data: &leaf.data },
@@ -242,8 +410,12 @@ impl<'rtree, T> Iterator for Query<'rtree, T> {
         } else {
             let layer = self.indices.len();
             let Some(index) = self.indices.last_mut() else {
-                // we finished iterating the tree
-                return None;
+                // we finished iterating the tree, only the delta is left
+                let (aabb, data) = self.delta.as_mut()?.next()?;
+                return Some(QueryItem {
+                    aabb: *aabb,
+                    data: QueryData::Leaf { data },
+                });
             };
             while index.0 < index.1 {
                 let node = &self.tree.layers[layer][index.0];
@@ -264,6 +436,261 @@ impl<'rtree, T> Iterator for Query<'rtree, T> {
     }
 }
 
+/// What [`RTree::visit`] passes to the visitor.
+#[derive(Debug)]
+pub enum Visit<'a, T> {
+    Node { aabb: &'a AABB, depth: usize },
+    Leaf(&'a Leaf<T>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Relation {
+    Overlaps,
+    ContainedIn,
+}
+
+/// Iterates over the leaves overlapping or contained in an AABB.
+///
+/// The leaves of nodes completely inside the AABB are yielded without
+/// testing them.
+pub struct LeafQuery<'a, T> {
+    tree: &'a RTree<T>,
+    aabb: AABB,
+    relation: Relation,
+    /// `(layer, index)` of the nodes left to test
+    stack: Vec<(usize, usize)>,
+    /// the leaves of a node completely inside the AABB
+    inside: std::slice::Iter<'a, Leaf<T>>,
+}
+
+impl<'a, T> LeafQuery<'a, T> {
+    fn new(tree: &'a RTree<T>, aabb: AABB, relation: Relation) -> Self {
+        Self {
+            tree,
+            aabb,
+            relation,
+            stack: vec![(0, 0)],
+            inside: [].iter(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for LeafQuery<'a, T> {
+    type Item = &'a Leaf<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(leaf) = self.inside.next() {
+                return Some(leaf);
+            }
+            let (layer, index) = self.stack.pop()?;
+            let Some(nodes) = self.tree.layers.get(layer) else {
+                let leaf = &self.tree.leaves[index];
+                let matches = match self.relation {
+                    Relation::Overlaps => leaf.aabb.overlaps(&self.aabb),
+                    Relation::ContainedIn => self.aabb.contains(&leaf.aabb),
+                };
+                if matches {
+                    return Some(leaf);
+                }
+                continue;
+            };
+            let node = &nodes[index];
+            if !node.aabb.overlaps(&self.aabb) {
+                continue;
+            }
+            if self.aabb.contains(&node.aabb) {
+                let range = self.tree.leaf_range(layer, index);
+                self.inside = self.tree.leaves[range].iter();
+            } else {
+                self.stack.extend(
+                    (node.start..node.end).rev().map(|i| (layer + 1, i)),
+                );
+            }
+        }
+    }
+}
+
+/// Iterates over the leaves that may be inside a frustum.
+///
+/// Nodes fully inside the frustum yield all of their leaves without testing
+/// them, so the leaves may be slightly outside, see [`Frustum::classify`].
+pub struct FrustumQuery<'a, 'f, T> {
+    tree: &'a RTree<T>,
+    frustum: &'f Frustum,
+    /// `(layer, index)` of the nodes left to test
+    stack: Vec<(usize, usize)>,
+    /// the leaves of a node fully inside the frustum
+    inside: std::slice::Iter<'a, Leaf<T>>,
+}
+
+impl<'a, 'f, T> FrustumQuery<'a, 'f, T> {
+    pub fn new(tree: &'a RTree<T>, frustum: &'f Frustum) -> Self {
+        Self {
+            tree,
+            frustum,
+            stack: vec![(0, 0)],
+            inside: [].iter(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for FrustumQuery<'a, '_, T> {
+    type Item = &'a Leaf<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(leaf) = self.inside.next() {
+                return Some(leaf);
+            }
+            let (layer, index) = self.stack.pop()?;
+            let Some(nodes) = self.tree.layers.get(layer) else {
+                let leaf = &self.tree.leaves[index];
+                let aabb = &leaf.aabb;
+                if self.frustum.classify(aabb.min, aabb.max)
+                    != Containment::Outside
+                {
+                    return Some(leaf);
+                }
+                continue;
+            };
+            let node = &nodes[index];
+            match self.frustum.classify(node.aabb.min, node.aabb.max) {
+                Containment::Outside => {}
+                Containment::Inside => {
+                    let range = self.tree.leaf_range(layer, index);
+                    self.inside = self.tree.leaves[range].iter();
+                }
+                Containment::Intersecting => self.stack.extend(
+                    (node.start..node.end).rev().map(|i| (layer + 1, i)),
+                ),
+            }
+        }
+    }
+}
+
+impl<T> FrustumIndex for RTree<T> {
+    type Item<'a>
+        = (&'a AABB, &'a T)
+    where
+        T: 'a;
+
+    fn visible<'a>(
+        &'a self,
+        frustum: &Frustum,
+        visit: &mut dyn FnMut((&'a AABB, &'a T)),
+    ) {
+        for leaf in self.query_frustum(frustum) {
+            visit((&leaf.aabb, &leaf.data));
+        }
+    }
+}
+
+/// The items below node `index` of `layer`, in layers where the children
+/// of every node are contiguous.
+pub(crate) fn leaf_range(
+    layers: &[Vec<Node>],
+    layer: usize,
+    index: usize,
+) -> Range<usize> {
+    let node = &layers[layer][index];
+    let (mut start, mut end) = (node.start, node.end);
+    for nodes in &layers[layer + 1..] {
+        if start == end {
+            break;
+        }
+        (start, end) = (nodes[start].start, nodes[end - 1].end);
+    }
+    start..end
+}
+
+/// Builds the node layers of an OMT over `items`, reordering the items so
+/// those of every node are contiguous.
+///
+/// `pos` is where an item is sorted by, `bounds` merges a run of items.
+pub(crate) fn omt_layers<I>(
+    items: &mut [I],
+    pos: impl Fn(&I) -> [f64; 3],
+    bounds: impl Fn(&[I]) -> AABB,
+) -> Vec<Vec<Node>> {
+    let height = if items.len() <= MAX_NODE_SIZE {
+        1
+    } else {
+        (items.len() - 1).ilog(MAX_NODE_SIZE) as usize + 1
+    };
+    let mut layers = Vec::with_capacity(height);
+    layers.push(vec![Node {
+        start: 0,
+        end: items.len(),
+        aabb: bounds(items),
+    }]);
+    for _ in 1..height {
+        let old_layer = layers.last_mut().unwrap();
+        let mut new_layer = Vec::new();
+        for node in old_layer {
+            omt_split(
+                node,
+                &mut items[node.start..node.end],
+                &pos,
+                &bounds,
+                &mut new_layer,
+            );
+        }
+        layers.push(new_layer);
+    }
+    layers
+}
+
+fn omt_split<I>(
+    node: &mut Node,
+    node_items: &mut [I],
+    pos: &impl Fn(&I) -> [f64; 3],
+    bounds: &impl Fn(&[I]) -> AABB,
+    new_nodes: &mut Vec<Node>,
+) {
+    let node_count = split_count(node_items.len());
+    debug_assert!(node_count >= MIN_NODE_SIZE, "{node_count} children");
+    let splits = calculate_splits(node_count, node_items.len(), 3);
+    node_items.sort_unstable_by(|i1, i2| pos(i1)[0].total_cmp(&pos(i2)[0]));
+    let mut i = 0;
+    for &size in &splits[0] {
+        node_items[i..][..size]
+            .sort_unstable_by(|i1, i2| pos(i1)[1].total_cmp(&pos(i2)[1]));
+        i += size;
+    }
+    let mut i = 0;
+    for &size in &splits[1] {
+        node_items[i..][..size]
+            .sort_unstable_by(|i1, i2| pos(i1)[2].total_cmp(&pos(i2)[2]));
+        i += size;
+    }
+
+    let mut child_start = node.start;
+    let mut i = 0;
+    node.start = new_nodes.len();
+    for &size in &splits[2] {
+        new_nodes.push(Node {
+            start: child_start,
+            end: child_start + size,
+            aabb: bounds(&node_items[i..][..size]),
+        });
+        i += size;
+        child_start += size
+    }
+    node.end = new_nodes.len();
+}
+
+/// The number of children a node with `item_count` leaves is split into, so
+/// that every child fits into a subtree one level lower.
+fn split_count(item_count: usize) -> usize {
+    // integer logarithm, the float version is off by one for exact powers
+    let mut height = 0;
+    while MAX_NODE_SIZE.pow(height) < item_count {
+        height += 1;
+    }
+    item_count.div_ceil(MAX_NODE_SIZE.pow(height.saturating_sub(1)))
+}
+
 fn calculate_splits(
     node_count: usize,
     item_count: usize,
@@ -277,18 +704,16 @@ fn calculate_splits(
             .powf((dimensions - i) as f64 / dimensions as f64)
             .round() as usize;
         let item_count = last.len();
-        let small_size = item_count / chunk_count;
-        let large_size = small_size + 1;
-        let large_count = item_count - chunk_count * small_size;
-        let small_count = chunk_count - large_count;
         let mut i = 0;
-        // TODO: distribute the items from `last` more evenly
-        let new_split = iter::empty()
-            .chain(iter::repeat(small_size).take(small_count))
-            .chain(iter::repeat(large_size).take(large_count))
+        // chunk `c` ends at `item_count * (c + 1) / chunk_count`, which
+        // spreads the larger chunks evenly instead of bunching them up at
+        // the end
+        let new_split = (0..chunk_count)
             .map(|c| {
-                let res = last[i..][..c].iter().sum();
-                i += c;
+                let size = item_count * (c + 1) / chunk_count
+                    - item_count * c / chunk_count;
+                let res = last[i..][..size].iter().sum();
+                i += size;
                 res
             })
             .collect();
@@ -299,40 +724,29 @@ fn calculate_splits(
     splits
 }
 
+/// `n` uniformly distributed AABBs inside `bounds`, with edge lengths in
+/// `size_bounds`.
+///
+/// Not reproducible, see [`generate`](crate::generate) for seeded
+/// generation and other distributions.
 pub fn rand_aabbs(
     n: usize,
     bounds: AABB,
     size_bounds: Range<f64>,
 ) -> Vec<AABB> {
-    let mut aabbs = Vec::with_capacity(n);
-    for _ in 0..n {
-        aabbs.push(rand_aabb(bounds, size_bounds.clone()));
-    }
-    aabbs
+    let uniform = Distribution::Uniform { size: size_bounds };
+    Generator::new(rand::thread_rng(), bounds, uniform)
+        .expect("a uniform distribution is always valid")
+        .take(n)
+        .collect()
 }
 
 pub fn rand_aabb(bounds: AABB, size_bounds: Range<f64>) -> AABB {
-    let mut rng = rand::thread_rng();
-    let half_size = [
-        size_bounds.clone().sample_single(&mut rng) / 0.5,
-        size_bounds.clone().sample_single(&mut rng) / 0.5,
-        size_bounds.clone().sample_single(&mut rng) / 0.5,
-    ];
-    let pos = [
-        (bounds.min[0] + half_size[0]..bounds.max[0] - half_size[0])
-            .sample_single(&mut rng),
-        (bounds.min[1] + half_size[1]..bounds.max[1] - half_size[1])
-            .sample_single(&mut rng),
-        (bounds.min[2] + half_size[2]..bounds.max[2] - half_size[2])
-            .sample_single(&mut rng),
-    ];
-    AABB {
-        min: [0, 1, 2].map(|i| pos[i] - half_size[i]),
-        max: [0, 1, 2].map(|i| pos[i] + half_size[i]),
-    }
-}
-
-#[derive(Clone, Copy, Debug)]
+    rand_aabbs(1, bounds, size_bounds)[0]
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct AABB {
     pub min: [f64; 3],
     pub max: [f64; 3],
@@ -347,12 +761,28 @@ impl AABB {
         [0, 1, 2].map(|i| self.max[i] - self.min[i])
     }
 
+    /// A zero sized AABB at `point`.
+    pub fn point(point: [f64; 3]) -> AABB {
+        AABB {
+            min: point,
+            max: point,
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = [f64; 3]>) -> AABB {
+        let mut aabb = AABB::merge([]);
+        for point in points {
+            aabb = AABB::merge([&aabb, &AABB::point(point)]);
+        }
+        aabb
+    }
+
     pub fn merge<'a>(aabbs: impl IntoIterator<Item = &'a AABB>) -> AABB {
         let mut iter = aabbs.into_iter();
         let Some(first) = iter.next() else {
             return AABB {
-                min: [std::f64::MAX; 3],
-                max: [std::f64::MIN; 3],
+                min: [f64::MAX; 3],
+                max: [f64::MIN; 3],
             };
         };
         iter.fold(*first, |mut a, b| {
@@ -371,6 +801,27 @@ impl AABB {
         w * h * d
     }
 
+    pub fn surface_area(&self) -> f64 {
+        let [w, h, d] = self.size();
+        2.0 * (w * h + h * d + d * w)
+    }
+
+    pub fn intersection_volume(&self, other: &AABB) -> f64 {
+        [0, 1, 2]
+            .map(|i| {
+                (self.max[i].min(other.max[i]) - self.min[i].max(other.min[i]))
+                    .max(0.0)
+            })
+            .iter()
+            .product()
+    }
+
+    /// Whether `other` is completely inside `self`.
+    pub fn contains(&self, other: &AABB) -> bool {
+        (0..3)
+            .all(|i| self.min[i] <= other.min[i] && other.max[i] <= self.max[i])
+    }
+
     pub fn overlaps(&self, other: &AABB) -> bool {
         self.min[0] <= other.max[0]
             && self.min[1] <= other.max[1]
@@ -392,3 +843,226 @@ impl Leaf<()> {
         Self::new(aabb, ())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use graphics::camera::FirstPersonCamera;
+
+    use super::*;
+    use crate::generate::uniform_aabbs;
+
+    const BOUNDS: AABB = AABB {
+        min: [-10.0, -10.0, -10.0],
+        max: [10.0, 10.0, 10.0],
+    };
+
+    /// Counts where the float logarithm used to be off, plus their
+    /// neighbours.
+    const EDGE_COUNTS: [usize; 14] =
+        [0, 1, 2, 6, 7, 35, 36, 37, 215, 216, 217, 1295, 1296, 1297];
+
+    /// Smallest `h` with `MAX_NODE_SIZE.pow(h) >= item_count`, at least 1.
+    fn expected_layers(item_count: usize) -> usize {
+        let mut height = 1;
+        while MAX_NODE_SIZE.pow(height) < item_count {
+            height += 1;
+        }
+        height as usize
+    }
+
+    /// The child counts of every node, layer by layer, as `RTree::new` would
+    /// build them, without having to sort any leaves.
+    fn shape(item_count: usize) -> Vec<Vec<usize>> {
+        let mut node_sizes = vec![item_count];
+        let mut layers = vec![];
+        for _ in 1..expected_layers(item_count) {
+            let mut children = vec![];
+            let mut child_sizes = vec![];
+            for &size in &node_sizes {
+                let splits = calculate_splits(split_count(size), size, 3);
+                children.push(splits[2].len());
+                assert_eq!(splits[2].iter().sum::<usize>(), size);
+                child_sizes.extend_from_slice(&splits[2]);
+            }
+            layers.push(children);
+            node_sizes = child_sizes;
+        }
+        layers.push(node_sizes);
+        layers
+    }
+
+    #[test]
+    fn fill_factor_and_height() {
+        for item_count in 1..=10_000 {
+            let layers = shape(item_count);
+            assert_eq!(layers.len(), expected_layers(item_count));
+            for (depth, layer) in layers.iter().enumerate() {
+                for &children in layer {
+                    assert!(
+                        children <= MAX_NODE_SIZE,
+                        "{item_count} items: node at depth {depth} has \
+                         {children} children"
+                    );
+                    assert!(
+                        depth == 0 || children >= MIN_NODE_SIZE,
+                        "{item_count} items: node at depth {depth} has \
+                         {children} children"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn splits_are_even() {
+        for node_count in 1..=MAX_NODE_SIZE {
+            let min_items = MAX_NODE_SIZE.pow(2) * (node_count - 1) + 1;
+            for item_count in min_items.max(node_count)..=min_items + 100 {
+                let splits = calculate_splits(node_count, item_count, 3);
+                let nodes = &splits[2];
+                let min = nodes.iter().min().unwrap();
+                let max = nodes.iter().max().unwrap();
+                assert!(max - min <= 1, "{item_count}: {nodes:?}");
+            }
+        }
+        // 8 nodes in 2 slabs, the 4 larger nodes should not end up in the
+        // same slab
+        assert_eq!(calculate_splits(8, 68, 3)[0], vec![34, 34]);
+    }
+
+    #[test]
+    fn frustum_query_matches_brute_force() {
+        // at the origin looking along +z, so about half of the leaves are
+        // visible
+        let frustum = FirstPersonCamera::default().frustum();
+        for count in [0, 1, 7, 2000] {
+            let aabbs = uniform_aabbs(2, count, BOUNDS, 0.1..2.0);
+            let expected: Vec<usize> = (0..count)
+                .filter(|&i| {
+                    frustum.classify(aabbs[i].min, aabbs[i].max)
+                        != Containment::Outside
+                })
+                .collect();
+            let leaves =
+                aabbs.iter().enumerate().map(|(i, &a)| Leaf::new(a, i));
+            let omt = RTree::new(leaves.collect());
+            let mut found: Vec<usize> =
+                omt.query_frustum(&frustum).map(|leaf| leaf.data).collect();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+
+            let mut rtree = crate::rtree::RTree::new();
+            for (i, &aabb) in aabbs.iter().enumerate() {
+                rtree.insert(aabb, i);
+            }
+            let mut found: Vec<usize> = rtree
+                .search_frustum(&frustum)
+                .into_iter()
+                .copied()
+                .collect();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+
+            // both trees report the same items to `Canvas::draw_visible`
+            let expected: Vec<_> =
+                expected.iter().map(|&i| (aabbs[i], i)).collect();
+            let mut visible = [Vec::new(), Vec::new()];
+            omt.visible(&frustum, &mut |(aabb, &i)| {
+                visible[0].push((*aabb, i))
+            });
+            rtree.visible(&frustum, &mut |(aabb, &i)| {
+                visible[1].push((*aabb, i))
+            });
+            for mut visible in visible {
+                visible.sort_unstable_by_key(|&(_, i)| i);
+                assert_eq!(visible, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn leaf_queries_match_brute_force() {
+        let queries = uniform_aabbs(3, 20, BOUNDS, 1.0..8.0);
+        for count in [0, 1, 7, 2000] {
+            let aabbs = uniform_aabbs(2, count, BOUNDS, 0.1..2.0);
+            let leaves =
+                aabbs.iter().enumerate().map(|(i, &a)| Leaf::new(a, i));
+            let omt = RTree::new(leaves.collect());
+            let sorted = |leaves: &mut dyn Iterator<Item = &Leaf<usize>>| {
+                let mut found: Vec<usize> = leaves.map(|l| l.data).collect();
+                found.sort_unstable();
+                found
+            };
+            let mut buffer = Vec::new();
+            for query in &queries {
+                let overlapping: Vec<usize> =
+                    (0..count).filter(|&i| aabbs[i].overlaps(query)).collect();
+                let contained: Vec<usize> =
+                    (0..count).filter(|&i| query.contains(&aabbs[i])).collect();
+                let leaves = &mut omt.query_leaves(*query);
+                assert_eq!(sorted(leaves), overlapping);
+                let leaves = &mut omt.query_contained(*query);
+                assert_eq!(sorted(leaves), contained);
+                omt.query_into(query, &mut buffer);
+                assert_eq!(sorted(&mut buffer.iter().copied()), overlapping);
+            }
+        }
+    }
+
+    #[test]
+    fn visit_stops_early() {
+        let aabbs = uniform_aabbs(2, 2000, BOUNDS, 0.1..2.0);
+        let leaves = aabbs.iter().enumerate().map(|(i, &a)| Leaf::new(a, i));
+        let omt = RTree::new(leaves.collect());
+        let mut visited = 0;
+        let found = omt.visit(&BOUNDS, |item| {
+            visited += 1;
+            match item {
+                Visit::Leaf(leaf) if leaf.data == 1234 => {
+                    ControlFlow::Break(leaf.data)
+                }
+                _ => ControlFlow::Continue(()),
+            }
+        });
+        assert_eq!(found, ControlFlow::Break(1234));
+        assert!(
+            visited < 2000 + omt.layers.iter().map(Vec::len).sum::<usize>()
+        );
+
+        let mut leaves = 0;
+        let all = omt.visit::<()>(&BOUNDS, |item| {
+            leaves += matches!(item, Visit::Leaf(_)) as usize;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(all, ControlFlow::Continue(()));
+        assert_eq!(leaves, 2000);
+    }
+
+    #[test]
+    fn edge_case_trees() {
+        for item_count in EDGE_COUNTS {
+            let leaves: Vec<_> =
+                uniform_aabbs(1, item_count, BOUNDS, 0.01..0.1)
+                    .into_iter()
+                    .map(Leaf::new_empty)
+                    .collect();
+            let tree = RTree::new(leaves);
+            assert_eq!(tree.height(), expected_layers(item_count) + 1);
+            assert_eq!(tree.leaves.len(), item_count);
+            assert_eq!(tree.layers[0].len(), 1);
+            for (depth, layer) in tree.layers.iter().enumerate() {
+                let child_count = tree
+                    .layers
+                    .get(depth + 1)
+                    .map_or(tree.leaves.len(), Vec::len);
+                let mut next = 0;
+                for node in layer {
+                    assert_eq!(node.start, next);
+                    assert!(node.end - node.start <= MAX_NODE_SIZE);
+                    next = node.end;
+                }
+                assert_eq!(next, child_count);
+            }
+        }
+    }
+}
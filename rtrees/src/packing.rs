@@ -0,0 +1,223 @@
+//! Bottom-up packing loaders for [`omt::RTree`].
+//!
+//! The leaves are ordered once, cut into runs of at most `MAX_NODE_SIZE`,
+//! and the resulting nodes are grouped the same way until a single root
+//! remains. The layers are then reversed, so the trees have the same flat
+//! layout as the ones built by the OMT loader.
+//!
+//! [`omt::RTree`]: crate::omt::RTree
+
+use crate::omt::{Leaf, Node, RTree, AABB, MAX_NODE_SIZE, MIN_NODE_SIZE};
+
+/// Bits per axis used when mapping box centers onto a space filling curve.
+const CURVE_BITS: u32 = 16;
+
+/// Sort-Tile-Recursive packing.
+pub(crate) fn str<T>(mut leaves: Vec<Leaf<T>>) -> RTree<T> {
+    str_sort(&mut leaves, |l| &l.aabb, 0);
+    // the tiles are multiples of `MAX_NODE_SIZE`, so only full runs line up
+    // with their borders
+    pack(leaves, full_sizes, |nodes| str_sort(nodes, |n| &n.aabb, 0))
+}
+
+/// Hilbert curve packing.
+pub(crate) fn hilbert<T>(leaves: Vec<Leaf<T>>) -> RTree<T> {
+    curve_pack(leaves, hilbert_index)
+}
+
+/// Morton (Z-order) curve packing.
+pub(crate) fn z_order<T>(leaves: Vec<Leaf<T>>) -> RTree<T> {
+    curve_pack(leaves, morton_index)
+}
+
+fn curve_pack<T>(
+    mut leaves: Vec<Leaf<T>>,
+    index: fn([u32; 3]) -> u64,
+) -> RTree<T> {
+    let bounds = AABB::merge(leaves.iter().map(|l| &l.aabb));
+    leaves.sort_by_cached_key(|l| index(quantize(&bounds, &l.aabb)));
+    // consecutive runs along the curve are already close to each other,
+    // so the upper layers keep the order of the layer below
+    pack(leaves, even_sizes, |_| {})
+}
+
+fn pack<T>(
+    leaves: Vec<Leaf<T>>,
+    sizes: fn(usize) -> Vec<usize>,
+    mut order: impl FnMut(&mut [Node]),
+) -> RTree<T> {
+    let mut layers = Vec::new();
+    let mut layer = group(&leaves, sizes, |l| &l.aabb);
+    while layer.len() > 1 {
+        order(&mut layer);
+        let parents = group(&layer, sizes, |n| &n.aabb);
+        layers.push(layer);
+        layer = parents;
+    }
+    if layer.is_empty() {
+        layer.push(Node {
+            aabb: AABB::merge([]),
+            start: 0,
+            end: 0,
+        });
+    }
+    layers.push(layer);
+    layers.reverse();
+    let leaves = relayout(&mut layers, leaves);
+    RTree { layers, leaves }
+}
+
+/// Reorders every layer below the root, and the leaves, so the children of
+/// each node are contiguous and in the order of their parents again after
+/// `order` shuffled the nodes of a layer.
+fn relayout<T>(layers: &mut [Vec<Node>], leaves: Vec<Leaf<T>>) -> Vec<Leaf<T>> {
+    for depth in 1..layers.len() {
+        let (parents, children) = layers.split_at_mut(depth);
+        let children = &mut children[0];
+        *children = reorder(&mut parents[depth - 1], std::mem::take(children));
+    }
+    reorder(layers.last_mut().unwrap(), leaves)
+}
+
+/// Moves the children of `parents` into the order of their parents and
+/// updates the ranges of the parents to match.
+fn reorder<I>(parents: &mut [Node], children: Vec<I>) -> Vec<I> {
+    let mut children: Vec<Option<I>> = children.into_iter().map(Some).collect();
+    let mut reordered = Vec::with_capacity(children.len());
+    for node in parents {
+        let start = reordered.len();
+        reordered.extend(
+            children[node.start..node.end]
+                .iter_mut()
+                .map(|child| child.take().expect("a child with two parents")),
+        );
+        node.start = start;
+        node.end = reordered.len();
+    }
+    reordered
+}
+
+/// Groups consecutive items into nodes of the given sizes.
+fn group<I>(
+    items: &[I],
+    sizes: fn(usize) -> Vec<usize>,
+    aabb: impl Fn(&I) -> &AABB,
+) -> Vec<Node> {
+    let mut start = 0;
+    sizes(items.len())
+        .into_iter()
+        .map(|size| {
+            let node = Node {
+                aabb: AABB::merge(items[start..][..size].iter().map(&aabb)),
+                start,
+                end: start + size,
+            };
+            start += size;
+            node
+        })
+        .collect()
+}
+
+/// As few nodes as possible, with sizes differing by at most one.
+fn even_sizes(item_count: usize) -> Vec<usize> {
+    let node_count = item_count.div_ceil(MAX_NODE_SIZE);
+    (0..node_count)
+        .map(|i| {
+            item_count * (i + 1) / node_count - item_count * i / node_count
+        })
+        .collect()
+}
+
+/// Full nodes, with the remainder in the last one. A remainder below
+/// `MIN_NODE_SIZE` takes the missing items from the node before.
+fn full_sizes(item_count: usize) -> Vec<usize> {
+    let mut sizes: Vec<usize> = (0..item_count)
+        .step_by(MAX_NODE_SIZE)
+        .map(|start| MAX_NODE_SIZE.min(item_count - start))
+        .collect();
+    if let [.., before, last] = sizes.as_mut_slice() {
+        let missing = MIN_NODE_SIZE.saturating_sub(*last);
+        *before -= missing;
+        *last += missing;
+    }
+    sizes
+}
+
+/// Orders the items so that consecutive runs of `MAX_NODE_SIZE` form tiles,
+/// slicing the space along the x, then y, then z axis.
+fn str_sort<I>(items: &mut [I], aabb: fn(&I) -> &AABB, axis: usize) {
+    items.sort_unstable_by(|i1, i2| {
+        aabb(i1).pos()[axis].total_cmp(&aabb(i2).pos()[axis])
+    });
+    if axis == 2 {
+        return;
+    }
+    let pages = items.len().div_ceil(MAX_NODE_SIZE);
+    let slabs = (pages as f64).powf(1.0 / (3 - axis) as f64).ceil() as usize;
+    let slab_size = MAX_NODE_SIZE * pages.div_ceil(slabs.max(1));
+    for slab in items.chunks_mut(slab_size.max(1)) {
+        str_sort(slab, aabb, axis + 1);
+    }
+}
+
+/// Maps the center of `aabb` onto a `CURVE_BITS` grid spanning `bounds`.
+fn quantize(bounds: &AABB, aabb: &AABB) -> [u32; 3] {
+    let max = ((1u32 << CURVE_BITS) - 1) as f64;
+    let pos = aabb.pos();
+    let size = bounds.size();
+    [0, 1, 2].map(|i| {
+        if size[i] > 0.0 {
+            ((pos[i] - bounds.min[i]) / size[i] * max) as u32
+        } else {
+            0
+        }
+    })
+}
+
+fn morton_index(coords: [u32; 3]) -> u64 {
+    interleave(coords)
+}
+
+/// Position along the Hilbert curve, using Skilling's transpose algorithm.
+///
+/// J. Skilling, "Programming the Hilbert curve", AIP Conf. Proc. 707, 2004
+fn hilbert_index(mut x: [u32; 3]) -> u64 {
+    let m = 1u32 << (CURVE_BITS - 1);
+    // inverse undo
+    let mut q = m;
+    while q > 1 {
+        let p = q - 1;
+        for i in 0..3 {
+            if x[i] & q != 0 {
+                x[0] ^= p;
+            } else {
+                let t = (x[0] ^ x[i]) & p;
+                x[0] ^= t;
+                x[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+    // gray encode
+    x[1] ^= x[0];
+    x[2] ^= x[1];
+    let mut t = 0;
+    let mut q = m;
+    while q > 1 {
+        if x[2] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    interleave(x.map(|c| c ^ t))
+}
+
+fn interleave(coords: [u32; 3]) -> u64 {
+    let mut index = 0;
+    for bit in (0..CURVE_BITS).rev() {
+        for c in coords {
+            index = (index << 1) | ((c >> bit) & 1) as u64;
+        }
+    }
+    index
+}
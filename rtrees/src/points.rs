@@ -0,0 +1,380 @@
+//! A tree over points, for datasets where [`omt::RTree`] would store every
+//! point as a zero sized AABB.
+//!
+//! It is bulk loaded with the same OMT splitting and has the same flat
+//! layout, with the points in place of the leaves, so a point takes half
+//! the memory of a leaf.
+//!
+//! [`omt::RTree`]: crate::omt::RTree
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use graphics::math::Containment;
+
+use crate::dataset::Point;
+use crate::omt::{self, Node, AABB};
+
+pub struct PointTree<T> {
+    layers: Vec<Vec<Node>>,
+    points: Vec<PointLeaf<T>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointLeaf<T> {
+    pub pos: Point,
+    pub data: T,
+}
+
+impl<T> PointLeaf<T> {
+    pub fn new(pos: Point, data: T) -> Self {
+        Self { pos, data }
+    }
+}
+
+/// A result of [`PointTree::nearest`].
+#[derive(Debug)]
+pub struct Neighbour<'a, T> {
+    pub distance: f64,
+    pub leaf: &'a PointLeaf<T>,
+}
+
+impl<T> PointTree<T> {
+    pub fn new(mut points: Vec<PointLeaf<T>>) -> Self {
+        let layers = omt::omt_layers(
+            &mut points,
+            |point| point.pos,
+            |points| AABB::from_points(points.iter().map(|p| p.pos)),
+        );
+        Self { layers, points }
+    }
+
+    /// Number of levels, counting the points as well.
+    pub fn height(&self) -> usize {
+        self.layers.len() + 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn points(&self) -> impl Iterator<Item = &PointLeaf<T>> {
+        self.points.iter()
+    }
+
+    /// Heap memory used by the tree, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.layers.capacity() * size_of::<Vec<Node>>()
+            + self
+                .layers
+                .iter()
+                .map(|l| l.capacity() * size_of::<Node>())
+                .sum::<usize>()
+            + self.points.capacity() * size_of::<PointLeaf<T>>()
+    }
+
+    /// The points inside `aabb`, borders included.
+    pub fn query_box(&self, aabb: AABB) -> PointQuery<'_, T> {
+        PointQuery::new(self, Region::Box(aabb))
+    }
+
+    /// The points at most `radius` away from `center`.
+    pub fn query_radius(
+        &self,
+        center: Point,
+        radius: f64,
+    ) -> PointQuery<'_, T> {
+        PointQuery::new(self, Region::Sphere { center, radius })
+    }
+
+    /// The `k` points closest to `point`, closest first.
+    ///
+    /// Visits the nodes and points best first, so only the nodes that could
+    /// hold one of the `k` points are opened.
+    pub fn nearest(&self, point: Point, k: usize) -> Vec<Neighbour<'_, T>> {
+        let mut neighbours = Vec::with_capacity(k.min(self.len()));
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance2: 0.0,
+            layer: 0,
+            index: 0,
+        });
+        while neighbours.len() < k {
+            let Some(Candidate {
+                distance2,
+                layer,
+                index,
+            }) = queue.pop()
+            else {
+                break;
+            };
+            let Some(nodes) = self.layers.get(layer) else {
+                neighbours.push(Neighbour {
+                    distance: distance2.sqrt(),
+                    leaf: &self.points[index],
+                });
+                continue;
+            };
+            let node = &nodes[index];
+            let children = node.start..node.end;
+            queue.extend(children.map(|child| Candidate {
+                distance2: match self.layers.get(layer + 1) {
+                    Some(nodes) => {
+                        squared_distance_to(&nodes[child].aabb, point)
+                    }
+                    None => squared_distance(self.points[child].pos, point),
+                },
+                layer: layer + 1,
+                index: child,
+            }));
+        }
+        neighbours
+    }
+}
+
+/// The shape a [`PointQuery`] looks for points in.
+#[derive(Debug, Clone, Copy)]
+enum Region {
+    Box(AABB),
+    Sphere { center: Point, radius: f64 },
+}
+
+impl Region {
+    fn classify(&self, aabb: &AABB) -> Containment {
+        match *self {
+            Region::Box(ref region) => {
+                if !region.overlaps(aabb) {
+                    Containment::Outside
+                } else if region.contains(aabb) {
+                    Containment::Inside
+                } else {
+                    Containment::Intersecting
+                }
+            }
+            Region::Sphere { center, radius } => {
+                if squared_distance_to(aabb, center) > radius * radius {
+                    Containment::Outside
+                } else if farthest_squared_distance(aabb, center)
+                    <= radius * radius
+                {
+                    Containment::Inside
+                } else {
+                    Containment::Intersecting
+                }
+            }
+        }
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        match *self {
+            Region::Box(ref region) => region.contains(&AABB::point(point)),
+            Region::Sphere { center, radius } => {
+                squared_distance(center, point) <= radius * radius
+            }
+        }
+    }
+}
+
+/// Iterates over the points inside a box or a sphere.
+///
+/// The points of nodes completely inside are yielded without testing them.
+pub struct PointQuery<'a, T> {
+    tree: &'a PointTree<T>,
+    region: Region,
+    /// `(layer, index)` of the nodes left to test
+    stack: Vec<(usize, usize)>,
+    /// the points of a node completely inside the region
+    inside: std::slice::Iter<'a, PointLeaf<T>>,
+}
+
+impl<'a, T> PointQuery<'a, T> {
+    fn new(tree: &'a PointTree<T>, region: Region) -> Self {
+        Self {
+            tree,
+            region,
+            stack: vec![(0, 0)],
+            inside: [].iter(),
+        }
+    }
+}
+
+impl<'a, T> Iterator for PointQuery<'a, T> {
+    type Item = &'a PointLeaf<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(point) = self.inside.next() {
+                return Some(point);
+            }
+            let (layer, index) = self.stack.pop()?;
+            let Some(nodes) = self.tree.layers.get(layer) else {
+                let point = &self.tree.points[index];
+                if self.region.contains(point.pos) {
+                    return Some(point);
+                }
+                continue;
+            };
+            let node = &nodes[index];
+            match self.region.classify(&node.aabb) {
+                Containment::Outside => {}
+                Containment::Inside => {
+                    let range =
+                        omt::leaf_range(&self.tree.layers, layer, index);
+                    self.inside = self.tree.points[range].iter();
+                }
+                Containment::Intersecting => self.stack.extend(
+                    (node.start..node.end).rev().map(|i| (layer + 1, i)),
+                ),
+            }
+        }
+    }
+}
+
+/// A node or point in the queue of [`PointTree::nearest`], ordered so the
+/// closest one is popped first.
+struct Candidate {
+    distance2: f64,
+    layer: usize,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance2.total_cmp(&self.distance2)
+    }
+}
+
+fn squared_distance(a: Point, b: Point) -> f64 {
+    (0..3).map(|i| (a[i] - b[i]).powi(2)).sum()
+}
+
+/// The squared distance from `point` to the closest point of `aabb`.
+fn squared_distance_to(aabb: &AABB, point: Point) -> f64 {
+    (0..3)
+        .map(|i| {
+            let d = (aabb.min[i] - point[i]).max(point[i] - aabb.max[i]);
+            d.max(0.0).powi(2)
+        })
+        .sum()
+}
+
+/// The squared distance from `point` to the farthest corner of `aabb`.
+fn farthest_squared_distance(aabb: &AABB, point: Point) -> f64 {
+    (0..3)
+        .map(|i| {
+            let d = (point[i] - aabb.min[i]).abs();
+            d.max((aabb.max[i] - point[i]).abs()).powi(2)
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::uniform_aabbs;
+    use crate::omt::Leaf;
+
+    const BOUNDS: AABB = AABB {
+        min: [-10.0, -10.0, -10.0],
+        max: [10.0, 10.0, 10.0],
+    };
+
+    fn points(seed: u64, count: usize) -> Vec<Point> {
+        let aabbs = uniform_aabbs(seed, count, BOUNDS, 0.01..0.1);
+        aabbs.iter().map(AABB::pos).collect()
+    }
+
+    fn tree(points: &[Point]) -> PointTree<usize> {
+        let leaves = points.iter().enumerate();
+        PointTree::new(leaves.map(|(i, &p)| PointLeaf::new(p, i)).collect())
+    }
+
+    fn sorted<'a>(
+        points: impl Iterator<Item = &'a PointLeaf<usize>>,
+    ) -> Vec<usize> {
+        let mut found: Vec<usize> = points.map(|p| p.data).collect();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let queries = uniform_aabbs(3, 20, BOUNDS, 1.0..8.0);
+        for count in [0, 1, 7, 2000] {
+            let points = points(2, count);
+            let tree = tree(&points);
+            assert_eq!(tree.len(), count);
+            for query in &queries {
+                let expected: Vec<usize> = (0..count)
+                    .filter(|&i| query.contains(&AABB::point(points[i])))
+                    .collect();
+                assert_eq!(sorted(tree.query_box(*query)), expected);
+
+                let (center, radius) = (query.pos(), query.size()[0]);
+                let expected: Vec<usize> = (0..count)
+                    .filter(|&i| {
+                        squared_distance(points[i], center) <= radius.powi(2)
+                    })
+                    .collect();
+                assert_eq!(sorted(tree.query_radius(center, radius)), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let queries = points(3, 20);
+        for count in [0, 1, 7, 2000] {
+            let points = points(2, count);
+            let tree = tree(&points);
+            for &query in &queries {
+                for k in [0, 1, 5, 50] {
+                    let mut expected: Vec<f64> = points
+                        .iter()
+                        .map(|&p| squared_distance(p, query).sqrt())
+                        .collect();
+                    expected.sort_unstable_by(f64::total_cmp);
+                    expected.truncate(k);
+                    let found = tree.nearest(query, k);
+                    let distances: Vec<f64> =
+                        found.iter().map(|n| n.distance).collect();
+                    assert_eq!(distances, expected);
+                    for neighbour in found {
+                        let pos = points[neighbour.leaf.data];
+                        assert_eq!(neighbour.leaf.pos, pos);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smaller_than_omt() {
+        let points = points(4, 10_000);
+        let tree = PointTree::new(
+            points.iter().map(|&p| PointLeaf::new(p, ())).collect(),
+        );
+        let leaves = points.iter().map(|&p| Leaf::new_empty(AABB::point(p)));
+        let omt = omt::RTree::new(leaves.collect());
+        assert_eq!(tree.height(), omt.height());
+        assert!(tree.memory_usage() * 3 < omt.memory_usage() * 2);
+    }
+}
@@ -1,13 +1,23 @@
 #![allow(clippy::new_without_default)]
 #![allow(missing_debug_implementations)]
 
+use graphics::canvas::FrustumIndex;
+use graphics::math::{Containment, Frustum};
+
+use crate::join::{Join, SelfJoin, Traverse};
 use crate::omt::AABB;
+use crate::stats::{self, QueryStats, TreeStats};
+use crate::validate::{self, InvalidTree};
 use std::fmt::Debug;
 
 const NODE_MAX_CHILDREN: usize = 6;
+/// Splits leave at least this many children in both halves, and removes
+/// dissolve nodes with fewer.
+const NODE_MIN_CHILDREN: usize = 2;
 
 pub struct RTree<T> {
     height: usize,
+    len: usize,
     root: Option<Node<T>>,
 }
 
@@ -31,12 +41,15 @@ impl<T> RTree<T> {
     pub const fn new() -> Self {
         Self {
             height: 1,
+            len: 0,
             root: None,
         }
     }
 
     pub fn clear(&mut self) {
         self.root = None;
+        self.height = 1;
+        self.len = 0;
     }
 
     #[must_use]
@@ -57,7 +70,78 @@ impl<T> RTree<T> {
         collector
     }
 
+    /// The data of the leaves that may be inside `frustum`, the leaves of
+    /// nodes fully inside are collected without testing them.
+    #[must_use]
+    pub fn search_frustum(&self, frustum: &Frustum) -> Vec<&T> {
+        let mut collector = vec![];
+        if let Some(ref root) = self.root {
+            root.search_frustum_into(frustum, &mut |(_, data)| {
+                collector.push(data)
+            });
+        }
+        collector
+    }
+
+    /// Like [`search`](Self::search), but lazily and with the AABBs of the
+    /// leaves.
+    pub fn search_iter(&self, aabb: &AABB) -> Search<'_, T> {
+        Search {
+            aabb: *aabb,
+            stack: self.root.iter().filter(|r| r.aabb.overlaps(aabb)).collect(),
+            leaves: [].iter(),
+        }
+    }
+
+    /// Removes a leaf with exactly `aabb` whose data `matches`.
+    ///
+    /// Nodes left with fewer than [`NODE_MIN_CHILDREN`] children are
+    /// dissolved and their leaves inserted again, like Guttman's
+    /// CondenseTree.
+    pub fn remove(
+        &mut self,
+        aabb: &AABB,
+        mut matches: impl FnMut(&T) -> bool,
+    ) -> Option<T> {
+        let root = self.root.as_mut()?;
+        let mut orphans = Vec::new();
+        let removed = root.remove(aabb, &mut matches, &mut orphans);
+        self.len -= removed.is_some() as usize + orphans.len();
+        // the root may be underfull, but a root with a single child is
+        // replaced by the child
+        while let Some(root) = self.root.take() {
+            match root.entry {
+                Entry::Nodes(mut nodes) if nodes.len() <= 1 => {
+                    self.root = nodes.pop();
+                    self.height -= 1;
+                }
+                Entry::Leaves(ref leaves) if leaves.is_empty() => {}
+                _ => {
+                    self.root = Some(root);
+                    break;
+                }
+            }
+        }
+        if self.root.is_none() {
+            self.height = 1;
+        }
+        for (aabb, data) in orphans {
+            self.insert(aabb, data);
+        }
+        removed
+    }
+
+    /// Takes the tree apart into its leaves.
+    pub fn into_leaves(self) -> Vec<(AABB, T)> {
+        let mut leaves = vec![];
+        if let Some(root) = self.root {
+            root.into_leaves(&mut leaves);
+        }
+        leaves
+    }
+
     pub fn insert(&mut self, aabb: AABB, data: T) {
+        self.len += 1;
         self.root = Some(if let Some(mut root) = self.root.take() {
             if let InsertResult::Split(new_node) = root.insert(aabb, data) {
                 let mut vec = Vec::with_capacity(NODE_MAX_CHILDREN + 1);
@@ -86,6 +170,174 @@ impl<T> RTree<T> {
     pub fn height(&self) -> usize {
         self.height
     }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Heap memory used by the tree, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.root.as_ref().map_or(0, Node::memory_usage)
+    }
+
+    pub fn query_stats(&self, aabb: &AABB) -> QueryStats {
+        stats::query_stats(self, aabb)
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        stats::tree_stats(self, NODE_MAX_CHILDREN)
+    }
+
+    /// Checks the invariants of [`validate::validate`].
+    pub fn validate(&self) -> Result<(), InvalidTree> {
+        validate::validate(
+            self,
+            NODE_MIN_CHILDREN,
+            NODE_MAX_CHILDREN,
+            self.height,
+            self.len,
+        )
+    }
+
+    /// All pairs of overlapping leaves of `self` and `other`.
+    pub fn join<'a, Other: Traverse<'a>>(
+        &'a self,
+        other: Other,
+    ) -> Join<'a, &'a Self, Other> {
+        Join::new(self, other)
+    }
+
+    /// All pairs of overlapping leaves of `self`, without reporting a pair
+    /// twice or a leaf with itself.
+    pub fn self_join(&self) -> SelfJoin<'_, &Self> {
+        SelfJoin::new(self)
+    }
+}
+
+impl<T> FrustumIndex for RTree<T> {
+    type Item<'a>
+        = (&'a AABB, &'a T)
+    where
+        T: 'a;
+
+    fn visible<'a>(
+        &'a self,
+        frustum: &Frustum,
+        visit: &mut dyn FnMut((&'a AABB, &'a T)),
+    ) {
+        if let Some(ref root) = self.root {
+            root.search_frustum_into(frustum, visit);
+        }
+    }
+}
+
+/// Iterates over the leaves overlapping an AABB, see
+/// [`RTree::search_iter`].
+pub struct Search<'a, T> {
+    aabb: AABB,
+    stack: Vec<&'a Node<T>>,
+    leaves: std::slice::Iter<'a, Leaf<T>>,
+}
+
+impl<'a, T> Iterator for Search<'a, T> {
+    type Item = (&'a AABB, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for leaf in self.leaves.by_ref() {
+                if leaf.aabb.overlaps(&self.aabb) {
+                    return Some((&leaf.aabb, &leaf.data));
+                }
+            }
+            match self.stack.pop()?.entry {
+                Entry::Nodes(ref nodes) => self.stack.extend(
+                    nodes.iter().filter(|n| n.aabb.overlaps(&self.aabb)),
+                ),
+                Entry::Leaves(ref leaves) => self.leaves = leaves.iter(),
+            }
+        }
+    }
+}
+
+pub struct NodeRef<'a, T>(NodeRefInner<'a, T>);
+
+enum NodeRefInner<'a, T> {
+    Node(&'a Node<T>),
+    Leaf(&'a Leaf<T>),
+}
+
+impl<T> Clone for NodeRef<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for NodeRef<'_, T> {}
+
+impl<T> Clone for NodeRefInner<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for NodeRefInner<'_, T> {}
+
+impl<'a, T> Traverse<'a> for &'a RTree<T> {
+    type Node = NodeRef<'a, T>;
+    type Data = T;
+
+    fn root(self) -> Option<Self::Node> {
+        self.root
+            .as_ref()
+            .map(|root| NodeRef(NodeRefInner::Node(root)))
+    }
+
+    fn aabb(self, node: Self::Node) -> &'a AABB {
+        match node.0 {
+            NodeRefInner::Node(node) => &node.aabb,
+            NodeRefInner::Leaf(leaf) => &leaf.aabb,
+        }
+    }
+
+    fn children(
+        self,
+        node: Self::Node,
+        children: &mut Vec<Self::Node>,
+    ) -> bool {
+        let NodeRefInner::Node(node) = node.0 else {
+            return false;
+        };
+        match node.entry {
+            Entry::Nodes(ref nodes) => children
+                .extend(nodes.iter().map(|n| NodeRef(NodeRefInner::Node(n)))),
+            Entry::Leaves(ref leaves) => children
+                .extend(leaves.iter().map(|l| NodeRef(NodeRefInner::Leaf(l)))),
+        }
+        true
+    }
+
+    fn data(self, node: Self::Node) -> &'a T {
+        match node.0 {
+            NodeRefInner::Leaf(leaf) => &leaf.data,
+            NodeRefInner::Node(_) => panic!("not a leaf"),
+        }
+    }
+
+    fn same(node1: Self::Node, node2: Self::Node) -> bool {
+        match (node1.0, node2.0) {
+            (NodeRefInner::Node(n1), NodeRefInner::Node(n2)) => {
+                std::ptr::eq(n1, n2)
+            }
+            (NodeRefInner::Leaf(l1), NodeRefInner::Leaf(l2)) => {
+                std::ptr::eq(l1, l2)
+            }
+            _ => false,
+        }
+    }
 }
 
 impl<T> Node<T> {
@@ -112,6 +364,109 @@ impl<T> Node<T> {
         // println!("descends: {descends}");
     }
 
+    fn search_frustum_into<'a>(
+        &'a self,
+        frustum: &Frustum,
+        visit: &mut dyn FnMut((&'a AABB, &'a T)),
+    ) {
+        match frustum.classify(self.aabb.min, self.aabb.max) {
+            Containment::Outside => {}
+            Containment::Inside => self.for_each_leaf(visit),
+            Containment::Intersecting => match self.entry {
+                Entry::Nodes(ref nodes) => {
+                    for node in nodes {
+                        node.search_frustum_into(frustum, visit);
+                    }
+                }
+                Entry::Leaves(ref leaves) => {
+                    for leaf in leaves {
+                        let aabb = &leaf.aabb;
+                        if frustum.classify(aabb.min, aabb.max)
+                            != Containment::Outside
+                        {
+                            visit((aabb, &leaf.data));
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    fn for_each_leaf<'a>(&'a self, visit: &mut dyn FnMut((&'a AABB, &'a T))) {
+        match self.entry {
+            Entry::Nodes(ref nodes) => {
+                for node in nodes {
+                    node.for_each_leaf(visit);
+                }
+            }
+            Entry::Leaves(ref leaves) => {
+                for leaf in leaves {
+                    visit((&leaf.aabb, &leaf.data));
+                }
+            }
+        }
+    }
+
+    /// Collects the leaves of the nodes below that became underfull in
+    /// `orphans`.
+    fn remove(
+        &mut self,
+        aabb: &AABB,
+        matches: &mut dyn FnMut(&T) -> bool,
+        orphans: &mut Vec<(AABB, T)>,
+    ) -> Option<T> {
+        let removed = match self.entry {
+            Entry::Nodes(ref mut nodes) => {
+                let (i, data) =
+                    nodes.iter_mut().enumerate().find_map(|(i, n)| {
+                        if !n.aabb.overlaps(aabb) {
+                            return None;
+                        }
+                        n.remove(aabb, matches, orphans).map(|data| (i, data))
+                    })?;
+                if nodes[i].child_count() < NODE_MIN_CHILDREN {
+                    nodes.swap_remove(i).into_leaves(orphans);
+                }
+                data
+            }
+            Entry::Leaves(ref mut leaves) => {
+                let i = leaves.iter().position(|leaf| {
+                    leaf.aabb == *aabb && matches(&leaf.data)
+                })?;
+                leaves.swap_remove(i).data
+            }
+        };
+        self.aabb = match self.entry {
+            Entry::Nodes(ref nodes) => {
+                AABB::merge(nodes.iter().map(|n| &n.aabb))
+            }
+            Entry::Leaves(ref leaves) => {
+                AABB::merge(leaves.iter().map(|l| &l.aabb))
+            }
+        };
+        Some(removed)
+    }
+
+    fn child_count(&self) -> usize {
+        match self.entry {
+            Entry::Nodes(ref nodes) => nodes.len(),
+            Entry::Leaves(ref leaves) => leaves.len(),
+        }
+    }
+
+    fn into_leaves(self, collector: &mut Vec<(AABB, T)>) {
+        match self.entry {
+            Entry::Nodes(nodes) => {
+                for node in nodes {
+                    node.into_leaves(collector);
+                }
+            }
+            Entry::Leaves(leaves) => {
+                collector.extend(leaves.into_iter().map(|l| (l.aabb, l.data)))
+            }
+        }
+    }
+
     fn insert(&mut self, aabb: AABB, data: T) -> InsertResult<T> {
         match self.entry {
             Entry::Nodes(ref mut nodes) => {
@@ -154,6 +509,18 @@ impl<T> Node<T> {
         }
     }
 
+    fn memory_usage(&self) -> usize {
+        match self.entry {
+            Entry::Nodes(ref nodes) => {
+                nodes.capacity() * size_of::<Node<T>>()
+                    + nodes.iter().map(Node::memory_usage).sum::<usize>()
+            }
+            Entry::Leaves(ref leaves) => {
+                leaves.capacity() * size_of::<Leaf<T>>()
+            }
+        }
+    }
+
     fn aabbs_into<'a>(
         &'a self,
         depth: usize,
@@ -191,18 +558,21 @@ fn split<T: HasAABB>(nodes: &mut Vec<T>) -> ((AABB, Vec<T>), (AABB, Vec<T>)) {
         .unwrap();
     let mut nodes1 = Vec::with_capacity(NODE_MAX_CHILDREN + 1);
     let mut nodes2 = Vec::with_capacity(NODE_MAX_CHILDREN + 1);
-    // we need to worry about ordering
-    assert!(seed1 < seed2);
+    // the pairs are enumerated with `i < j`, so removing `seed2` first
+    // leaves `seed1` where it was
+    debug_assert!(seed1 < seed2);
     nodes2.push(nodes.swap_remove(seed2));
     nodes1.push(nodes.swap_remove(seed1));
-    let mut aabb1 = nodes1[0].aabb().clone();
-    let mut aabb2 = nodes2[0].aabb().clone();
-    for node in nodes.drain(..) {
+    let mut aabb1 = *nodes1[0].aabb();
+    let mut aabb2 = *nodes2[0].aabb();
+    let count = nodes.len();
+    for (i, node) in nodes.drain(..).enumerate() {
         let new_aabb1 = AABB::merge([&aabb1, node.aabb()]);
         let new_aabb2 = AABB::merge([&aabb2, node.aabb()]);
         let diff1 = new_aabb1.volume() - aabb1.volume();
         let diff2 = new_aabb2.volume() - aabb2.volume();
-        if diff1 < diff2 {
+        let remaining = count - i;
+        if into_first(nodes1.len(), nodes2.len(), remaining, diff1 < diff2) {
             nodes1.push(node);
             aabb1 = new_aabb1;
         } else {
@@ -238,12 +608,13 @@ fn quadratic_split<T: HasAABB>(
         .unwrap();
     let mut nodes1 = Vec::with_capacity(NODE_MAX_CHILDREN + 1);
     let mut nodes2 = Vec::with_capacity(NODE_MAX_CHILDREN + 1);
-    // we need to worry about ordering
-    assert!(seed1 < seed2);
+    // the pairs are enumerated with `i < j`, so removing `seed2` first
+    // leaves `seed1` where it was
+    debug_assert!(seed1 < seed2);
     nodes2.push(nodes.swap_remove(seed2));
     nodes1.push(nodes.swap_remove(seed1));
-    let mut aabb1 = nodes1[0].aabb().clone();
-    let mut aabb2 = nodes2[0].aabb().clone();
+    let mut aabb1 = *nodes1[0].aabb();
+    let mut aabb2 = *nodes2[0].aabb();
     while !nodes.is_empty() {
         // PickNext
         let next = nodes
@@ -259,12 +630,13 @@ fn quadratic_split<T: HasAABB>(
             .max_by(|(_, diff1), (_, diff2)| diff1.total_cmp(diff2))
             .map(|(i, _)| i)
             .expect("nodes cannot be empty");
+        let remaining = nodes.len();
         let node = nodes.swap_remove(next);
         let new_aabb1 = AABB::merge([&aabb1, node.aabb()]);
         let new_aabb2 = AABB::merge([&aabb2, node.aabb()]);
         let diff1 = new_aabb1.volume() - aabb1.volume();
         let diff2 = new_aabb2.volume() - aabb2.volume();
-        if diff1 < diff2 {
+        if into_first(nodes1.len(), nodes2.len(), remaining, diff1 < diff2) {
             nodes1.push(node);
             aabb1 = new_aabb1;
         } else {
@@ -275,6 +647,24 @@ fn quadratic_split<T: HasAABB>(
     ((aabb1, nodes1), (aabb2, nodes2))
 }
 
+/// Whether the next of `remaining` nodes goes into the first half, as
+/// `prefer_first` says unless a half needs all remaining nodes to reach
+/// [`NODE_MIN_CHILDREN`].
+fn into_first(
+    len1: usize,
+    len2: usize,
+    remaining: usize,
+    prefer_first: bool,
+) -> bool {
+    if len1 + remaining <= NODE_MIN_CHILDREN {
+        true
+    } else if len2 + remaining <= NODE_MIN_CHILDREN {
+        false
+    } else {
+        prefer_first
+    }
+}
+
 trait HasAABB {
     fn aabb(&self) -> &AABB;
 }
@@ -355,3 +745,65 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::uniform_aabbs;
+
+    fn leaves(aabbs: &[AABB]) -> Vec<Leaf<usize>> {
+        let leaves = aabbs.iter().enumerate();
+        leaves.map(|(i, &aabb)| Leaf { aabb, data: i }).collect()
+    }
+
+    type Halves<T> = ((AABB, Vec<T>), (AABB, Vec<T>));
+
+    /// Both halves have at least the minimum of children, hold every leaf
+    /// once and have the merged AABB of their leaves.
+    fn check_split(
+        count: usize,
+        ((aabb1, leaves1), (aabb2, leaves2)): Halves<Leaf<usize>>,
+    ) {
+        assert!(leaves1.len() >= NODE_MIN_CHILDREN);
+        assert!(leaves2.len() >= NODE_MIN_CHILDREN);
+        assert_eq!(aabb1, AABB::merge(leaves1.iter().map(|l| &l.aabb)));
+        assert_eq!(aabb2, AABB::merge(leaves2.iter().map(|l| &l.aabb)));
+        let mut data: Vec<usize> =
+            leaves1.iter().chain(&leaves2).map(|l| l.data).collect();
+        data.sort_unstable();
+        assert_eq!(data, (0..count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn splits_keep_every_leaf() {
+        let bounds = AABB {
+            min: [-10.0; 3],
+            max: [10.0; 3],
+        };
+        let count = NODE_MAX_CHILDREN + 1;
+        let point = AABB::point([1.0, 2.0, 3.0]);
+        let mut cases = vec![vec![point; count], vec![bounds; count]];
+        for seed in 0..50 {
+            cases.push(uniform_aabbs(seed, count, bounds, 0.0..5.0));
+        }
+        for aabbs in cases {
+            check_split(count, split(&mut leaves(&aabbs)));
+            check_split(count, quadratic_split(&mut leaves(&aabbs)));
+        }
+    }
+
+    #[test]
+    fn memory_usage_counts_heap_only() {
+        let mut tree = RTree::new();
+        assert_eq!(tree.memory_usage(), 0);
+        tree.insert(AABB::point([1.0, 2.0, 3.0]), 0usize);
+        let Some(Entry::Leaves(leaves)) = tree.root.as_ref().map(|r| &r.entry)
+        else {
+            panic!("a single leaf is stored in the root");
+        };
+        assert_eq!(
+            tree.memory_usage(),
+            leaves.capacity() * size_of::<Leaf<usize>>()
+        );
+    }
+}
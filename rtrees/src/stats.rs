@@ -0,0 +1,195 @@
+//! Query statistics and tree quality metrics, computed the same way for every
+//! tree that implements [`Traverse`].
+
+use std::ops::AddAssign;
+
+use crate::join::Traverse;
+use crate::omt::AABB;
+
+/// The amount of work a query did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    /// Nodes that overlapped the query and were descended into, by depth.
+    pub nodes_visited: Vec<usize>,
+    /// Leaves whose AABB was tested against the query.
+    pub leaf_tests: usize,
+    /// Leaves that overlapped the query.
+    pub hits: usize,
+}
+
+impl QueryStats {
+    pub fn total_nodes_visited(&self) -> usize {
+        self.nodes_visited.iter().sum()
+    }
+}
+
+impl AddAssign<&QueryStats> for QueryStats {
+    fn add_assign(&mut self, rhs: &QueryStats) {
+        if self.nodes_visited.len() < rhs.nodes_visited.len() {
+            self.nodes_visited.resize(rhs.nodes_visited.len(), 0);
+        }
+        for (a, b) in self.nodes_visited.iter_mut().zip(&rhs.nodes_visited) {
+            *a += b;
+        }
+        self.leaf_tests += rhs.leaf_tests;
+        self.hits += rhs.hits;
+    }
+}
+
+/// Quality metrics of the shape of a tree, lower is better for all of the
+/// volumes and the SAH cost.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeStats {
+    /// Number of levels, counting the leaves as well.
+    pub height: usize,
+    /// Number of internal nodes.
+    pub node_count: usize,
+    pub leaf_count: usize,
+    /// Sum of the volumes of the internal nodes.
+    pub node_volume: f64,
+    /// Sum of the pairwise intersection volumes of sibling nodes.
+    pub overlap: f64,
+    /// Sum of the volumes of the internal nodes not taken up by their
+    /// children.
+    ///
+    /// Overlapping children are counted twice, so this is a lower bound.
+    pub dead_space: f64,
+    /// Average number of children per internal node, relative to the
+    /// maximum.
+    pub fill_factor: f64,
+    pub min_children: usize,
+    pub max_children: usize,
+    /// Surface area heuristic: the expected number of child AABBs tested by
+    /// a random ray that hits the root.
+    pub sah_cost: f64,
+}
+
+pub fn query_stats<'a, A: Traverse<'a>>(tree: A, aabb: &AABB) -> QueryStats {
+    let mut stats = QueryStats::default();
+    let mut stack: Vec<_> =
+        tree.root().map(|root| (root, 0)).into_iter().collect();
+    let mut children = Vec::new();
+    while let Some((node, depth)) = stack.pop() {
+        let overlaps = tree.aabb(node).overlaps(aabb);
+        children.clear();
+        if !tree.children(node, &mut children) {
+            stats.leaf_tests += 1;
+            stats.hits += overlaps as usize;
+            continue;
+        }
+        if !overlaps {
+            continue;
+        }
+        if stats.nodes_visited.len() <= depth {
+            stats.nodes_visited.resize(depth + 1, 0);
+        }
+        stats.nodes_visited[depth] += 1;
+        stack.extend(children.iter().map(|&child| (child, depth + 1)));
+    }
+    stats
+}
+
+pub fn tree_stats<'a, A: Traverse<'a>>(
+    tree: A,
+    max_children: usize,
+) -> TreeStats {
+    let mut stats = TreeStats {
+        min_children: usize::MAX,
+        ..TreeStats::default()
+    };
+    let Some(root) = tree.root() else {
+        stats.min_children = 0;
+        return stats;
+    };
+    let root_area = tree.aabb(root).surface_area();
+    let mut child_count = 0;
+    let mut stack = vec![(root, 0)];
+    let mut children = Vec::new();
+    while let Some((node, depth)) = stack.pop() {
+        children.clear();
+        if !tree.children(node, &mut children) {
+            stats.leaf_count += 1;
+            stats.height = stats.height.max(depth + 1);
+            continue;
+        }
+        stats.node_count += 1;
+        stats.min_children = stats.min_children.min(children.len());
+        stats.max_children = stats.max_children.max(children.len());
+        child_count += children.len();
+        if children.is_empty() {
+            // the root of an empty tree, its AABB is inside out
+            stats.height = stats.height.max(depth + 1);
+            continue;
+        }
+        let aabb = tree.aabb(node);
+        let volume = aabb.volume();
+        stats.node_volume += volume;
+        let child_volume: f64 =
+            children.iter().map(|&c| tree.aabb(c).volume()).sum();
+        stats.dead_space += (volume - child_volume).max(0.0);
+        for (i, &child1) in children.iter().enumerate() {
+            for &child2 in &children[i + 1..] {
+                stats.overlap +=
+                    tree.aabb(child1).intersection_volume(tree.aabb(child2));
+            }
+        }
+        if root_area > 0.0 {
+            stats.sah_cost +=
+                aabb.surface_area() / root_area * children.len() as f64;
+        }
+        stack.extend(children.iter().map(|&child| (child, depth + 1)));
+    }
+    stats.fill_factor = if stats.node_count == 0 {
+        0.0
+    } else {
+        child_count as f64 / (stats.node_count * max_children) as f64
+    };
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate::uniform_aabbs;
+    use crate::omt::{Leaf, RTree as Omt, AABB};
+    use crate::rtree::RTree;
+
+    const BOUNDS: AABB = AABB {
+        min: [-10.0, -10.0, -10.0],
+        max: [10.0, 10.0, 10.0],
+    };
+
+    #[test]
+    fn query_hits_match_brute_force() {
+        let aabbs = uniform_aabbs(1, 2000, BOUNDS, 0.1..0.5);
+        let omt = Omt::new(aabbs.iter().map(|&a| Leaf::new_empty(a)).collect());
+        let mut rtree = RTree::new();
+        for &aabb in &aabbs {
+            rtree.insert(aabb, ());
+        }
+        for query in uniform_aabbs(2, 20, BOUNDS, 1.0..2.0) {
+            let expected = aabbs.iter().filter(|a| a.overlaps(&query)).count();
+            for stats in [omt.query_stats(&query), rtree.query_stats(&query)] {
+                assert_eq!(stats.hits, expected);
+                assert!(stats.leaf_tests >= expected);
+                assert!(stats.nodes_visited.first().unwrap_or(&0) <= &1);
+            }
+        }
+    }
+
+    #[test]
+    fn tree_stats() {
+        let aabbs = uniform_aabbs(3, 1000, BOUNDS, 0.1..0.5);
+        let omt = Omt::new(aabbs.iter().map(|&a| Leaf::new_empty(a)).collect());
+        let stats = omt.stats();
+        assert_eq!(stats.leaf_count, 1000);
+        assert_eq!(stats.height, omt.height());
+        assert!(stats.min_children >= 2 && stats.max_children <= 6);
+        assert!(stats.fill_factor > 0.0 && stats.fill_factor <= 1.0);
+        assert!(stats.overlap >= 0.0 && stats.dead_space >= 0.0);
+        assert!(stats.sah_cost > 0.0);
+
+        let empty = Omt::<()>::new(vec![]).stats();
+        assert_eq!(empty.leaf_count, 0);
+        assert_eq!(empty.node_volume, 0.0);
+    }
+}
@@ -0,0 +1,240 @@
+//! Structural invariants shared by every tree that implements [`Traverse`].
+
+use std::error::Error;
+use std::fmt::Display;
+
+use crate::join::Traverse;
+
+/// The first invariant a tree was found to break, depths start at 0 for
+/// the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidTree {
+    /// The AABB of a node at `depth` does not contain one of its children.
+    NotContained { depth: usize },
+    /// A node at `depth` has more or fewer children than allowed. The root
+    /// only needs one child, or none in an empty tree.
+    ChildCount { depth: usize, children: usize },
+    /// A leaf is at `depth`, but all leaves should be at `height - 1`.
+    LeafDepth { depth: usize, height: usize },
+    /// Not every leaf the tree holds can be reached from the root.
+    LeafCount { reachable: usize, len: usize },
+    /// The nodes of a packed layer do not cover the layer below exactly
+    /// once and in order.
+    Layout { depth: usize },
+}
+
+impl Display for InvalidTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotContained { depth } => {
+                write!(f, "a child of a node at depth {depth} sticks out")
+            }
+            Self::ChildCount { depth, children } => {
+                write!(f, "a node at depth {depth} has {children} children")
+            }
+            Self::LeafDepth { depth, height } => {
+                write!(
+                    f,
+                    "a leaf is at depth {depth} in a tree of height {height}"
+                )
+            }
+            Self::LeafCount { reachable, len } => {
+                write!(f, "{reachable} of {len} leaves are reachable")
+            }
+            Self::Layout { depth } => {
+                write!(f, "the nodes at depth {depth} are not contiguous")
+            }
+        }
+    }
+}
+
+impl Error for InvalidTree {}
+
+/// Checks that every node contains its children and has at most
+/// `max_children` of them, and at least `min_children` unless it is the
+/// root, that all `len` leaves are reachable, and that they all are at the
+/// bottom of a tree with `height` levels.
+pub fn validate<'a, A: Traverse<'a>>(
+    tree: A,
+    min_children: usize,
+    max_children: usize,
+    height: usize,
+    len: usize,
+) -> Result<(), InvalidTree> {
+    let mut reachable = 0;
+    let mut stack: Vec<_> =
+        tree.root().map(|root| (root, 0)).into_iter().collect();
+    let mut children = Vec::new();
+    while let Some((node, depth)) = stack.pop() {
+        children.clear();
+        if !tree.children(node, &mut children) {
+            if depth + 1 != height {
+                return Err(InvalidTree::LeafDepth { depth, height });
+            }
+            reachable += 1;
+            continue;
+        }
+        let min_children = match depth {
+            0 if len == 0 => 0,
+            0 => 1,
+            _ => min_children,
+        };
+        if children.len() > max_children || children.len() < min_children {
+            return Err(InvalidTree::ChildCount {
+                depth,
+                children: children.len(),
+            });
+        }
+        let aabb = tree.aabb(node);
+        if !children
+            .iter()
+            .all(|&child| aabb.contains(tree.aabb(child)))
+        {
+            return Err(InvalidTree::NotContained { depth });
+        }
+        stack.extend(children.iter().map(|&child| (child, depth + 1)));
+    }
+    if reachable != len {
+        return Err(InvalidTree::LeafCount { reachable, len });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::array::uniform3;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::generate::uniform_aabbs;
+    use crate::omt::{self, Leaf, Loader, AABB};
+    use crate::rtree;
+
+    fn aabb() -> impl Strategy<Value = AABB> {
+        (uniform3(-10.0..10.0), uniform3(0.0..3.0)).prop_map(|(min, size)| {
+            AABB {
+                min,
+                max: [0, 1, 2].map(|i| min[i] + size[i]),
+            }
+        })
+    }
+
+    fn brute_force(aabbs: &[Option<AABB>], query: &AABB) -> Vec<usize> {
+        (0..aabbs.len())
+            .filter(|&i| aabbs[i].is_some_and(|aabb| aabb.overlaps(query)))
+            .collect()
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(AABB),
+        /// Removes the inserted leaf at this index modulo the leaf count.
+        Remove(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => aabb().prop_map(Op::Insert),
+            1 => any::<usize>().prop_map(Op::Remove),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn omt_is_valid(
+            aabbs in prop::collection::vec(aabb(), 0..500),
+            queries in prop::collection::vec(aabb(), 1..10),
+            loader in prop::sample::select(Loader::ALL.to_vec()),
+        ) {
+            let leaves =
+                aabbs.iter().enumerate().map(|(i, &a)| Leaf::new(a, i));
+            let tree = omt::RTree::with_loader(leaves.collect(), loader);
+            prop_assert_eq!(tree.validate(), Ok(()));
+            let present: Vec<_> = aabbs.into_iter().map(Some).collect();
+            for query in queries {
+                let mut found: Vec<usize> =
+                    tree.query_leaves(query).map(|l| l.data).collect();
+                found.sort_unstable();
+                prop_assert_eq!(found, brute_force(&present, &query));
+            }
+        }
+
+        #[test]
+        fn rtree_stays_valid(
+            ops in prop::collection::vec(op(), 0..300),
+            queries in prop::collection::vec(aabb(), 1..10),
+        ) {
+            let mut tree = rtree::RTree::new();
+            // indexed by the data of the leaves, `None` once removed
+            let mut present: Vec<Option<AABB>> = vec![];
+            for op in ops {
+                match op {
+                    Op::Insert(aabb) => {
+                        tree.insert(aabb, present.len());
+                        present.push(Some(aabb));
+                    }
+                    Op::Remove(_) if present.is_empty() => {}
+                    Op::Remove(i) => {
+                        let i = i % present.len();
+                        let removed = present[i].and_then(|aabb| {
+                            tree.remove(&aabb, |&data| data == i)
+                        });
+                        prop_assert_eq!(
+                            removed.is_some(),
+                            present[i].is_some()
+                        );
+                        present[i] = None;
+                    }
+                }
+                prop_assert_eq!(tree.validate(), Ok(()));
+            }
+            for query in queries {
+                let mut found: Vec<usize> =
+                    tree.search(&query).into_iter().copied().collect();
+                found.sort_unstable();
+                prop_assert_eq!(found, brute_force(&present, &query));
+            }
+        }
+    }
+
+    #[test]
+    fn detects_broken_omt() {
+        let bounds = AABB {
+            min: [-10.0; 3],
+            max: [10.0; 3],
+        };
+        let aabbs = uniform_aabbs(1, 100, bounds, 0.1..1.0);
+        let build = || {
+            omt::RTree::new(aabbs.iter().map(|&a| Leaf::new(a, ())).collect())
+        };
+
+        let mut tree = build();
+        tree.layers[1][0].aabb.max[0] -= 100.0;
+        assert_eq!(
+            tree.validate(),
+            Err(InvalidTree::NotContained { depth: 1 })
+        );
+
+        let mut tree = build();
+        tree.layers[1][0].end -= 1;
+        assert_eq!(tree.validate(), Err(InvalidTree::Layout { depth: 1 }));
+
+        let mut tree = build();
+        tree.leaves.pop();
+        assert!(tree.validate().is_err());
+    }
+
+    #[test]
+    fn empty_trees_are_valid() {
+        let omt = omt::RTree::<()>::new(vec![]);
+        assert_eq!(omt.validate(), Ok(()));
+        let mut rtree = rtree::RTree::new();
+        assert_eq!(rtree.validate(), Ok(()));
+        let aabb = AABB::point([1.0, 2.0, 3.0]);
+        rtree.insert(aabb, ());
+        assert_eq!(rtree.validate(), Ok(()));
+        rtree.clear();
+        assert_eq!(rtree.validate(), Ok(()));
+        assert_eq!(rtree.height(), 1);
+    }
+}